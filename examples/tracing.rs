@@ -0,0 +1,89 @@
+// CQL Tracing allows to see each step during execution of a query
+// query(), execute(), batch() and paged queries can be traced
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use scylla::batch::Batch;
+use scylla::query::Query;
+use scylla::transport::tracing::TracingInfo;
+use scylla::{BatchResult, QueryResult, Session, SessionBuilder};
+use std::env;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let uri = env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+
+    println!("Connecting to {} ...", uri);
+
+    let session: Session = SessionBuilder::new().known_node(uri).build().await?;
+
+    session.query("CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await?;
+    session
+        .query(
+            "CREATE TABLE IF NOT EXISTS ks.tracing_example (val text primary key)",
+            &[],
+        )
+        .await?;
+
+    // QUERY
+    // Create a simple query and enable tracing for it
+    let mut query: Query = Query::new("SELECT * FROM ks.tracing_example".to_string());
+    query.set_tracing(true);
+
+    // QueryResult will contain a tracing_id which can be used to query tracing information
+    let query_result: QueryResult = session.query(query, &[]).await?;
+    let query_tracing_id = query_result
+        .tracing_id
+        .ok_or_else(|| anyhow!("Tracing id is None!"))?;
+
+    // PREPARE + EXECUTE
+    let mut prepared = session.prepare("SELECT * FROM ks.tracing_example").await?;
+    prepared.set_tracing(true);
+
+    let execute_result: QueryResult = session.execute(&prepared, &[]).await?;
+    println!("Execute tracing id: {:?}", execute_result.tracing_id);
+
+    // BATCH
+    let mut batch: Batch = Default::default();
+    batch.append_statement("INSERT INTO ks.tracing_example (val) VALUES('val')");
+    batch.set_tracing(true);
+
+    let batch_result: BatchResult = session.batch(&batch, ((),)).await?;
+    println!("Batch tracing id: {:?}", batch_result.tracing_id);
+
+    // PAGED QUERY
+    // Each fetched page has its own tracing id
+    let mut paged_query: Query = Query::new("SELECT * FROM ks.tracing_example".to_string());
+    paged_query.set_tracing(true);
+    paged_query.set_page_size(1);
+
+    let mut row_iterator = session.query_iter(paged_query, &[]).await?;
+    while let Some(row) = row_iterator.next().await {
+        row?;
+    }
+    println!(
+        "Paged query tracing ids: {:?}\n",
+        row_iterator.get_tracing_ids()
+    );
+
+    // Tracing info is written asynchronously by the database,
+    // get_tracing_info() polls system_traces until it's complete
+    let tracing_info: TracingInfo = session.get_tracing_info(&query_tracing_id).await?;
+    println!(
+        "Query {:?} took {:?} microseconds on coordinator {:?}",
+        tracing_info.request, tracing_info.duration, tracing_info.coordinator
+    );
+
+    for node in tracing_info.nodes() {
+        println!("Events on node {}:", node);
+        for event in tracing_info.events_on_node(node) {
+            println!(
+                "  [{:>8?} us] {}",
+                event.source_elapsed,
+                event.activity.as_deref().unwrap_or("")
+            );
+        }
+    }
+
+    Ok(())
+}
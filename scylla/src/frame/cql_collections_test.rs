@@ -52,6 +52,7 @@ async fn test_cql_collections() {
         .query("SELECT team FROM ks.teams", &[])
         .await
         .unwrap()
+        .rows
     {
         for row in rows.into_typed::<(HashMap<i32, String>,)>() {
             let teams: HashMap<i32, String> = row.unwrap().0;
@@ -85,6 +86,7 @@ async fn test_cql_collections() {
         .query("SELECT tags FROM ks.images", &[])
         .await
         .unwrap()
+        .rows
     {
         for row in rows.into_typed::<(Vec<String>,)>() {
             let tag: Vec<String> = row.unwrap().0;
@@ -103,6 +105,7 @@ async fn test_cql_collections() {
         .query("SELECT tags FROM ks.images", &[])
         .await
         .unwrap()
+        .rows
     {
         for row in rows.into_typed::<(Vec<String>,)>() {
             let tag: Vec<String> = row.unwrap().0;
@@ -132,6 +135,7 @@ async fn test_cql_collections() {
         .query("SELECT scores FROM ks.plays", &[])
         .await
         .unwrap()
+        .rows
     {
         for row in rows.into_typed::<(Vec<i32>,)>() {
             let scores: Vec<i32> = row.unwrap().0;
@@ -153,6 +157,7 @@ async fn test_cql_collections() {
         .query("SELECT scores FROM ks.plays", &[])
         .await
         .unwrap()
+        .rows
     {
         for row in rows.into_typed::<(Vec<i32>,)>() {
             let scores: Vec<i32> = row.unwrap().0;
@@ -182,6 +187,7 @@ async fn test_cql_collections() {
         .query("SELECT duration FROM ks.durations", &[])
         .await
         .unwrap()
+        .rows
     {
         for row in rows.into_typed::<((i32, String),)>() {
             let duration = row.unwrap().0;
@@ -1,3 +1,4 @@
+use super::request;
 use super::response;
 use crate::cql_to_rust::CQLTypeError;
 use crate::frame::value::SerializeValuesError;
@@ -13,7 +14,9 @@ pub enum FrameError {
     LZ4BodyDecompression,
     #[error("Received frame marked as coming from a client")]
     FrameFromClient,
-    #[error("Received a frame from version {0}, but only 4 is supported")]
+    #[error("Received frame marked as coming from a server")]
+    FrameFromServer,
+    #[error("Received a frame from version {0}, but only versions 3, 4 and 5 are supported")]
     VersionNotSupported(u8),
     #[error("Connection was closed before body was read: missing {0} out of {1}")]
     ConnectionClosed(usize, usize),
@@ -21,10 +24,18 @@ pub enum FrameError {
     FrameDecompression,
     #[error("Frame compression failed.")]
     FrameCompression,
+    #[error("Segment header checksum mismatch")]
+    SegmentHeaderChecksum,
+    #[error("Segment payload checksum mismatch")]
+    SegmentPayloadChecksum,
+    #[error("Connection was closed before segment was read")]
+    SegmentConnectionClosed,
     #[error("std io error encountered while processing")]
     StdIOError(#[from] std::io::Error),
     #[error("Unrecognized opcode{0}")]
     TryFromPrimitiveError(#[from] num_enum::TryFromPrimitiveError<response::ResponseOpcode>),
+    #[error("Unrecognized request opcode{0}")]
+    UnknownRequestOpcode(#[from] num_enum::TryFromPrimitiveError<request::RequestOpcode>),
 }
 
 #[derive(Error, Debug)]
@@ -35,6 +46,8 @@ pub enum ParseError {
     IoError(#[from] std::io::Error),
     #[error("type not yet implemented, id: {0}")]
     TypeNotImplemented(i16),
+    #[error("Result metadata was skipped, but there is no matching cached metadata")]
+    NoCachedResultMetadata,
     #[error(transparent)]
     SerializeValuesError(#[from] SerializeValuesError),
     #[error(transparent)]
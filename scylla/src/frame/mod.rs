@@ -12,10 +12,11 @@ mod cql_collections_test;
 
 use crate::frame::frame_errors::FrameError;
 use crate::transport::Compression;
-use bytes::{Buf, BufMut, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use uuid::Uuid;
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 
 use compress::lz4;
@@ -28,6 +29,43 @@ pub const FLAG_TRACING: u8 = 0x02;
 pub const FLAG_CUSTOM_PAYLOAD: u8 = 0x04;
 pub const FLAG_WARNING: u8 = 0x08;
 
+/// Version of the CQL binary protocol used on a connection.\
+/// The default is the highest version supported by the driver.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum ProtocolVersion {
+    V3 = 0x03,
+    V4 = 0x04,
+    #[default]
+    V5 = 0x05,
+}
+
+impl ProtocolVersion {
+    /// The lowest version supported by the driver
+    pub const MIN: ProtocolVersion = ProtocolVersion::V3;
+
+    /// Next lower version to try during negotiation, None if there is no such version
+    pub fn downgrade(self) -> Option<ProtocolVersion> {
+        match self {
+            ProtocolVersion::V5 => Some(ProtocolVersion::V4),
+            ProtocolVersion::V4 => Some(ProtocolVersion::V3),
+            ProtocolVersion::V3 => None,
+        }
+    }
+}
+
+impl TryFrom<u8> for ProtocolVersion {
+    type Error = FrameError;
+
+    fn try_from(version: u8) -> Result<Self, Self::Error> {
+        match version {
+            0x03 => Ok(ProtocolVersion::V3),
+            0x04 => Ok(ProtocolVersion::V4),
+            0x05 => Ok(ProtocolVersion::V5),
+            v => Err(FrameError::VersionNotSupported(v)),
+        }
+    }
+}
+
 // Parts of the frame header which are not determined by the request/response type.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct FrameParams {
@@ -39,28 +77,37 @@ pub struct FrameParams {
 impl Default for FrameParams {
     fn default() -> Self {
         Self {
-            version: 0x04,
+            version: ProtocolVersion::default() as u8,
             flags: 0x00,
             stream: 0,
         }
     }
 }
 
-pub async fn write_request_frame(
-    writer: &mut (impl AsyncWrite + Unpin),
-    params: FrameParams,
-    opcode: RequestOpcode,
-    body: Bytes,
-) -> Result<(), std::io::Error> {
+// Response frames have the highest bit of the version set
+const RESPONSE_VERSION_BIT: u8 = 0x80;
+
+fn frame_header(params: FrameParams, opcode: u8, body_len: usize) -> [u8; 9] {
     let mut header = [0u8; 9];
     let mut v = &mut header[..];
     v.put_u8(params.version);
     v.put_u8(params.flags);
     v.put_i16(params.stream);
-    v.put_u8(opcode as u8);
+    v.put_u8(opcode);
 
     // TODO: Return an error if the frame is too big?
-    v.put_u32(body.len() as u32);
+    v.put_u32(body_len as u32);
+
+    header
+}
+
+pub async fn write_request_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    params: FrameParams,
+    opcode: RequestOpcode,
+    body: Bytes,
+) -> Result<(), std::io::Error> {
+    let header = frame_header(params, opcode as u8, body.len());
 
     writer.write_all(&header).await?;
     writer.write_all(&body).await?;
@@ -68,22 +115,36 @@ pub async fn write_request_frame(
     Ok(())
 }
 
-pub async fn read_response_frame(
-    reader: &mut (impl AsyncRead + Unpin),
-) -> Result<(FrameParams, ResponseOpcode, Bytes), FrameError> {
-    let mut raw_header = [0u8; 9];
-    reader.read_exact(&mut raw_header[..]).await?;
+/// Writes a response frame, used when acting as a server
+pub async fn write_response_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    params: FrameParams,
+    opcode: ResponseOpcode,
+    body: Bytes,
+) -> Result<(), std::io::Error> {
+    let params = FrameParams {
+        version: params.version | RESPONSE_VERSION_BIT,
+        ..params
+    };
+    let header = frame_header(params, opcode as u8, body.len());
 
-    let mut buf = &raw_header[..];
+    writer.write_all(&header).await?;
+    writer.write_all(&body).await?;
 
-    // TODO: Validate version
+    Ok(())
+}
+
+fn parse_frame_header(
+    mut buf: &[u8],
+    is_response: bool,
+) -> Result<(FrameParams, u8, usize), FrameError> {
     let version = buf.get_u8();
-    if version & 0x80 != 0x80 {
-        return Err(FrameError::FrameFromClient);
-    }
-    if version & 0x7F != 0x04 {
-        return Err(FrameError::VersionNotSupported(version & 0x7f));
+    match (version & RESPONSE_VERSION_BIT != 0, is_response) {
+        (false, true) => return Err(FrameError::FrameFromClient),
+        (true, false) => return Err(FrameError::FrameFromServer),
+        _ => {}
     }
+    ProtocolVersion::try_from(version & !RESPONSE_VERSION_BIT)?;
 
     let flags = buf.get_u8();
     let stream = buf.get_i16();
@@ -94,11 +155,57 @@ pub async fn read_response_frame(
         stream,
     };
 
-    let opcode = ResponseOpcode::try_from(buf.get_u8())?;
+    let opcode = buf.get_u8();
 
     // TODO: Guard from frames that are too large
     let length = buf.get_u32() as usize;
 
+    Ok((frame_params, opcode, length))
+}
+
+fn parse_response_frame_header(
+    buf: &[u8],
+) -> Result<(FrameParams, ResponseOpcode, usize), FrameError> {
+    let (frame_params, opcode, length) = parse_frame_header(buf, true)?;
+    Ok((frame_params, ResponseOpcode::try_from(opcode)?, length))
+}
+
+fn parse_request_frame_header(
+    buf: &[u8],
+) -> Result<(FrameParams, RequestOpcode, usize), FrameError> {
+    let (frame_params, opcode, length) = parse_frame_header(buf, false)?;
+    Ok((frame_params, RequestOpcode::try_from(opcode)?, length))
+}
+
+pub async fn read_response_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<(FrameParams, ResponseOpcode, Bytes), FrameError> {
+    let mut raw_header = [0u8; 9];
+    reader.read_exact(&mut raw_header[..]).await?;
+
+    let (frame_params, opcode, length) = parse_response_frame_header(&raw_header)?;
+    let body = read_frame_body(reader, length).await?;
+
+    Ok((frame_params, opcode, body))
+}
+
+/// Reads a request frame, used when acting as a server
+pub async fn read_request_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<(FrameParams, RequestOpcode, Bytes), FrameError> {
+    let mut raw_header = [0u8; 9];
+    reader.read_exact(&mut raw_header[..]).await?;
+
+    let (frame_params, opcode, length) = parse_request_frame_header(&raw_header)?;
+    let body = read_frame_body(reader, length).await?;
+
+    Ok((frame_params, opcode, body))
+}
+
+async fn read_frame_body(
+    reader: &mut (impl AsyncRead + Unpin),
+    length: usize,
+) -> Result<Bytes, FrameError> {
     let mut raw_body = Vec::with_capacity(length).limit(length);
     while raw_body.has_remaining_mut() {
         let n = reader.read_buf(&mut raw_body).await?;
@@ -111,11 +218,285 @@ pub async fn read_response_frame(
         }
     }
 
-    Ok((frame_params, opcode, raw_body.into_inner().into()))
+    Ok(raw_body.into_inner().into())
+}
+
+// Protocol v5 segments
+//
+// Since protocol v5, once STARTUP completes, frames are no longer sent directly.
+// They are wrapped in segments, each consisting of a header protected by a CRC24
+// checksum and a payload protected by a CRC32 checksum. A self-contained segment
+// carries one or more complete frames, a frame which doesn't fit in a single segment
+// is split across multiple segments which are not self-contained.
+// Compression (only LZ4 is allowed) is applied to segment payloads, not to frames.
+
+/// Maximum length of an (uncompressed) segment payload
+pub const MAX_SEGMENT_PAYLOAD_LEN: usize = 128 * 1024 - 1;
+
+const SEGMENT_CRC_LEN: usize = 3;
+const UNCOMPRESSED_SEGMENT_HEADER_LEN: usize = 3;
+const COMPRESSED_SEGMENT_HEADER_LEN: usize = 5;
+const SEGMENT_PAYLOAD_LEN_BITS: u32 = 17;
+const SEGMENT_PAYLOAD_LEN_MASK: u64 = (1 << SEGMENT_PAYLOAD_LEN_BITS) - 1;
+
+const CRC24_INIT: u32 = 0x875060;
+const CRC24_POLY: u32 = 0x1974F0B;
+
+// Bytes fed into the CRC32 before the payload, the same as in Cassandra
+const CRC32_INITIAL_BYTES: [u8; 4] = [0xfa, 0x2d, 0x55, 0xca];
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc24(bytes: &[u8]) -> u32 {
+    let mut crc = CRC24_INIT;
+    for byte in bytes {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    crc
+}
+
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in bytes {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn crc32(payload: &[u8]) -> u32 {
+    crc32_update(crc32_update(0, &CRC32_INITIAL_BYTES), payload)
+}
+
+fn put_segment(payload: &[u8], self_contained: bool, compressed: bool, buf: &mut impl BufMut) {
+    let mut header: u64;
+    let header_len: usize;
+    let compressed_payload: Vec<u8>;
+    let mut payload = payload;
+
+    if compressed {
+        let mut tmp =
+            Vec::with_capacity(lz4::compression_bound(payload.len() as u32).unwrap_or(0) as usize);
+        lz4::encode_block(payload, &mut tmp);
+
+        if tmp.len() < payload.len() {
+            header = tmp.len() as u64 | (payload.len() as u64) << SEGMENT_PAYLOAD_LEN_BITS;
+            compressed_payload = tmp;
+            payload = &compressed_payload;
+        } else {
+            // Compression doesn't pay off, uncompressed length of 0 means the payload is sent as is
+            header = payload.len() as u64;
+        }
+        header |= (self_contained as u64) << (2 * SEGMENT_PAYLOAD_LEN_BITS);
+        header_len = COMPRESSED_SEGMENT_HEADER_LEN;
+    } else {
+        header = payload.len() as u64 | (self_contained as u64) << SEGMENT_PAYLOAD_LEN_BITS;
+        header_len = UNCOMPRESSED_SEGMENT_HEADER_LEN;
+    }
+
+    let header_bytes = &header.to_le_bytes()[..header_len];
+    buf.put_slice(header_bytes);
+    buf.put_slice(&crc24(header_bytes).to_le_bytes()[..SEGMENT_CRC_LEN]);
+    buf.put_slice(payload);
+    buf.put_u32_le(crc32(payload));
+}
+
+/// Wraps a serialized frame (or multiple frames) in protocol v5 segments.
+/// Data which doesn't fit in a single segment is split across multiple segments.
+pub fn encode_segments(frames: &[u8], compressed: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(frames.len() + 16);
+    if frames.len() <= MAX_SEGMENT_PAYLOAD_LEN {
+        put_segment(frames, true, compressed, &mut buf);
+    } else {
+        for chunk in frames.chunks(MAX_SEGMENT_PAYLOAD_LEN) {
+            put_segment(chunk, false, compressed, &mut buf);
+        }
+    }
+    buf
+}
+
+pub async fn write_request_frame_in_segments(
+    writer: &mut (impl AsyncWrite + Unpin),
+    params: FrameParams,
+    opcode: RequestOpcode,
+    body: Bytes,
+    compressed: bool,
+) -> Result<(), std::io::Error> {
+    write_frame_in_segments(writer, params, opcode as u8, body, compressed).await
+}
+
+/// Writes a response frame wrapped in protocol v5 segments, used when acting as a server
+pub async fn write_response_frame_in_segments(
+    writer: &mut (impl AsyncWrite + Unpin),
+    params: FrameParams,
+    opcode: ResponseOpcode,
+    body: Bytes,
+    compressed: bool,
+) -> Result<(), std::io::Error> {
+    let params = FrameParams {
+        version: params.version | RESPONSE_VERSION_BIT,
+        ..params
+    };
+    write_frame_in_segments(writer, params, opcode as u8, body, compressed).await
+}
+
+async fn write_frame_in_segments(
+    writer: &mut (impl AsyncWrite + Unpin),
+    params: FrameParams,
+    opcode: u8,
+    body: Bytes,
+    compressed: bool,
+) -> Result<(), std::io::Error> {
+    let header = frame_header(params, opcode, body.len());
+
+    let mut frame = Vec::with_capacity(header.len() + body.len());
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(&body);
+
+    writer
+        .write_all(&encode_segments(&frame, compressed))
+        .await?;
+
+    Ok(())
 }
 
-pub struct RequestBodyWithExtensions {
+/// Reads a single protocol v5 segment, verifies its checksums and returns the decompressed payload
+pub async fn read_segment(
+    reader: &mut (impl AsyncRead + Unpin),
+    compressed: bool,
+) -> Result<Bytes, FrameError> {
+    let header_len = if compressed {
+        COMPRESSED_SEGMENT_HEADER_LEN
+    } else {
+        UNCOMPRESSED_SEGMENT_HEADER_LEN
+    };
+
+    let mut raw_header = [0u8; COMPRESSED_SEGMENT_HEADER_LEN + SEGMENT_CRC_LEN];
+    let raw_header = &mut raw_header[..header_len + SEGMENT_CRC_LEN];
+    read_segment_part(reader, raw_header).await?;
+
+    let (header_bytes, header_crc) = raw_header.split_at(header_len);
+    let mut crc_bytes = [0u8; 4];
+    crc_bytes[..SEGMENT_CRC_LEN].copy_from_slice(header_crc);
+    if crc24(header_bytes) != u32::from_le_bytes(crc_bytes) {
+        return Err(FrameError::SegmentHeaderChecksum);
+    }
+
+    let mut header_u64_bytes = [0u8; 8];
+    header_u64_bytes[..header_len].copy_from_slice(header_bytes);
+    let header = u64::from_le_bytes(header_u64_bytes);
+
+    let payload_len = (header & SEGMENT_PAYLOAD_LEN_MASK) as usize;
+    let uncompressed_len = if compressed {
+        ((header >> SEGMENT_PAYLOAD_LEN_BITS) & SEGMENT_PAYLOAD_LEN_MASK) as usize
+    } else {
+        0
+    };
+
+    let mut payload = vec![0u8; payload_len + 4];
+    read_segment_part(reader, &mut payload).await?;
+    let payload_crc = (&payload[payload_len..]).get_u32_le();
+    payload.truncate(payload_len);
+    if crc32(&payload) != payload_crc {
+        return Err(FrameError::SegmentPayloadChecksum);
+    }
+
+    if uncompressed_len == 0 {
+        return Ok(payload.into());
+    }
+
+    let mut uncompressed = Vec::with_capacity(uncompressed_len);
+    if lz4::decode_block(&payload, &mut uncompressed) > 0 && uncompressed.len() == uncompressed_len
+    {
+        Ok(uncompressed.into())
+    } else {
+        Err(FrameError::LZ4BodyDecompression)
+    }
+}
+
+async fn read_segment_part(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut [u8],
+) -> Result<(), FrameError> {
+    reader.read_exact(buf).await.map_err(|err| {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            FrameError::SegmentConnectionClosed
+        } else {
+            err.into()
+        }
+    })?;
+    Ok(())
+}
+
+/// Takes the first complete response frame out of data received in segments.
+/// Returns None if more segments are needed to complete the frame.
+pub fn take_response_frame(
+    buf: &mut BytesMut,
+) -> Result<Option<(FrameParams, ResponseOpcode, Bytes)>, FrameError> {
+    if buf.len() < 9 {
+        return Ok(None);
+    }
+
+    let (frame_params, opcode, length) = parse_response_frame_header(&buf[..9])?;
+    if buf.len() < 9 + length {
+        return Ok(None);
+    }
+
+    buf.advance(9);
+    let body = buf.split_to(length).freeze();
+
+    Ok(Some((frame_params, opcode, body)))
+}
+
+/// Takes the first complete request frame out of data received in segments.
+/// Returns None if more segments are needed to complete the frame.
+pub fn take_request_frame(
+    buf: &mut BytesMut,
+) -> Result<Option<(FrameParams, RequestOpcode, Bytes)>, FrameError> {
+    if buf.len() < 9 {
+        return Ok(None);
+    }
+
+    let (frame_params, opcode, length) = parse_request_frame_header(&buf[..9])?;
+    if buf.len() < 9 + length {
+        return Ok(None);
+    }
+
+    buf.advance(9);
+    let body = buf.split_to(length).freeze();
+
+    Ok(Some((frame_params, opcode, body)))
+}
+
+pub struct RequestBodyWithExtensions<'a> {
     pub body: Bytes,
+    pub tracing: bool,
+    pub custom_payload: Option<&'a HashMap<String, Vec<u8>>>,
 }
 
 pub fn prepare_request_body_with_extensions(
@@ -124,7 +505,21 @@ pub fn prepare_request_body_with_extensions(
 ) -> Result<(u8, Bytes), FrameError> {
     let mut flags = 0;
 
+    if body_with_ext.tracing {
+        flags |= FLAG_TRACING;
+    }
+
     let mut body = body_with_ext.body;
+
+    if let Some(custom_payload) = body_with_ext.custom_payload {
+        // The custom payload precedes the actual request body
+        flags |= FLAG_CUSTOM_PAYLOAD;
+        let mut body_with_payload = Vec::new();
+        types::write_bytes_map(custom_payload, &mut body_with_payload)?;
+        body_with_payload.extend_from_slice(&body);
+        body = body_with_payload.into();
+    }
+
     if let Some(compression) = compression {
         flags |= FLAG_COMPRESSION;
         body = compress(&body, compression)?.into();
@@ -133,9 +528,48 @@ pub fn prepare_request_body_with_extensions(
     Ok((flags, body))
 }
 
+/// Request body with extensions, as received by a server
+pub struct ParsedRequestBodyWithExtensions {
+    pub tracing: bool,
+    pub custom_payload: Option<HashMap<String, Vec<u8>>>,
+    pub body: Bytes,
+}
+
+pub fn parse_request_body_extensions(
+    flags: u8,
+    compression: Option<Compression>,
+    mut body: Bytes,
+) -> Result<ParsedRequestBodyWithExtensions, FrameError> {
+    if flags & FLAG_COMPRESSION != 0 {
+        if let Some(compression) = compression {
+            body = decompress(&body, compression)?.into();
+        } else {
+            return Err(FrameError::NoCompressionNegotiated);
+        }
+    }
+
+    let custom_payload = if flags & FLAG_CUSTOM_PAYLOAD != 0 {
+        let body_len = body.len();
+        let buf = &mut &*body;
+        let payload_map = types::read_bytes_map(buf)?;
+        let buf_len = buf.len();
+        body.advance(body_len - buf_len);
+        Some(payload_map)
+    } else {
+        None
+    };
+
+    Ok(ParsedRequestBodyWithExtensions {
+        tracing: flags & FLAG_TRACING != 0,
+        custom_payload,
+        body,
+    })
+}
+
 pub struct ResponseBodyWithExtensions {
     pub trace_id: Option<Uuid>,
     pub warnings: Vec<String>,
+    pub custom_payload: Option<HashMap<String, Vec<u8>>>,
     pub body: Bytes,
 }
 
@@ -172,23 +606,59 @@ pub fn parse_response_body_extensions(
         Vec::new()
     };
 
-    if flags & FLAG_CUSTOM_PAYLOAD != 0 {
-        // TODO: Do something useful with the custom payload map
-        // For now, just skip it
+    let custom_payload = if flags & FLAG_CUSTOM_PAYLOAD != 0 {
         let body_len = body.len();
         let buf = &mut &*body;
-        types::read_bytes_map(buf)?;
+        let payload_map = types::read_bytes_map(buf)?;
         let buf_len = buf.len();
         body.advance(body_len - buf_len);
-    }
+        Some(payload_map)
+    } else {
+        None
+    };
 
     Ok(ResponseBodyWithExtensions {
         trace_id,
         warnings,
+        custom_payload,
         body,
     })
 }
 
+/// Prepares a response body with extensions, used when acting as a server
+pub fn prepare_response_body_with_extensions(
+    body_with_ext: ResponseBodyWithExtensions,
+    compression: Option<Compression>,
+) -> Result<(u8, Bytes), FrameError> {
+    let mut flags = 0;
+    let mut body = Vec::new();
+
+    // Extensions precede the actual response body in this order
+    if let Some(trace_id) = &body_with_ext.trace_id {
+        flags |= FLAG_TRACING;
+        types::write_uuid(trace_id, &mut body);
+    }
+
+    if !body_with_ext.warnings.is_empty() {
+        flags |= FLAG_WARNING;
+        types::write_string_list(&body_with_ext.warnings, &mut body)?;
+    }
+
+    if let Some(custom_payload) = &body_with_ext.custom_payload {
+        flags |= FLAG_CUSTOM_PAYLOAD;
+        types::write_bytes_map(custom_payload, &mut body)?;
+    }
+
+    body.extend_from_slice(&body_with_ext.body);
+
+    if let Some(compression) = compression {
+        flags |= FLAG_COMPRESSION;
+        body = compress(&body, compression)?;
+    }
+
+    Ok((flags, body.into()))
+}
+
 pub fn compress(uncomp_body: &[u8], compression: Compression) -> Result<Vec<u8>, FrameError> {
     match compression {
         Compression::LZ4 => {
@@ -227,3 +697,305 @@ pub fn decompress(mut comp_body: &[u8], compression: Compression) -> Result<Vec<
             .map_err(|_| FrameError::FrameDecompression),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_payload_roundtrip() {
+        let mut custom_payload = HashMap::new();
+        custom_payload.insert("proxy-route".to_string(), vec![1, 2, 3]);
+
+        let body_with_ext = RequestBodyWithExtensions {
+            body: Bytes::from_static(b"body"),
+            tracing: false,
+            custom_payload: Some(&custom_payload),
+        };
+
+        for compression in &[None, Some(Compression::LZ4), Some(Compression::Snappy)] {
+            let (flags, body) = prepare_request_body_with_extensions(
+                RequestBodyWithExtensions {
+                    body: body_with_ext.body.clone(),
+                    ..body_with_ext
+                },
+                *compression,
+            )
+            .unwrap();
+            assert_ne!(flags & FLAG_CUSTOM_PAYLOAD, 0);
+
+            // The response carries the payload in the same place as the request
+            let parsed = parse_response_body_extensions(flags, *compression, body).unwrap();
+            assert_eq!(parsed.custom_payload.as_ref(), Some(&custom_payload));
+            assert_eq!(parsed.body, Bytes::from_static(b"body"));
+        }
+    }
+
+    #[test]
+    fn query_flags_depend_on_version() {
+        use request::{query, Request};
+
+        let values = value::SerializedValues::new();
+        let query = query::Query {
+            contents: "SELECT * FROM ks.t".to_string(),
+            parameters: query::QueryParameters {
+                values: std::borrow::Cow::Borrowed(&values),
+                ..Default::default()
+            },
+        };
+
+        let v4 = query.to_bytes(ProtocolVersion::V4).unwrap();
+        let v5 = query.to_bytes(ProtocolVersion::V5).unwrap();
+
+        // Flags grew from [byte] to [int] in v5
+        assert_eq!(v5.len(), v4.len() + 3);
+        assert_eq!(ProtocolVersion::V3.downgrade(), None);
+        assert_eq!(
+            ProtocolVersion::try_from(0x05).unwrap(),
+            ProtocolVersion::V5
+        );
+        assert!(ProtocolVersion::try_from(0x02).is_err());
+    }
+
+    #[test]
+    fn segment_checksums() {
+        // Standard CRC-32 check value
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF43926);
+        assert_eq!(
+            crc32(b"payload"),
+            crc32_update(0, &[&CRC32_INITIAL_BYTES[..], b"payload"].concat())
+        );
+
+        // Flipping any bit of the header changes its checksum
+        let header = [0x12, 0x34, 0x05];
+        for bit in 0..24 {
+            let mut flipped = header;
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert_ne!(crc24(&flipped), crc24(&header));
+        }
+        assert!(crc24(&header) < 1 << 24);
+    }
+
+    #[test]
+    fn segments_roundtrip() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let small: Vec<u8> = (0..100u8).collect();
+        let large: Vec<u8> = (0..3 * MAX_SEGMENT_PAYLOAD_LEN)
+            .map(|i| (i % 7) as u8)
+            .collect();
+
+        for data in &[small, large] {
+            for &compressed in &[false, true] {
+                let encoded = encode_segments(data, compressed);
+                let mut reader = &encoded[..];
+
+                let mut decoded = Vec::new();
+                while !reader.is_empty() {
+                    let payload = rt.block_on(read_segment(&mut reader, compressed)).unwrap();
+                    decoded.extend_from_slice(&payload);
+                }
+                assert_eq!(&decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn segment_corruption_detected() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let encoded = encode_segments(b"some frame", false);
+
+        let mut corrupted_header = encoded.clone();
+        corrupted_header[0] ^= 1;
+        assert!(matches!(
+            rt.block_on(read_segment(&mut &corrupted_header[..], false)),
+            Err(FrameError::SegmentHeaderChecksum)
+        ));
+
+        let mut corrupted_payload = encoded;
+        corrupted_payload[7] ^= 1;
+        assert!(matches!(
+            rt.block_on(read_segment(&mut &corrupted_payload[..], false)),
+            Err(FrameError::SegmentPayloadChecksum)
+        ));
+    }
+
+    #[test]
+    fn server_side_frames_roundtrip() {
+        use request::{ParsedRequest, Request};
+        use response::{Response, Supported};
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let params = FrameParams {
+            version: ProtocolVersion::V4 as u8,
+            flags: 0,
+            stream: 17,
+        };
+
+        // Client -> server
+        let mut buf = Vec::new();
+        let body = request::Options.to_bytes(ProtocolVersion::V4).unwrap();
+        rt.block_on(write_request_frame(
+            &mut buf,
+            params,
+            RequestOpcode::Options,
+            body,
+        ))
+        .unwrap();
+        let (received_params, opcode, body) =
+            rt.block_on(read_request_frame(&mut &buf[..])).unwrap();
+        assert_eq!(received_params, params);
+        let request =
+            ParsedRequest::deserialize(opcode, ProtocolVersion::V4, &mut &body[..]).unwrap();
+        assert_eq!(request.opcode(), RequestOpcode::Options);
+
+        // A client can't read requests and a server can't read responses
+        assert!(matches!(
+            rt.block_on(read_response_frame(&mut &buf[..])),
+            Err(FrameError::FrameFromClient)
+        ));
+
+        // Server -> client
+        let mut options = HashMap::new();
+        options.insert("COMPRESSION".to_string(), vec!["lz4".to_string()]);
+        let response = Response::Supported(Supported { options });
+        let body = response.to_bytes(ProtocolVersion::V4).unwrap();
+
+        let mut buf = Vec::new();
+        rt.block_on(write_response_frame(
+            &mut buf,
+            params,
+            response.opcode(),
+            body,
+        ))
+        .unwrap();
+        assert!(matches!(
+            rt.block_on(read_request_frame(&mut &buf[..])),
+            Err(FrameError::FrameFromServer)
+        ));
+        let (received_params, opcode, body) =
+            rt.block_on(read_response_frame(&mut &buf[..])).unwrap();
+        assert_eq!(received_params.stream, 17);
+        assert_eq!(opcode, ResponseOpcode::Supported);
+
+        let features = Default::default();
+        match Response::deserialize(opcode, ProtocolVersion::V4, &features, &mut &body[..], None)
+            .unwrap()
+        {
+            Response::Supported(supported) => {
+                assert_eq!(supported.options["COMPRESSION"], vec!["lz4".to_string()])
+            }
+            other => panic!("Expected SUPPORTED, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn server_side_frames_in_segments() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let params = FrameParams {
+            version: ProtocolVersion::V5 as u8,
+            flags: 0,
+            stream: 3,
+        };
+
+        let mut buf = Vec::new();
+        rt.block_on(write_request_frame_in_segments(
+            &mut buf,
+            params,
+            RequestOpcode::Options,
+            Bytes::new(),
+            true,
+        ))
+        .unwrap();
+        let payload = rt.block_on(read_segment(&mut &buf[..], true)).unwrap();
+        let mut received = BytesMut::from(&payload[..]);
+        let (_, opcode, _) = take_request_frame(&mut received).unwrap().unwrap();
+        assert_eq!(opcode, RequestOpcode::Options);
+        assert!(received.is_empty());
+
+        let mut buf = Vec::new();
+        rt.block_on(write_response_frame_in_segments(
+            &mut buf,
+            params,
+            ResponseOpcode::Ready,
+            Bytes::new(),
+            false,
+        ))
+        .unwrap();
+        let payload = rt.block_on(read_segment(&mut &buf[..], false)).unwrap();
+        let mut received = BytesMut::from(&payload[..]);
+        let (received_params, opcode, _) = take_response_frame(&mut received).unwrap().unwrap();
+        assert_eq!(opcode, ResponseOpcode::Ready);
+        assert_eq!(received_params.version, 0x85);
+    }
+
+    #[test]
+    fn server_side_body_extensions_roundtrip() {
+        let mut custom_payload = HashMap::new();
+        custom_payload.insert("key".to_string(), vec![1, 2, 3]);
+        let trace_id = Uuid::parse_str("f3b4958c-52a1-11e7-802a-010203040506").unwrap();
+
+        for compression in &[None, Some(Compression::LZ4), Some(Compression::Snappy)] {
+            let (flags, body) = prepare_response_body_with_extensions(
+                ResponseBodyWithExtensions {
+                    trace_id: Some(trace_id),
+                    warnings: vec!["warning".to_string()],
+                    custom_payload: Some(custom_payload.clone()),
+                    body: Bytes::from_static(b"body"),
+                },
+                *compression,
+            )
+            .unwrap();
+
+            let parsed = parse_response_body_extensions(flags, *compression, body).unwrap();
+            assert_eq!(parsed.trace_id, Some(trace_id));
+            assert_eq!(parsed.warnings, vec!["warning".to_string()]);
+            assert_eq!(parsed.custom_payload.as_ref(), Some(&custom_payload));
+            assert_eq!(parsed.body, Bytes::from_static(b"body"));
+
+            let (flags, body) = prepare_request_body_with_extensions(
+                RequestBodyWithExtensions {
+                    body: Bytes::from_static(b"body"),
+                    tracing: true,
+                    custom_payload: Some(&custom_payload),
+                },
+                *compression,
+            )
+            .unwrap();
+
+            let parsed = parse_request_body_extensions(flags, *compression, body).unwrap();
+            assert!(parsed.tracing);
+            assert_eq!(parsed.custom_payload.as_ref(), Some(&custom_payload));
+            assert_eq!(parsed.body, Bytes::from_static(b"body"));
+        }
+    }
+
+    #[test]
+    fn query_parameters_serial_consistency_and_timestamp() {
+        use request::query::QueryParameters;
+        use types::Consistency;
+
+        let parameters = QueryParameters {
+            consistency: Consistency::One,
+            serial_consistency: Some(Consistency::LocalSerial),
+            timestamp: Some(1234),
+            ..Default::default()
+        };
+
+        let mut buf = Vec::new();
+        parameters.serialize(ProtocolVersion::V4, &mut buf).unwrap();
+
+        let mut expected = vec![0x00, 0x01, 0x30, 0x00, 0x09];
+        expected.extend_from_slice(&1234i64.to_be_bytes());
+        assert_eq!(buf, expected);
+    }
+}
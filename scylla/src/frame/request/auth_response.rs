@@ -0,0 +1,37 @@
+use crate::frame::frame_errors::ParseError;
+use bytes::BufMut;
+
+use crate::{
+    frame::request::{DeserializableRequest, Request, RequestOpcode},
+    frame::types,
+    frame::ProtocolVersion,
+};
+
+/// Answers an authentication challenge, the token format depends on the authenticator
+pub struct AuthResponse {
+    pub response: Option<Vec<u8>>,
+}
+
+impl Request for AuthResponse {
+    const OPCODE: RequestOpcode = RequestOpcode::AuthResponse;
+
+    fn serialize(
+        &self,
+        _version: ProtocolVersion,
+        buf: &mut impl BufMut,
+    ) -> Result<(), ParseError> {
+        match &self.response {
+            Some(response) => types::write_bytes(response, buf)?,
+            None => types::write_int(-1, buf),
+        }
+        Ok(())
+    }
+}
+
+impl DeserializableRequest<'_> for AuthResponse {
+    fn deserialize(_version: ProtocolVersion, buf: &mut &[u8]) -> Result<Self, ParseError> {
+        let response = types::read_bytes_opt(buf)?.map(|response| response.to_owned());
+
+        Ok(AuthResponse { response })
+    }
+}
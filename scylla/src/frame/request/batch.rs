@@ -1,11 +1,17 @@
 use crate::frame::frame_errors::ParseError;
-use bytes::{BufMut, Bytes};
-use std::convert::TryInto;
+use bytes::BufMut;
+use std::convert::{TryFrom, TryInto};
+
+// Batch flags
+const FLAG_WITH_SERIAL_CONSISTENCY: u8 = 0x10;
+const FLAG_WITH_DEFAULT_TIMESTAMP: u8 = 0x20;
+const FLAG_WITH_NAMES_FOR_VALUES: u8 = 0x40;
 
 use crate::frame::{
-    request::{Request, RequestOpcode},
+    request::{read_flags, write_flags, DeserializableRequest, Request, RequestOpcode},
     types,
-    value::BatchValues,
+    value::{BatchValues, SerializedValues},
+    ProtocolVersion,
 };
 
 pub struct Batch<'a, StatementsIter, Values>
@@ -17,11 +23,16 @@ where
     pub statements_count: usize,
     pub batch_type: BatchType,
     pub consistency: types::Consistency,
+    pub serial_consistency: Option<types::Consistency>,
+    pub timestamp: Option<i64>,
     pub values: Values,
 }
 
+/// Batch parsed from a request, see [DeserializableRequest]
+pub type ParsedBatch<'a> = Batch<'a, std::vec::IntoIter<BatchStatement<'a>>, Vec<SerializedValues>>;
+
 /// The type of a batch.
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BatchType {
     Logged = 0,
     Unlogged = 1,
@@ -31,7 +42,7 @@ pub enum BatchType {
 #[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub enum BatchStatement<'a> {
     Query { text: &'a str },
-    Prepared { id: &'a Bytes },
+    Prepared { id: &'a [u8] },
 }
 
 impl<'a, StatementsIter, Values> Request for Batch<'a, StatementsIter, Values>
@@ -41,7 +52,7 @@ where
 {
     const OPCODE: RequestOpcode = RequestOpcode::Batch;
 
-    fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+    fn serialize(&self, version: ProtocolVersion, buf: &mut impl BufMut) -> Result<(), ParseError> {
         // Serializing type of batch
         buf.put_u8(self.batch_type as u8);
 
@@ -57,14 +68,106 @@ where
         types::write_consistency(self.consistency, buf);
 
         // Serializing flags
-        // FIXME: consider other flag values than 0
-        buf.put_u8(0);
+        let mut flags = 0;
+        if self.serial_consistency.is_some() {
+            flags |= FLAG_WITH_SERIAL_CONSISTENCY;
+        }
+        if self.timestamp.is_some() {
+            flags |= FLAG_WITH_DEFAULT_TIMESTAMP;
+        }
+        write_flags(flags, version, buf);
+
+        if let Some(serial_consistency) = self.serial_consistency {
+            types::write_consistency(serial_consistency, buf);
+        }
+
+        if let Some(timestamp) = self.timestamp {
+            types::write_long(timestamp, buf);
+        }
 
         Ok(())
     }
 }
 
-impl BatchStatement<'_> {
+impl TryFrom<u8> for BatchType {
+    type Error = ParseError;
+
+    fn try_from(batch_type: u8) -> Result<Self, Self::Error> {
+        match batch_type {
+            0 => Ok(BatchType::Logged),
+            1 => Ok(BatchType::Unlogged),
+            2 => Ok(BatchType::Counter),
+            other => Err(ParseError::BadData(format!(
+                "Unknown batch type: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl<'a> DeserializableRequest<'a> for ParsedBatch<'a> {
+    fn deserialize(version: ProtocolVersion, buf: &mut &'a [u8]) -> Result<Self, ParseError> {
+        let batch_type = BatchType::try_from(types::read_byte(buf)?)?;
+
+        let statements_count: usize = types::read_short(buf)?.try_into()?;
+        let mut statements = Vec::with_capacity(statements_count);
+        let mut values = Vec::with_capacity(statements_count);
+        for _ in 0..statements_count {
+            statements.push(BatchStatement::deserialize(buf)?);
+            values.push(SerializedValues::new_from_frame(buf)?);
+        }
+
+        let consistency = types::read_consistency(buf)?;
+
+        let flags = read_flags(version, buf)?;
+        if flags & !0x7F != 0 || flags & FLAG_WITH_NAMES_FOR_VALUES as u32 != 0 {
+            return Err(ParseError::BadData(format!(
+                "Unsupported batch flags: {:#x}",
+                flags
+            )));
+        }
+        let flags = flags as u8;
+
+        let serial_consistency = if flags & FLAG_WITH_SERIAL_CONSISTENCY != 0 {
+            Some(types::read_consistency(buf)?)
+        } else {
+            None
+        };
+
+        let timestamp = if flags & FLAG_WITH_DEFAULT_TIMESTAMP != 0 {
+            Some(types::read_long(buf)?)
+        } else {
+            None
+        };
+
+        Ok(Batch {
+            statements: statements.into_iter(),
+            statements_count,
+            batch_type,
+            consistency,
+            serial_consistency,
+            timestamp,
+            values,
+        })
+    }
+}
+
+impl<'a> BatchStatement<'a> {
+    fn deserialize(buf: &mut &'a [u8]) -> Result<Self, ParseError> {
+        match types::read_byte(buf)? {
+            0 => Ok(BatchStatement::Query {
+                text: types::read_long_string(buf)?,
+            }),
+            1 => Ok(BatchStatement::Prepared {
+                id: types::read_short_bytes(buf)?,
+            }),
+            other => Err(ParseError::BadData(format!(
+                "Unknown batch statement kind: {}",
+                other
+            ))),
+        }
+    }
+
     fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
         match self {
             BatchStatement::Query { text } => {
@@ -73,7 +176,7 @@ impl BatchStatement<'_> {
             }
             BatchStatement::Prepared { id } => {
                 buf.put_u8(1);
-                types::write_short_bytes(&id[..], buf)?;
+                types::write_short_bytes(id, buf)?;
             }
         }
 
@@ -2,24 +2,54 @@ use crate::frame::frame_errors::ParseError;
 use bytes::{BufMut, Bytes};
 
 use crate::{
-    frame::request::{query, Request, RequestOpcode},
+    frame::request::{query, DeserializableRequest, Request, RequestOpcode},
     frame::types,
+    frame::ProtocolVersion,
 };
 
 pub struct Execute<'a> {
     pub id: Bytes,
+    /// Id of the result metadata returned on PREPARE, sent in protocol v5.\
+    /// In older versions it's sent if Some, which requires Scylla's metadata id extension.
+    pub result_metadata_id: Option<Bytes>,
     pub parameters: query::QueryParameters<'a>,
 }
 
 impl Request for Execute<'_> {
     const OPCODE: RequestOpcode = RequestOpcode::Execute;
 
-    fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+    fn serialize(&self, version: ProtocolVersion, buf: &mut impl BufMut) -> Result<(), ParseError> {
         // Serializing statement id
         types::write_short_bytes(&self.id[..], buf)?;
 
+        if version >= ProtocolVersion::V5 || self.result_metadata_id.is_some() {
+            // Serializing result metadata id
+            let result_metadata_id: &[u8] = self.result_metadata_id.as_deref().unwrap_or(&[]);
+            types::write_short_bytes(result_metadata_id, buf)?;
+        }
+
         // Serializing params
-        self.parameters.serialize(buf)?;
+        self.parameters.serialize(version, buf)?;
         Ok(())
     }
 }
+
+impl<'a> DeserializableRequest<'a> for Execute<'a> {
+    fn deserialize(version: ProtocolVersion, buf: &mut &'a [u8]) -> Result<Self, ParseError> {
+        let id = Bytes::copy_from_slice(types::read_short_bytes(buf)?);
+
+        let result_metadata_id = if version >= ProtocolVersion::V5 {
+            Some(Bytes::copy_from_slice(types::read_short_bytes(buf)?)).filter(|id| !id.is_empty())
+        } else {
+            None
+        };
+
+        let parameters = query::QueryParameters::deserialize(version, buf)?;
+
+        Ok(Execute {
+            id,
+            result_metadata_id,
+            parameters,
+        })
+    }
+}
@@ -1,18 +1,24 @@
+pub mod auth_response;
 pub mod batch;
 pub mod execute;
 pub mod options;
 pub mod prepare;
 pub mod query;
+pub mod register;
 pub mod startup;
 
 use crate::frame::frame_errors::ParseError;
+use crate::frame::{types, ProtocolVersion};
 use bytes::{BufMut, Bytes};
 use num_enum::TryFromPrimitive;
 
+pub use auth_response::AuthResponse;
 pub use batch::Batch;
+pub use execute::Execute;
 pub use options::Options;
 pub use prepare::Prepare;
 pub use query::Query;
+pub use register::Register;
 pub use startup::Startup;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
@@ -31,11 +37,270 @@ pub enum RequestOpcode {
 pub trait Request {
     const OPCODE: RequestOpcode;
 
-    fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError>;
+    fn serialize(&self, version: ProtocolVersion, buf: &mut impl BufMut) -> Result<(), ParseError>;
 
-    fn to_bytes(&self) -> Result<Bytes, ParseError> {
+    fn to_bytes(&self, version: ProtocolVersion) -> Result<Bytes, ParseError> {
         let mut v = Vec::new();
-        self.serialize(&mut v)?;
+        self.serialize(version, &mut v)?;
         Ok(v.into())
     }
 }
+
+/// Request which can be parsed from a request body, e.g. by a proxy or a fake server
+pub trait DeserializableRequest<'a>: Request + Sized {
+    fn deserialize(version: ProtocolVersion, buf: &mut &'a [u8]) -> Result<Self, ParseError>;
+}
+
+/// Any request sent by a client, parsed on the server side
+pub enum ParsedRequest<'a> {
+    Startup(Startup),
+    Options(Options),
+    Query(Query<'a>),
+    Prepare(Prepare<'a>),
+    Execute(Execute<'a>),
+    Batch(batch::ParsedBatch<'a>),
+    Register(Register),
+    AuthResponse(AuthResponse),
+}
+
+impl<'a> ParsedRequest<'a> {
+    pub fn deserialize(
+        opcode: RequestOpcode,
+        version: ProtocolVersion,
+        buf: &mut &'a [u8],
+    ) -> Result<Self, ParseError> {
+        let request = match opcode {
+            RequestOpcode::Startup => ParsedRequest::Startup(Startup::deserialize(version, buf)?),
+            RequestOpcode::Options => ParsedRequest::Options(Options::deserialize(version, buf)?),
+            RequestOpcode::Query => ParsedRequest::Query(Query::deserialize(version, buf)?),
+            RequestOpcode::Prepare => ParsedRequest::Prepare(Prepare::deserialize(version, buf)?),
+            RequestOpcode::Execute => ParsedRequest::Execute(Execute::deserialize(version, buf)?),
+            RequestOpcode::Register => {
+                ParsedRequest::Register(Register::deserialize(version, buf)?)
+            }
+            RequestOpcode::Batch => {
+                ParsedRequest::Batch(batch::ParsedBatch::deserialize(version, buf)?)
+            }
+            RequestOpcode::AuthResponse => {
+                ParsedRequest::AuthResponse(AuthResponse::deserialize(version, buf)?)
+            }
+        };
+
+        Ok(request)
+    }
+
+    pub fn opcode(&self) -> RequestOpcode {
+        match self {
+            ParsedRequest::Startup(_) => RequestOpcode::Startup,
+            ParsedRequest::Options(_) => RequestOpcode::Options,
+            ParsedRequest::Query(_) => RequestOpcode::Query,
+            ParsedRequest::Prepare(_) => RequestOpcode::Prepare,
+            ParsedRequest::Execute(_) => RequestOpcode::Execute,
+            ParsedRequest::Batch(_) => RequestOpcode::Batch,
+            ParsedRequest::Register(_) => RequestOpcode::Register,
+            ParsedRequest::AuthResponse(_) => RequestOpcode::AuthResponse,
+        }
+    }
+
+    /// Text of the statement, if the request is a QUERY or a PREPARE
+    pub fn statement_text(&self) -> Option<&str> {
+        match self {
+            ParsedRequest::Query(query) => Some(&query.contents),
+            ParsedRequest::Prepare(prepare) => Some(prepare.query),
+            _ => None,
+        }
+    }
+}
+
+/// Writes query/batch flags, which are a [byte] up to protocol v4 and an [int] since v5
+pub(crate) fn write_flags(flags: u8, version: ProtocolVersion, buf: &mut impl BufMut) {
+    if version >= ProtocolVersion::V5 {
+        buf.put_u32(flags as u32);
+    } else {
+        buf.put_u8(flags);
+    }
+}
+
+/// Reads query/batch flags written by [write_flags]
+pub(crate) fn read_flags(version: ProtocolVersion, buf: &mut &[u8]) -> Result<u32, ParseError> {
+    if version >= ProtocolVersion::V5 {
+        Ok(types::read_int(buf)? as u32)
+    } else {
+        Ok(types::read_byte(buf)? as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::types::Consistency;
+    use crate::frame::value::SerializedValues;
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    fn parse(opcode: RequestOpcode, version: ProtocolVersion, body: &[u8]) -> ParsedRequest<'_> {
+        let mut buf = body;
+        let request = ParsedRequest::deserialize(opcode, version, &mut buf).unwrap();
+        assert!(buf.is_empty(), "{:?} was not parsed completely", opcode);
+        assert_eq!(request.opcode(), opcode);
+        request
+    }
+
+    #[test]
+    fn query_roundtrip() {
+        let mut values = SerializedValues::new();
+        values.add_value(&7_i32).unwrap();
+        values.add_value(&Option::<i32>::None).unwrap();
+
+        let query = Query {
+            contents: "SELECT * FROM ks.t WHERE a = ? AND b = ?".to_string(),
+            parameters: query::QueryParameters {
+                consistency: Consistency::Quorum,
+                serial_consistency: Some(Consistency::Serial),
+                timestamp: Some(42),
+                skip_metadata: true,
+                page_size: Some(100),
+                paging_state: Some(Bytes::from_static(b"state")),
+                values: Cow::Borrowed(&values),
+            },
+        };
+
+        for &version in &[
+            ProtocolVersion::V3,
+            ProtocolVersion::V4,
+            ProtocolVersion::V5,
+        ] {
+            let body = query.to_bytes(version).unwrap();
+            let parsed = match parse(RequestOpcode::Query, version, &body) {
+                ParsedRequest::Query(parsed) => parsed,
+                _ => panic!("Expected a QUERY"),
+            };
+
+            assert_eq!(parsed.contents, query.contents);
+            let (params, expected) = (&parsed.parameters, &query.parameters);
+            assert_eq!(params.consistency, expected.consistency);
+            assert_eq!(params.serial_consistency, expected.serial_consistency);
+            assert_eq!(params.timestamp, expected.timestamp);
+            assert_eq!(params.skip_metadata, expected.skip_metadata);
+            assert_eq!(params.page_size, expected.page_size);
+            assert_eq!(params.paging_state, expected.paging_state);
+            assert_eq!(params.values, expected.values);
+        }
+    }
+
+    #[test]
+    fn execute_and_prepare_roundtrip() {
+        for &version in &[ProtocolVersion::V4, ProtocolVersion::V5] {
+            // Result metadata id is sent in v4 only with Scylla's extension, which isn't parsed here
+            let result_metadata_id =
+                Some(Bytes::from_static(b"metadata id")).filter(|_| version >= ProtocolVersion::V5);
+            let execute = Execute {
+                id: Bytes::from_static(b"id"),
+                result_metadata_id,
+                parameters: Default::default(),
+            };
+
+            let body = execute.to_bytes(version).unwrap();
+            match parse(RequestOpcode::Execute, version, &body) {
+                ParsedRequest::Execute(parsed) => {
+                    assert_eq!(parsed.id, execute.id);
+                    assert_eq!(parsed.result_metadata_id, execute.result_metadata_id);
+                }
+                _ => panic!("Expected an EXECUTE"),
+            }
+
+            let prepare = Prepare {
+                query: "SELECT * FROM ks.t",
+            };
+            let body = prepare.to_bytes(version).unwrap();
+            let parsed = parse(RequestOpcode::Prepare, version, &body);
+            assert_eq!(parsed.statement_text(), Some("SELECT * FROM ks.t"));
+        }
+    }
+
+    #[test]
+    fn batch_roundtrip() {
+        let id = Bytes::from_static(b"prepared id");
+        let statements = vec![
+            batch::BatchStatement::Query {
+                text: "INSERT INTO ks.t (a) VALUES (?)",
+            },
+            batch::BatchStatement::Prepared { id: &id },
+        ];
+        let values = vec![(1_i32,), (2_i32,)];
+
+        let batch = Batch {
+            statements: statements.clone().into_iter(),
+            statements_count: statements.len(),
+            batch_type: batch::BatchType::Unlogged,
+            consistency: Consistency::One,
+            serial_consistency: None,
+            timestamp: Some(1234),
+            values: &values,
+        };
+
+        for &version in &[ProtocolVersion::V4, ProtocolVersion::V5] {
+            let body = batch.to_bytes(version).unwrap();
+            let parsed = match parse(RequestOpcode::Batch, version, &body) {
+                ParsedRequest::Batch(parsed) => parsed,
+                _ => panic!("Expected a BATCH"),
+            };
+
+            assert_eq!(parsed.statements.clone().collect::<Vec<_>>(), statements);
+            assert_eq!(parsed.batch_type, batch::BatchType::Unlogged);
+            assert_eq!(parsed.consistency, Consistency::One);
+            assert_eq!(parsed.timestamp, Some(1234));
+            assert_eq!(
+                parsed.values[1].iter().next(),
+                Some(Some(&[0, 0, 0, 2][..]))
+            );
+        }
+    }
+
+    #[test]
+    fn control_requests_roundtrip() {
+        let version = ProtocolVersion::V4;
+
+        let mut options = HashMap::new();
+        options.insert("CQL_VERSION".to_string(), "4.0.0".to_string());
+        let body = Startup {
+            options: options.clone(),
+        }
+        .to_bytes(version)
+        .unwrap();
+        match parse(RequestOpcode::Startup, version, &body) {
+            ParsedRequest::Startup(startup) => assert_eq!(startup.options, options),
+            _ => panic!("Expected a STARTUP"),
+        }
+
+        let body = Options.to_bytes(version).unwrap();
+        assert!(matches!(
+            parse(RequestOpcode::Options, version, &body),
+            ParsedRequest::Options(_)
+        ));
+
+        use crate::frame::response::event::EventType;
+        let event_types = vec![EventType::TopologyChange, EventType::SchemaChange];
+        let body = Register {
+            event_types: event_types.clone(),
+        }
+        .to_bytes(version)
+        .unwrap();
+        match parse(RequestOpcode::Register, version, &body) {
+            ParsedRequest::Register(register) => assert_eq!(register.event_types, event_types),
+            _ => panic!("Expected a REGISTER"),
+        }
+
+        for response in &[None, Some(b"\0user\0password".to_vec())] {
+            let body = AuthResponse {
+                response: response.clone(),
+            }
+            .to_bytes(version)
+            .unwrap();
+            match parse(RequestOpcode::AuthResponse, version, &body) {
+                ParsedRequest::AuthResponse(parsed) => assert_eq!(&parsed.response, response),
+                _ => panic!("Expected an AUTH_RESPONSE"),
+            }
+        }
+    }
+}
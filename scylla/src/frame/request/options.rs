@@ -1,14 +1,25 @@
 use crate::frame::frame_errors::ParseError;
 use bytes::BufMut;
 
-use crate::frame::request::{Request, RequestOpcode};
+use crate::frame::request::{DeserializableRequest, Request, RequestOpcode};
+use crate::frame::ProtocolVersion;
 
 pub struct Options;
 
 impl Request for Options {
     const OPCODE: RequestOpcode = RequestOpcode::Options;
 
-    fn serialize(&self, _buf: &mut impl BufMut) -> Result<(), ParseError> {
+    fn serialize(
+        &self,
+        _version: ProtocolVersion,
+        _buf: &mut impl BufMut,
+    ) -> Result<(), ParseError> {
         Ok(())
     }
 }
+
+impl DeserializableRequest<'_> for Options {
+    fn deserialize(_version: ProtocolVersion, _buf: &mut &[u8]) -> Result<Self, ParseError> {
+        Ok(Options)
+    }
+}
@@ -2,8 +2,9 @@ use crate::frame::frame_errors::ParseError;
 use bytes::BufMut;
 
 use crate::{
-    frame::request::{Request, RequestOpcode},
+    frame::request::{DeserializableRequest, Request, RequestOpcode},
     frame::types,
+    frame::ProtocolVersion,
 };
 
 pub struct Prepare<'a> {
@@ -13,8 +14,32 @@ pub struct Prepare<'a> {
 impl<'a> Request for Prepare<'a> {
     const OPCODE: RequestOpcode = RequestOpcode::Prepare;
 
-    fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+    fn serialize(&self, version: ProtocolVersion, buf: &mut impl BufMut) -> Result<(), ParseError> {
         types::write_long_string(self.query, buf)?;
+
+        if version >= ProtocolVersion::V5 {
+            // Prepare flags, no keyspace is sent
+            types::write_int(0, buf);
+        }
         Ok(())
     }
 }
+
+impl<'a> DeserializableRequest<'a> for Prepare<'a> {
+    fn deserialize(version: ProtocolVersion, buf: &mut &'a [u8]) -> Result<Self, ParseError> {
+        let query = types::read_long_string(buf)?;
+
+        if version >= ProtocolVersion::V5 {
+            let flags = types::read_int(buf)?;
+            if flags != 0 {
+                // The only flag means that a keyspace is sent, which the driver never does
+                return Err(ParseError::BadData(format!(
+                    "Unsupported prepare flags: {:#x}",
+                    flags
+                )));
+            }
+        }
+
+        Ok(Prepare { query })
+    }
+}
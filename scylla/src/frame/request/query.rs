@@ -1,21 +1,23 @@
 use crate::frame::frame_errors::ParseError;
 use bytes::{BufMut, Bytes};
+use std::borrow::Cow;
 
 use crate::{
-    frame::request::{Request, RequestOpcode},
+    frame::request::{read_flags, write_flags, DeserializableRequest, Request, RequestOpcode},
     frame::types,
     frame::value::SerializedValues,
+    frame::ProtocolVersion,
 };
 
 // Query flags
 // Unused flags are commented out so that they don't trigger warnings
 const FLAG_VALUES: u8 = 0x01;
-// const FLAG_SKIP_METADATA: u8 = 0x02;
+const FLAG_SKIP_METADATA: u8 = 0x02;
 const FLAG_PAGE_SIZE: u8 = 0x04;
 const FLAG_WITH_PAGING_STATE: u8 = 0x08;
-// const FLAG_WITH_SERIAL_CONSISTENCY: u8 = 0x10;
-// const FLAG_WITH_DEFAULT_TIMESTAMP: u8 = 0x20;
-// const FLAG_WITH_NAMES_FOR_VALUES: u8 = 0x40;
+const FLAG_WITH_SERIAL_CONSISTENCY: u8 = 0x10;
+const FLAG_WITH_DEFAULT_TIMESTAMP: u8 = 0x20;
+const FLAG_WITH_NAMES_FOR_VALUES: u8 = 0x40;
 
 pub struct Query<'a> {
     pub contents: String,
@@ -25,33 +27,56 @@ pub struct Query<'a> {
 impl Request for Query<'_> {
     const OPCODE: RequestOpcode = RequestOpcode::Query;
 
-    fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+    fn serialize(&self, version: ProtocolVersion, buf: &mut impl BufMut) -> Result<(), ParseError> {
         types::write_long_string(&self.contents, buf)?;
-        self.parameters.serialize(buf)?;
+        self.parameters.serialize(version, buf)?;
         Ok(())
     }
 }
 
+impl<'a> DeserializableRequest<'a> for Query<'a> {
+    fn deserialize(version: ProtocolVersion, buf: &mut &'a [u8]) -> Result<Self, ParseError> {
+        let contents = types::read_long_string(buf)?.to_owned();
+        let parameters = QueryParameters::deserialize(version, buf)?;
+
+        Ok(Query {
+            contents,
+            parameters,
+        })
+    }
+}
+
 pub struct QueryParameters<'a> {
     pub consistency: types::Consistency,
+    pub serial_consistency: Option<types::Consistency>,
+    pub timestamp: Option<i64>,
+    /// Asks the server not to send result metadata, because the driver has it cached
+    pub skip_metadata: bool,
     pub page_size: Option<i32>,
     pub paging_state: Option<Bytes>,
-    pub values: &'a SerializedValues,
+    pub values: Cow<'a, SerializedValues>,
 }
 
 impl Default for QueryParameters<'_> {
     fn default() -> Self {
         Self {
             consistency: Default::default(),
+            serial_consistency: None,
+            timestamp: None,
+            skip_metadata: false,
             page_size: None,
             paging_state: None,
-            values: SerializedValues::EMPTY,
+            values: Cow::Borrowed(SerializedValues::EMPTY),
         }
     }
 }
 
 impl QueryParameters<'_> {
-    pub fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+    pub fn serialize(
+        &self,
+        version: ProtocolVersion,
+        buf: &mut impl BufMut,
+    ) -> Result<(), ParseError> {
         types::write_consistency(self.consistency, buf);
 
         let mut flags = 0;
@@ -59,6 +84,10 @@ impl QueryParameters<'_> {
             flags |= FLAG_VALUES;
         }
 
+        if self.skip_metadata {
+            flags |= FLAG_SKIP_METADATA;
+        }
+
         if self.page_size.is_some() {
             flags |= FLAG_PAGE_SIZE;
         }
@@ -67,7 +96,15 @@ impl QueryParameters<'_> {
             flags |= FLAG_WITH_PAGING_STATE;
         }
 
-        buf.put_u8(flags);
+        if self.serial_consistency.is_some() {
+            flags |= FLAG_WITH_SERIAL_CONSISTENCY;
+        }
+
+        if self.timestamp.is_some() {
+            flags |= FLAG_WITH_DEFAULT_TIMESTAMP;
+        }
+
+        write_flags(flags, version, buf);
 
         if !self.values.is_empty() {
             self.values.write_to_request(buf);
@@ -81,6 +118,69 @@ impl QueryParameters<'_> {
             types::write_bytes(&paging_state, buf)?;
         }
 
+        if let Some(serial_consistency) = self.serial_consistency {
+            types::write_consistency(serial_consistency, buf);
+        }
+
+        if let Some(timestamp) = self.timestamp {
+            types::write_long(timestamp, buf);
+        }
+
         Ok(())
     }
 }
+
+impl QueryParameters<'_> {
+    pub fn deserialize(version: ProtocolVersion, buf: &mut &[u8]) -> Result<Self, ParseError> {
+        let consistency = types::read_consistency(buf)?;
+        let flags = read_flags(version, buf)?;
+
+        if flags & !0x7F != 0 || flags & FLAG_WITH_NAMES_FOR_VALUES as u32 != 0 {
+            return Err(ParseError::BadData(format!(
+                "Unsupported query flags: {:#x}",
+                flags
+            )));
+        }
+        let flags = flags as u8;
+
+        let values = if flags & FLAG_VALUES != 0 {
+            SerializedValues::new_from_frame(buf)?
+        } else {
+            SerializedValues::new()
+        };
+
+        let page_size = if flags & FLAG_PAGE_SIZE != 0 {
+            Some(types::read_int(buf)?)
+        } else {
+            None
+        };
+
+        let paging_state = if flags & FLAG_WITH_PAGING_STATE != 0 {
+            Some(Bytes::copy_from_slice(types::read_bytes(buf)?))
+        } else {
+            None
+        };
+
+        let serial_consistency = if flags & FLAG_WITH_SERIAL_CONSISTENCY != 0 {
+            Some(types::read_consistency(buf)?)
+        } else {
+            None
+        };
+
+        let timestamp = if flags & FLAG_WITH_DEFAULT_TIMESTAMP != 0 {
+            Some(types::read_long(buf)?)
+        } else {
+            None
+        };
+
+        Ok(QueryParameters {
+            consistency,
+            serial_consistency,
+            timestamp,
+            skip_metadata: flags & FLAG_SKIP_METADATA != 0,
+            page_size,
+            paging_state,
+            values: Cow::Owned(values),
+        })
+    }
+}
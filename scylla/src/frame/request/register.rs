@@ -0,0 +1,43 @@
+use crate::frame::frame_errors::ParseError;
+use bytes::BufMut;
+
+use crate::{
+    frame::request::{DeserializableRequest, Request, RequestOpcode},
+    frame::response::event::EventType,
+    frame::types,
+    frame::ProtocolVersion,
+};
+
+/// Subscribes the connection to server events of the given types
+pub struct Register {
+    pub event_types: Vec<EventType>,
+}
+
+impl Request for Register {
+    const OPCODE: RequestOpcode = RequestOpcode::Register;
+
+    fn serialize(
+        &self,
+        _version: ProtocolVersion,
+        buf: &mut impl BufMut,
+    ) -> Result<(), ParseError> {
+        let event_types: Vec<String> = self
+            .event_types
+            .iter()
+            .map(|event_type| event_type.to_string())
+            .collect();
+        types::write_string_list(&event_types, buf)?;
+        Ok(())
+    }
+}
+
+impl DeserializableRequest<'_> for Register {
+    fn deserialize(_version: ProtocolVersion, buf: &mut &[u8]) -> Result<Self, ParseError> {
+        let event_types = types::read_string_list(buf)?
+            .iter()
+            .map(|name| name.parse())
+            .collect::<Result<Vec<EventType>, ParseError>>()?;
+
+        Ok(Register { event_types })
+    }
+}
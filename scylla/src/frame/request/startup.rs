@@ -4,8 +4,9 @@ use bytes::BufMut;
 use std::collections::HashMap;
 
 use crate::{
-    frame::request::{Request, RequestOpcode},
+    frame::request::{DeserializableRequest, Request, RequestOpcode},
     frame::types,
+    frame::ProtocolVersion,
 };
 
 pub struct Startup {
@@ -15,8 +16,20 @@ pub struct Startup {
 impl Request for Startup {
     const OPCODE: RequestOpcode = RequestOpcode::Startup;
 
-    fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+    fn serialize(
+        &self,
+        _version: ProtocolVersion,
+        buf: &mut impl BufMut,
+    ) -> Result<(), ParseError> {
         types::write_string_map(&self.options, buf)?;
         Ok(())
     }
 }
+
+impl DeserializableRequest<'_> for Startup {
+    fn deserialize(_version: ProtocolVersion, buf: &mut &[u8]) -> Result<Self, ParseError> {
+        let options = types::read_string_map(buf)?;
+
+        Ok(Startup { options })
+    }
+}
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::hash::Hash;
 use std::net::IpAddr;
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum FromRowError {
@@ -67,10 +68,12 @@ macro_rules! impl_from_cql_val {
     };
 }
 
+impl_from_cql_val!(bool, as_boolean); // bool::from_cql<CQLValue>
 impl_from_cql_val!(i32, as_int); // i32::from_cql<CQLValue>
 impl_from_cql_val!(i64, as_bigint); // i64::from_cql<CQLValue>
 impl_from_cql_val!(String, into_string); // String::from_cql<CQLValue>
 impl_from_cql_val!(IpAddr, as_inet); // IpAddr::from_cql<CQLValue>
+impl_from_cql_val!(Uuid, as_uuid); // Uuid::from_cql<CQLValue>
 
 // Vec<T>::from_cql<CQLValue>
 impl<T: FromCQLVal<CQLValue>> FromCQLVal<CQLValue> for Vec<T> {
@@ -185,6 +188,13 @@ mod tests {
     use crate as scylla;
     use crate::macros::FromRow;
     use std::net::{IpAddr, Ipv4Addr};
+    use uuid::Uuid;
+
+    #[test]
+    fn bool_from_cql() {
+        assert_eq!(Ok(true), bool::from_cql(CQLValue::Boolean(true)));
+        assert_eq!(Ok(false), bool::from_cql(CQLValue::Boolean(false)));
+    }
 
     #[test]
     fn i32_from_cql() {
@@ -214,6 +224,21 @@ mod tests {
         assert_eq!(Ok(ip_addr), IpAddr::from_cql(CQLValue::Inet(ip_addr)));
     }
 
+    #[test]
+    fn uuid_from_cql() {
+        let test_uuid: Uuid = Uuid::parse_str("8e14e760-7fa8-11eb-bc66-000000000001").unwrap();
+
+        assert_eq!(
+            test_uuid,
+            Uuid::from_cql(CQLValue::Uuid(test_uuid)).unwrap()
+        );
+
+        assert_eq!(
+            test_uuid,
+            Uuid::from_cql(CQLValue::Timeuuid(test_uuid)).unwrap()
+        );
+    }
+
     #[test]
     fn vec_from_cql() {
         let cql_val = CQLValue::Set(vec![CQLValue::Int(1), CQLValue::Int(2), CQLValue::Int(3)]);
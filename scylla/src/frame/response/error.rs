@@ -1,24 +1,184 @@
 use crate::frame::frame_errors::ParseError;
 use crate::frame::types;
-use crate::transport::errors::{DBError, QueryError};
+use crate::transport::errors::{DBError, OperationType, QueryError};
+use crate::transport::protocol_features::ProtocolFeatures;
+use bytes::{BufMut, Bytes};
+
+// Code of the error sent in response to EXECUTE of an unknown prepared statement
+pub(crate) const UNPREPARED_CODE: i32 = 0x2500;
 
 #[derive(Debug)]
 pub struct Error {
     pub code: i32,
     pub reason: String,
+    /// Typed error, decoded from the code and additional fields of known errors
+    pub error: DBError,
 }
 
 impl Error {
-    pub fn deserialize(buf: &mut &[u8]) -> Result<Self, ParseError> {
+    /// Creates an error which isn't decoded into any specific [DBError]
+    pub fn new(code: i32, reason: String) -> Self {
+        Error {
+            code,
+            error: DBError::ErrorMsg(code, reason.clone()),
+            reason,
+        }
+    }
+
+    pub fn deserialize(features: &ProtocolFeatures, buf: &mut &[u8]) -> Result<Self, ParseError> {
         let code = types::read_int(buf)?;
         let reason = types::read_string(buf)?.to_owned();
 
-        Ok(Error { code, reason })
+        // The code of the rate limit error is negotiated with Scylla in STARTUP
+        let error = if Some(code) == features.rate_limit_error_code {
+            let op_type = OperationType::from(types::read_byte(buf)?);
+            let rejected_by_coordinator = types::read_byte(buf)? != 0;
+            DBError::RateLimitReached {
+                op_type,
+                rejected_by_coordinator,
+            }
+        } else if code == UNPREPARED_CODE {
+            let statement_id = Bytes::copy_from_slice(types::read_short_bytes(buf)?);
+            DBError::Unprepared { statement_id }
+        } else {
+            DBError::ErrorMsg(code, reason.clone())
+        };
+
+        Ok(Error {
+            code,
+            reason,
+            error,
+        })
+    }
+}
+
+impl Error {
+    pub fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+        types::write_int(self.code, buf);
+        types::write_string(&self.reason, buf)?;
+
+        match &self.error {
+            DBError::RateLimitReached {
+                op_type,
+                rejected_by_coordinator,
+            } => {
+                buf.put_u8((*op_type).into());
+                buf.put_u8(*rejected_by_coordinator as u8);
+            }
+            DBError::Unprepared { statement_id } => {
+                types::write_short_bytes(statement_id, buf)?;
+            }
+            DBError::ErrorMsg(..) => {}
+        }
+
+        Ok(())
     }
 }
 
-impl Into<QueryError> for Error {
-    fn into(self) -> QueryError {
-        QueryError::DBError(DBError::ErrorMsg(self.code, self.reason))
+impl From<Error> for QueryError {
+    fn from(error: Error) -> QueryError {
+        QueryError::DBError(error.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use crate::frame::types;
+    use crate::transport::errors::{DBError, OperationType};
+    use crate::transport::protocol_features::ProtocolFeatures;
+    use bytes::Bytes;
+
+    fn serialize_error(code: i32, reason: &str, extra: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        types::write_int(code, &mut buf);
+        types::write_string(reason, &mut buf).unwrap();
+        buf.extend_from_slice(extra);
+        buf
+    }
+
+    #[test]
+    fn rate_limit_error() {
+        let features = ProtocolFeatures {
+            rate_limit_error_code: Some(0xF000),
+            ..Default::default()
+        };
+        let buf = serialize_error(0xF000, "Too many requests", &[1, 1]);
+
+        let error = Error::deserialize(&features, &mut &buf[..]).unwrap();
+        assert!(matches!(
+            error.error,
+            DBError::RateLimitReached {
+                op_type: OperationType::Write,
+                rejected_by_coordinator: true,
+            }
+        ));
+
+        // Without the negotiated extension the code isn't recognized
+        let error = Error::deserialize(&ProtocolFeatures::default(), &mut &buf[..]).unwrap();
+        assert!(matches!(error.error, DBError::ErrorMsg(0xF000, _)));
+    }
+
+    #[test]
+    fn error_roundtrip() {
+        let features = ProtocolFeatures {
+            rate_limit_error_code: Some(0xF000),
+            ..Default::default()
+        };
+        let errors = vec![
+            Error::new(0x1001, "Overloaded".to_string()),
+            Error {
+                code: 0xF000,
+                reason: "Too many requests".to_string(),
+                error: DBError::RateLimitReached {
+                    op_type: OperationType::Read,
+                    rejected_by_coordinator: false,
+                },
+            },
+            Error {
+                code: 0x2500,
+                reason: "Unknown prepared statement".to_string(),
+                error: DBError::Unprepared {
+                    statement_id: Bytes::from_static(b"statement id"),
+                },
+            },
+        ];
+
+        for error in errors {
+            let mut buf = Vec::new();
+            error.serialize(&mut buf).unwrap();
+            let parsed = Error::deserialize(&features, &mut &buf[..]).unwrap();
+
+            assert_eq!(parsed.code, error.code);
+            assert_eq!(parsed.reason, error.reason);
+            assert_eq!(format!("{:?}", parsed.error), format!("{:?}", error.error));
+        }
+    }
+
+    #[test]
+    fn unprepared_error() {
+        let mut id = Vec::new();
+        types::write_short_bytes(b"statement id", &mut id).unwrap();
+        let buf = serialize_error(0x2500, "Unknown prepared statement", &id);
+
+        let mut parsed_buf = &buf[..];
+        let error = Error::deserialize(&ProtocolFeatures::default(), &mut parsed_buf).unwrap();
+        assert!(parsed_buf.is_empty());
+        assert!(matches!(
+            error.error,
+            DBError::Unprepared { statement_id } if statement_id == Bytes::from_static(b"statement id")
+        ));
+    }
+
+    #[test]
+    fn generic_error() {
+        let buf = serialize_error(0x2200, "Invalid query", &[]);
+
+        let error = Error::deserialize(&ProtocolFeatures::default(), &mut &buf[..]).unwrap();
+        assert_eq!(error.code, 0x2200);
+        assert_eq!(error.reason, "Invalid query");
+        assert!(
+            matches!(error.error, DBError::ErrorMsg(0x2200, reason) if reason == "Invalid query")
+        );
     }
 }
@@ -0,0 +1,181 @@
+use crate::frame::frame_errors::ParseError;
+use crate::frame::response::result::{self, SchemaChange};
+use crate::frame::types;
+use bytes::BufMut;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Type of events to which a connection can subscribe using REGISTER
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    TopologyChange,
+    StatusChange,
+    SchemaChange,
+}
+
+/// An event pushed by the server to connections which subscribed to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    TopologyChange(TopologyChangeEvent),
+    StatusChange(StatusChangeEvent),
+    SchemaChange(SchemaChange),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopologyChangeEvent {
+    NewNode(SocketAddr),
+    RemovedNode(SocketAddr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusChangeEvent {
+    Up(SocketAddr),
+    Down(SocketAddr),
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EventType::TopologyChange => "TOPOLOGY_CHANGE",
+            EventType::StatusChange => "STATUS_CHANGE",
+            EventType::SchemaChange => "SCHEMA_CHANGE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for EventType {
+    type Err = ParseError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "TOPOLOGY_CHANGE" => Ok(EventType::TopologyChange),
+            "STATUS_CHANGE" => Ok(EventType::StatusChange),
+            "SCHEMA_CHANGE" => Ok(EventType::SchemaChange),
+            other => Err(ParseError::BadData(format!(
+                "Unknown event type: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::TopologyChange(_) => EventType::TopologyChange,
+            Event::StatusChange(_) => EventType::StatusChange,
+            Event::SchemaChange(_) => EventType::SchemaChange,
+        }
+    }
+
+    pub fn deserialize(buf: &mut &[u8]) -> Result<Self, ParseError> {
+        let event_type: EventType = types::read_string(buf)?.parse()?;
+
+        let event = match event_type {
+            EventType::TopologyChange => {
+                let change = types::read_string(buf)?;
+                let addr = types::read_inet(buf)?;
+                Event::TopologyChange(match change {
+                    "NEW_NODE" => TopologyChangeEvent::NewNode(addr),
+                    "REMOVED_NODE" => TopologyChangeEvent::RemovedNode(addr),
+                    other => {
+                        return Err(ParseError::BadData(format!(
+                            "Unknown topology change: {}",
+                            other
+                        )))
+                    }
+                })
+            }
+            EventType::StatusChange => {
+                let status = types::read_string(buf)?;
+                let addr = types::read_inet(buf)?;
+                Event::StatusChange(match status {
+                    "UP" => StatusChangeEvent::Up(addr),
+                    "DOWN" => StatusChangeEvent::Down(addr),
+                    other => {
+                        return Err(ParseError::BadData(format!(
+                            "Unknown status change: {}",
+                            other
+                        )))
+                    }
+                })
+            }
+            EventType::SchemaChange => Event::SchemaChange(result::deser_schema_change(buf)?),
+        };
+
+        Ok(event)
+    }
+
+    pub fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+        types::write_string(&self.event_type().to_string(), buf)?;
+
+        match self {
+            Event::TopologyChange(change) => {
+                let (change, addr) = match change {
+                    TopologyChangeEvent::NewNode(addr) => ("NEW_NODE", addr),
+                    TopologyChangeEvent::RemovedNode(addr) => ("REMOVED_NODE", addr),
+                };
+                types::write_string(change, buf)?;
+                types::write_inet(*addr, buf);
+            }
+            Event::StatusChange(status) => {
+                let (status, addr) = match status {
+                    StatusChangeEvent::Up(addr) => ("UP", addr),
+                    StatusChangeEvent::Down(addr) => ("DOWN", addr),
+                };
+                types::write_string(status, buf)?;
+                types::write_inet(*addr, buf);
+            }
+            Event::SchemaChange(schema_change) => result::ser_schema_change(schema_change, buf)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::response::result::SchemaChangeTarget;
+    use crate::frame::response::result::SchemaChangeType;
+
+    #[test]
+    fn event_roundtrip() {
+        let events = vec![
+            Event::TopologyChange(TopologyChangeEvent::NewNode(
+                "127.0.0.2:9042".parse().unwrap(),
+            )),
+            Event::StatusChange(StatusChangeEvent::Down("[::1]:9042".parse().unwrap())),
+            Event::SchemaChange(SchemaChange {
+                change_type: SchemaChangeType::Dropped,
+                target: SchemaChangeTarget::Table {
+                    keyspace_name: "ks".to_string(),
+                    table_name: "t".to_string(),
+                },
+            }),
+        ];
+
+        for event in events {
+            let mut buf = Vec::new();
+            event.serialize(&mut buf).unwrap();
+            assert_eq!(Event::deserialize(&mut &buf[..]).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn event_type_names() {
+        for event_type in &[
+            EventType::TopologyChange,
+            EventType::StatusChange,
+            EventType::SchemaChange,
+        ] {
+            assert_eq!(
+                event_type.to_string().parse::<EventType>().unwrap(),
+                *event_type
+            );
+        }
+        assert!("MOVED_NODE".parse::<EventType>().is_err());
+    }
+}
@@ -1,12 +1,17 @@
 pub mod cql_to_rust;
 pub mod error;
+pub mod event;
 pub mod result;
 pub mod supported;
 
 use crate::frame::frame_errors::ParseError;
+use crate::frame::ProtocolVersion;
+use crate::transport::protocol_features::ProtocolFeatures;
+use bytes::BufMut;
 use num_enum::TryFromPrimitive;
 
 pub use error::Error;
+pub use event::Event;
 pub use supported::Supported;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
@@ -29,21 +34,72 @@ pub enum Response {
     Result(result::Result),
     Authenticate,
     Supported(Supported),
+    Event(Event),
 }
 
 impl Response {
-    pub fn deserialize(opcode: ResponseOpcode, buf: &mut &[u8]) -> Result<Response, ParseError> {
+    pub fn deserialize(
+        opcode: ResponseOpcode,
+        version: ProtocolVersion,
+        features: &ProtocolFeatures,
+        buf: &mut &[u8],
+        cached_metadata: Option<&result::ResultMetadata>,
+    ) -> Result<Response, ParseError> {
         let response = match opcode {
-            ResponseOpcode::Error => Response::Error(Error::deserialize(buf)?),
+            ResponseOpcode::Error => Response::Error(Error::deserialize(features, buf)?),
             ResponseOpcode::Ready => Response::Ready,
             ResponseOpcode::Authenticate => unimplemented!(),
             ResponseOpcode::Supported => Response::Supported(Supported::deserialize(buf)?),
-            ResponseOpcode::Result => Response::Result(result::deserialize(buf)?),
-            ResponseOpcode::Event => unimplemented!(),
+            ResponseOpcode::Result => Response::Result(result::deserialize(
+                version,
+                features,
+                buf,
+                cached_metadata,
+            )?),
+            ResponseOpcode::Event => Response::Event(Event::deserialize(buf)?),
             ResponseOpcode::AuthChallenge => unimplemented!(),
             ResponseOpcode::AuthSuccess => unimplemented!(),
         };
 
         Ok(response)
     }
+
+    pub fn opcode(&self) -> ResponseOpcode {
+        match self {
+            Response::Error(_) => ResponseOpcode::Error,
+            Response::Ready => ResponseOpcode::Ready,
+            Response::Result(_) => ResponseOpcode::Result,
+            Response::Authenticate => ResponseOpcode::Authenticate,
+            Response::Supported(_) => ResponseOpcode::Supported,
+            Response::Event(_) => ResponseOpcode::Event,
+        }
+    }
+
+    /// Serializes the response body, e.g. when acting as a server
+    pub fn serialize(
+        &self,
+        version: ProtocolVersion,
+        buf: &mut impl BufMut,
+    ) -> Result<(), ParseError> {
+        match self {
+            Response::Error(error) => error.serialize(buf)?,
+            Response::Ready => {}
+            Response::Result(result) => result::serialize(version, result, buf)?,
+            Response::Authenticate => {
+                return Err(ParseError::BadData(
+                    "Serializing AUTHENTICATE is not supported".to_string(),
+                ))
+            }
+            Response::Supported(supported) => supported.serialize(buf)?,
+            Response::Event(event) => event.serialize(buf)?,
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self, version: ProtocolVersion) -> Result<bytes::Bytes, ParseError> {
+        let mut v = Vec::new();
+        self.serialize(version, &mut v)?;
+        Ok(v.into())
+    }
 }
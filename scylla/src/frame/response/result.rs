@@ -1,7 +1,8 @@
 use crate::cql_to_rust::{FromRow, FromRowError};
-use crate::frame::{frame_errors::ParseError, types};
+use crate::frame::{frame_errors::ParseError, types, ProtocolVersion};
+use crate::transport::protocol_features::ProtocolFeatures;
 use byteorder::{BigEndian, ReadBytesExt};
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes};
 use std::{
     collections::BTreeMap,
     convert::{TryFrom, TryInto},
@@ -9,37 +10,91 @@ use std::{
     result::Result as StdResult,
     str,
 };
+use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SetKeyspace {
-    // TODO
+    pub keyspace_name: String,
 }
 
 #[derive(Debug)]
 pub struct Prepared {
     pub id: Bytes,
+    /// Present since protocol v5 or with Scylla's metadata id extension, has to be sent back on EXECUTE
+    pub result_metadata_id: Option<Bytes>,
     pub prepared_metadata: PreparedMetadata,
-    result_metadata: ResultMetadata,
+    pub result_metadata: ResultMetadata,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SchemaChange {
-    // TODO
+    pub change_type: SchemaChangeType,
+    pub target: SchemaChangeTarget,
 }
 
-#[derive(Clone, Debug)]
-struct TableSpec {
-    ks_name: String,
-    table_name: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaChangeType {
+    Created,
+    Updated,
+    Dropped,
 }
 
-#[derive(Debug, Clone)]
-enum ColumnType {
+/// Schema element affected by a schema change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaChangeTarget {
+    Keyspace {
+        keyspace_name: String,
+    },
+    Table {
+        keyspace_name: String,
+        table_name: String,
+    },
+    Type {
+        keyspace_name: String,
+        type_name: String,
+    },
+    Function {
+        keyspace_name: String,
+        function_name: String,
+        arguments: Vec<String>,
+    },
+    Aggregate {
+        keyspace_name: String,
+        aggregate_name: String,
+        arguments: Vec<String>,
+    },
+}
+
+impl SchemaChangeTarget {
+    /// Keyspace of the changed schema element
+    pub fn keyspace_name(&self) -> &str {
+        match self {
+            SchemaChangeTarget::Keyspace { keyspace_name }
+            | SchemaChangeTarget::Table { keyspace_name, .. }
+            | SchemaChangeTarget::Type { keyspace_name, .. }
+            | SchemaChangeTarget::Function { keyspace_name, .. }
+            | SchemaChangeTarget::Aggregate { keyspace_name, .. } => keyspace_name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableSpec {
+    pub ks_name: String,
+    pub table_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnType {
     Ascii,
+    Boolean,
     Int,
     BigInt,
     Text,
     Inet,
+    Uuid,
+    Timeuuid,
+    Timestamp,
     List(Box<ColumnType>),
     Map(Box<ColumnType>, Box<ColumnType>),
     Set(Box<ColumnType>),
@@ -51,13 +106,18 @@ enum ColumnType {
     Tuple(Vec<ColumnType>),
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CQLValue {
     Ascii(String),
+    Boolean(bool),
     Int(i32),
     BigInt(i64),
     Text(String),
     Inet(IpAddr),
+    Uuid(Uuid),
+    Timeuuid(Uuid),
+    /// Milliseconds since unix epoch
+    Timestamp(i64),
     List(Vec<CQLValue>),
     Map(Vec<(CQLValue, CQLValue)>),
     Set(Vec<CQLValue>),
@@ -77,6 +137,13 @@ impl CQLValue {
         }
     }
 
+    pub fn as_boolean(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
     pub fn as_int(&self) -> Option<i32> {
         match self {
             Self::Int(i) => Some(*i),
@@ -113,6 +180,21 @@ impl CQLValue {
         }
     }
 
+    pub fn as_uuid(&self) -> Option<Uuid> {
+        match self {
+            Self::Uuid(u) => Some(*u),
+            Self::Timeuuid(u) => Some(*u),
+            _ => None,
+        }
+    }
+
+    pub fn as_timestamp(&self) -> Option<i64> {
+        match self {
+            Self::Timestamp(t) => Some(*t),
+            _ => None,
+        }
+    }
+
     pub fn as_list(&self) -> Option<&Vec<CQLValue>> {
         match self {
             Self::List(s) => Some(&s),
@@ -145,28 +227,38 @@ impl CQLValue {
     // TODO
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ColumnSpec {
-    table_spec: TableSpec,
-    name: String,
-    typ: ColumnType,
+    pub table_spec: TableSpec,
+    pub name: String,
+    pub typ: ColumnType,
 }
 
-#[derive(Debug, Default)]
+impl ColumnSpec {
+    /// Name of the column
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct ResultMetadata {
-    col_count: usize,
+    pub col_count: usize,
     pub paging_state: Option<Bytes>,
-    col_specs: Vec<ColumnSpec>,
+    /// Sent by the server (since v5) when the result metadata of a prepared statement changed
+    pub new_metadata_id: Option<Bytes>,
+    pub col_specs: Vec<ColumnSpec>,
 }
 
 #[derive(Debug, Clone)]
 pub struct PreparedMetadata {
+    pub flags: i32,
     pub col_count: usize,
     pub pk_indexes: Vec<u16>,
     pub col_specs: Vec<ColumnSpec>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Row {
     pub columns: Vec<Option<CQLValue>>,
 }
@@ -178,13 +270,23 @@ impl Row {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Rows {
     pub metadata: ResultMetadata,
     rows_count: usize,
     pub rows: Vec<Row>,
 }
 
+impl Rows {
+    pub fn new(metadata: ResultMetadata, rows: Vec<Row>) -> Self {
+        Rows {
+            metadata,
+            rows_count: rows.len(),
+            rows,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Result {
     Void,
@@ -209,8 +311,12 @@ fn deser_type(buf: &mut &[u8]) -> StdResult<ColumnType, ParseError> {
     Ok(match id {
         0x0001 => Ascii,
         0x0002 => BigInt,
+        0x0004 => Boolean,
         0x0009 => Int,
+        0x000B => Timestamp,
+        0x000C => ColumnType::Uuid,
         0x000D => Text,
+        0x000F => Timeuuid,
         0x0010 => Inet,
         0x0020 => List(Box::new(deser_type(buf)?)),
         0x0021 => Map(Box::new(deser_type(buf)?), Box::new(deser_type(buf)?)),
@@ -273,11 +379,16 @@ fn deser_col_specs(
     Ok(col_specs)
 }
 
-fn deser_result_metadata(buf: &mut &[u8]) -> StdResult<ResultMetadata, ParseError> {
+// `metadata_ids` - whether result metadata ids are used, see [ProtocolFeatures::uses_metadata_ids]
+fn deser_result_metadata(
+    metadata_ids: bool,
+    buf: &mut &[u8],
+) -> StdResult<ResultMetadata, ParseError> {
     let flags = types::read_int(buf)?;
     let global_tables_spec = flags & 0x0001 != 0;
     let has_more_pages = flags & 0x0002 != 0;
     let no_metadata = flags & 0x0004 != 0;
+    let metadata_changed = metadata_ids && flags & 0x0008 != 0;
 
     let col_count: usize = types::read_int(buf)?.try_into()?;
 
@@ -287,10 +398,17 @@ fn deser_result_metadata(buf: &mut &[u8]) -> StdResult<ResultMetadata, ParseErro
         None
     };
 
+    let new_metadata_id = if metadata_changed {
+        Some(types::read_short_bytes(buf)?.to_owned().into())
+    } else {
+        None
+    };
+
     if no_metadata {
         return Ok(ResultMetadata {
             col_count,
             paging_state,
+            new_metadata_id,
             col_specs: vec![],
         });
     }
@@ -306,21 +424,28 @@ fn deser_result_metadata(buf: &mut &[u8]) -> StdResult<ResultMetadata, ParseErro
     Ok(ResultMetadata {
         col_count,
         paging_state,
+        new_metadata_id,
         col_specs,
     })
 }
 
-fn deser_prepared_metadata(buf: &mut &[u8]) -> StdResult<PreparedMetadata, ParseError> {
+fn deser_prepared_metadata(
+    version: ProtocolVersion,
+    buf: &mut &[u8],
+) -> StdResult<PreparedMetadata, ParseError> {
     let flags = types::read_int(buf)?;
     let global_tables_spec = flags & 0x0001 != 0;
 
     let col_count = types::read_int_length(buf)? as usize;
 
-    let pk_count: usize = types::read_int(buf)?.try_into()?;
-
-    let mut pk_indexes = Vec::with_capacity(pk_count);
-    for _ in 0..pk_count {
-        pk_indexes.push(types::read_short(buf)? as u16);
+    // Partition key indexes were added in protocol v4
+    let mut pk_indexes = Vec::new();
+    if version >= ProtocolVersion::V4 {
+        let pk_count: usize = types::read_int(buf)?.try_into()?;
+        pk_indexes.reserve(pk_count);
+        for _ in 0..pk_count {
+            pk_indexes.push(types::read_short(buf)? as u16);
+        }
     }
 
     let global_table_spec = if global_tables_spec {
@@ -332,6 +457,7 @@ fn deser_prepared_metadata(buf: &mut &[u8]) -> StdResult<PreparedMetadata, Parse
     let col_specs = deser_col_specs(buf, &global_table_spec, col_count)?;
 
     Ok(PreparedMetadata {
+        flags,
         col_count,
         pk_indexes,
         col_specs,
@@ -347,6 +473,15 @@ fn deser_cql_value(typ: &ColumnType, buf: &mut &[u8]) -> StdResult<CQLValue, Par
             }
             CQLValue::Ascii(str::from_utf8(buf)?.to_owned())
         }
+        Boolean => {
+            if buf.len() != 1 {
+                return Err(ParseError::BadData(format!(
+                    "Buffer length should be 1 not {}",
+                    buf.len()
+                )));
+            }
+            CQLValue::Boolean(buf[0] != 0x00)
+        }
         Int => {
             if buf.len() != 4 {
                 return Err(ParseError::BadData(format!(
@@ -366,6 +501,28 @@ fn deser_cql_value(typ: &ColumnType, buf: &mut &[u8]) -> StdResult<CQLValue, Par
             CQLValue::BigInt(buf.read_i64::<BigEndian>()?)
         }
         Text => CQLValue::Text(str::from_utf8(buf)?.to_owned()),
+        Timestamp => {
+            if buf.len() != 8 {
+                return Err(ParseError::BadData(format!(
+                    "Buffer length should be 8 not {}",
+                    buf.len()
+                )));
+            }
+            CQLValue::Timestamp(buf.read_i64::<BigEndian>()?)
+        }
+        ColumnType::Uuid | Timeuuid => {
+            if buf.len() != 16 {
+                return Err(ParseError::BadData(format!(
+                    "Buffer length should be 16 not {}",
+                    buf.len()
+                )));
+            }
+            let uuid = types::read_uuid(buf)?;
+            match typ {
+                ColumnType::Uuid => CQLValue::Uuid(uuid),
+                _ => CQLValue::Timeuuid(uuid),
+            }
+        }
         Inet => CQLValue::Inet(match buf.len() {
             4 => {
                 let ret = IpAddr::from(<[u8; 4]>::try_from(&buf[0..4])?);
@@ -448,14 +605,22 @@ fn deser_cql_value(typ: &ColumnType, buf: &mut &[u8]) -> StdResult<CQLValue, Par
     })
 }
 
-fn deser_rows(buf: &mut &[u8]) -> StdResult<Rows, ParseError> {
-    let metadata = deser_result_metadata(buf)?;
-
-    // TODO: the protocol allows an optimization (which must be explicitly requested on query by
-    // the driver) where the column metadata is not sent with the result.
-    // Implement this optimization. We'll then need to take the column types by a parameter.
-    // Beware of races; our column types may be outdated.
-    assert!(metadata.col_count == metadata.col_specs.len());
+fn deser_rows(
+    metadata_ids: bool,
+    buf: &mut &[u8],
+    cached_metadata: Option<&ResultMetadata>,
+) -> StdResult<Rows, ParseError> {
+    let mut metadata = deser_result_metadata(metadata_ids, buf)?;
+
+    // Metadata is skipped if the driver requested it, column types are taken from the cache then
+    if metadata.col_specs.len() != metadata.col_count {
+        match cached_metadata {
+            Some(cached) if cached.col_count == metadata.col_count => {
+                metadata.col_specs = cached.col_specs.clone();
+            }
+            _ => return Err(ParseError::NoCachedResultMetadata),
+        }
+    }
 
     let rows_count: usize = types::read_int(buf)?.try_into()?;
 
@@ -479,34 +644,101 @@ fn deser_rows(buf: &mut &[u8]) -> StdResult<Rows, ParseError> {
     })
 }
 
-fn deser_set_keyspace(_buf: &mut &[u8]) -> StdResult<SetKeyspace, ParseError> {
-    Ok(SetKeyspace {}) // TODO
+fn deser_set_keyspace(buf: &mut &[u8]) -> StdResult<SetKeyspace, ParseError> {
+    let keyspace_name = types::read_string(buf)?.to_string();
+
+    Ok(SetKeyspace { keyspace_name })
 }
 
-fn deser_prepared(buf: &mut &[u8]) -> StdResult<Prepared, ParseError> {
+fn deser_prepared(
+    version: ProtocolVersion,
+    metadata_ids: bool,
+    buf: &mut &[u8],
+) -> StdResult<Prepared, ParseError> {
     let id_len = types::read_short(buf)? as usize;
     let id: Bytes = buf[0..id_len].to_owned().into();
     buf.advance(id_len);
-    let prepared_metadata = deser_prepared_metadata(buf)?;
-    let result_metadata = deser_result_metadata(buf)?;
+    let result_metadata_id = if metadata_ids {
+        Some(types::read_short_bytes(buf)?.to_owned().into())
+    } else {
+        None
+    };
+    let prepared_metadata = deser_prepared_metadata(version, buf)?;
+    let result_metadata = deser_result_metadata(metadata_ids, buf)?;
     Ok(Prepared {
         id,
+        result_metadata_id,
         prepared_metadata,
         result_metadata,
     })
 }
 
-fn deser_schema_change(_buf: &mut &[u8]) -> StdResult<SchemaChange, ParseError> {
-    Ok(SchemaChange {}) // TODO
+pub(crate) fn deser_schema_change(buf: &mut &[u8]) -> StdResult<SchemaChange, ParseError> {
+    let change_type = match types::read_string(buf)? {
+        "CREATED" => SchemaChangeType::Created,
+        "UPDATED" => SchemaChangeType::Updated,
+        "DROPPED" => SchemaChangeType::Dropped,
+        other => {
+            return Err(ParseError::BadData(format!(
+                "Unknown schema change type: {}",
+                other
+            )))
+        }
+    };
+
+    let target_str = types::read_string(buf)?;
+    let keyspace_name = types::read_string(buf)?.to_string();
+
+    let target = match target_str {
+        "KEYSPACE" => SchemaChangeTarget::Keyspace { keyspace_name },
+        "TABLE" => SchemaChangeTarget::Table {
+            keyspace_name,
+            table_name: types::read_string(buf)?.to_string(),
+        },
+        "TYPE" => SchemaChangeTarget::Type {
+            keyspace_name,
+            type_name: types::read_string(buf)?.to_string(),
+        },
+        "FUNCTION" => SchemaChangeTarget::Function {
+            keyspace_name,
+            function_name: types::read_string(buf)?.to_string(),
+            arguments: types::read_string_list(buf)?,
+        },
+        "AGGREGATE" => SchemaChangeTarget::Aggregate {
+            keyspace_name,
+            aggregate_name: types::read_string(buf)?.to_string(),
+            arguments: types::read_string_list(buf)?,
+        },
+        other => {
+            return Err(ParseError::BadData(format!(
+                "Unknown schema change target: {}",
+                other
+            )))
+        }
+    };
+
+    Ok(SchemaChange {
+        change_type,
+        target,
+    })
 }
 
-pub fn deserialize(buf: &mut &[u8]) -> StdResult<Result, ParseError> {
+/// Deserializes a RESULT response.\
+/// `features` decide whether result metadata ids are sent in protocol v4.\
+/// `cached_metadata` is used to decode rows if the server skipped sending result metadata.
+pub fn deserialize(
+    version: ProtocolVersion,
+    features: &ProtocolFeatures,
+    buf: &mut &[u8],
+    cached_metadata: Option<&ResultMetadata>,
+) -> StdResult<Result, ParseError> {
     use self::Result::*;
+    let metadata_ids = features.uses_metadata_ids(version);
     Ok(match types::read_int(buf)? {
         0x0001 => Void,
-        0x0002 => Rows(deser_rows(buf)?),
+        0x0002 => Rows(deser_rows(metadata_ids, buf, cached_metadata)?),
         0x0003 => SetKeyspace(deser_set_keyspace(buf)?),
-        0x0004 => Prepared(deser_prepared(buf)?),
+        0x0004 => Prepared(deser_prepared(version, metadata_ids, buf)?),
         0x0005 => SchemaChange(deser_schema_change(buf)?),
         k => {
             return Err(ParseError::BadData(format!(
@@ -517,10 +749,678 @@ pub fn deserialize(buf: &mut &[u8]) -> StdResult<Result, ParseError> {
     })
 }
 
+fn ser_table_spec(table_spec: &TableSpec, buf: &mut impl BufMut) -> StdResult<(), ParseError> {
+    types::write_string(&table_spec.ks_name, buf)?;
+    types::write_string(&table_spec.table_name, buf)?;
+    Ok(())
+}
+
+fn ser_type(typ: &ColumnType, buf: &mut impl BufMut) -> StdResult<(), ParseError> {
+    use ColumnType::*;
+    let id: i16 = match typ {
+        Ascii => 0x0001,
+        BigInt => 0x0002,
+        Boolean => 0x0004,
+        Int => 0x0009,
+        Timestamp => 0x000B,
+        ColumnType::Uuid => 0x000C,
+        Text => 0x000D,
+        Timeuuid => 0x000F,
+        Inet => 0x0010,
+        List(_) => 0x0020,
+        Map(_, _) => 0x0021,
+        Set(_) => 0x0022,
+        UserDefinedType { .. } => 0x0030,
+        Tuple(_) => 0x0031,
+    };
+    types::write_short(id, buf);
+
+    match typ {
+        List(elem_type) | Set(elem_type) => ser_type(elem_type, buf)?,
+        Map(key_type, value_type) => {
+            ser_type(key_type, buf)?;
+            ser_type(value_type, buf)?;
+        }
+        UserDefinedType {
+            type_name,
+            keyspace,
+            field_types,
+        } => {
+            types::write_string(keyspace, buf)?;
+            types::write_string(type_name, buf)?;
+            types::write_short(field_types.len().try_into()?, buf);
+            for (field_name, field_type) in field_types {
+                types::write_string(field_name, buf)?;
+                ser_type(field_type, buf)?;
+            }
+        }
+        Tuple(elem_types) => {
+            types::write_short(elem_types.len().try_into()?, buf);
+            for elem_type in elem_types {
+                ser_type(elem_type, buf)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn ser_col_specs(col_specs: &[ColumnSpec], buf: &mut impl BufMut) -> StdResult<(), ParseError> {
+    // Table spec is written for each column, the global table spec is never used
+    for col_spec in col_specs {
+        ser_table_spec(&col_spec.table_spec, buf)?;
+        types::write_string(&col_spec.name, buf)?;
+        ser_type(&col_spec.typ, buf)?;
+    }
+    Ok(())
+}
+
+fn ser_result_metadata(
+    version: ProtocolVersion,
+    metadata: &ResultMetadata,
+    buf: &mut impl BufMut,
+) -> StdResult<(), ParseError> {
+    let no_metadata = metadata.col_specs.len() != metadata.col_count;
+    let metadata_changed = version >= ProtocolVersion::V5 && metadata.new_metadata_id.is_some();
+
+    let mut flags = 0;
+    if metadata.paging_state.is_some() {
+        flags |= 0x0002;
+    }
+    if no_metadata {
+        flags |= 0x0004;
+    }
+    if metadata_changed {
+        flags |= 0x0008;
+    }
+    types::write_int(flags, buf);
+    types::write_int(metadata.col_count.try_into()?, buf);
+
+    if let Some(paging_state) = &metadata.paging_state {
+        types::write_bytes(paging_state, buf)?;
+    }
+
+    if let (true, Some(new_metadata_id)) = (metadata_changed, &metadata.new_metadata_id) {
+        types::write_short_bytes(new_metadata_id, buf)?;
+    }
+
+    if !no_metadata {
+        ser_col_specs(&metadata.col_specs, buf)?;
+    }
+
+    Ok(())
+}
+
+fn ser_prepared_metadata(
+    version: ProtocolVersion,
+    metadata: &PreparedMetadata,
+    buf: &mut impl BufMut,
+) -> StdResult<(), ParseError> {
+    // Clear the global table spec flag, table spec is written for each column
+    types::write_int(metadata.flags & !0x0001, buf);
+    types::write_int(metadata.col_count.try_into()?, buf);
+
+    if version >= ProtocolVersion::V4 {
+        types::write_int(metadata.pk_indexes.len().try_into()?, buf);
+        for pk_index in &metadata.pk_indexes {
+            types::write_short(*pk_index as i16, buf);
+        }
+    }
+
+    ser_col_specs(&metadata.col_specs, buf)
+}
+
+// Writes value as [bytes]
+fn ser_cql_value_bytes(
+    typ: &ColumnType,
+    value: &CQLValue,
+    buf: &mut impl BufMut,
+) -> StdResult<(), ParseError> {
+    let mut value_buf = Vec::new();
+    ser_cql_value(typ, value, &mut value_buf)?;
+    types::write_bytes(&value_buf, buf)
+}
+
+fn ser_cql_value(
+    typ: &ColumnType,
+    value: &CQLValue,
+    buf: &mut Vec<u8>,
+) -> StdResult<(), ParseError> {
+    match (typ, value) {
+        (ColumnType::Ascii, CQLValue::Ascii(s)) | (ColumnType::Text, CQLValue::Text(s)) => {
+            buf.put_slice(s.as_bytes())
+        }
+        (ColumnType::Boolean, CQLValue::Boolean(b)) => buf.put_u8(*b as u8),
+        (ColumnType::Int, CQLValue::Int(i)) => buf.put_i32(*i),
+        (ColumnType::BigInt, CQLValue::BigInt(i))
+        | (ColumnType::Timestamp, CQLValue::Timestamp(i)) => buf.put_i64(*i),
+        (ColumnType::Uuid, CQLValue::Uuid(uuid))
+        | (ColumnType::Timeuuid, CQLValue::Timeuuid(uuid)) => types::write_uuid(uuid, buf),
+        (ColumnType::Inet, CQLValue::Inet(IpAddr::V4(addr))) => buf.put_slice(&addr.octets()),
+        (ColumnType::Inet, CQLValue::Inet(IpAddr::V6(addr))) => buf.put_slice(&addr.octets()),
+        (ColumnType::List(elem_type), CQLValue::List(elems))
+        | (ColumnType::Set(elem_type), CQLValue::Set(elems)) => {
+            types::write_int(elems.len().try_into()?, buf);
+            for elem in elems {
+                ser_cql_value_bytes(elem_type, elem, buf)?;
+            }
+        }
+        (ColumnType::Map(key_type, value_type), CQLValue::Map(entries)) => {
+            types::write_int(entries.len().try_into()?, buf);
+            for (key, value) in entries {
+                ser_cql_value_bytes(key_type, key, buf)?;
+                ser_cql_value_bytes(value_type, value, buf)?;
+            }
+        }
+        (
+            ColumnType::UserDefinedType { field_types, .. },
+            CQLValue::UserDefinedType { fields, .. },
+        ) => {
+            // Fields are written in the order defined by the type
+            for (field_name, field_type) in field_types {
+                match fields.get(field_name) {
+                    Some(Some(field_value)) => ser_cql_value_bytes(field_type, field_value, buf)?,
+                    _ => types::write_int(-1, buf),
+                }
+            }
+        }
+        (ColumnType::Tuple(elem_types), CQLValue::Tuple(elems))
+            if elem_types.len() == elems.len() =>
+        {
+            for (elem_type, elem) in elem_types.iter().zip(elems) {
+                ser_cql_value_bytes(elem_type, elem, buf)?;
+            }
+        }
+        (typ, value) => {
+            return Err(ParseError::BadData(format!(
+                "Value {:?} doesn't match column type {:?}",
+                value, typ
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+fn ser_rows(
+    version: ProtocolVersion,
+    rows: &Rows,
+    buf: &mut impl BufMut,
+) -> StdResult<(), ParseError> {
+    let metadata = &rows.metadata;
+    ser_result_metadata(version, metadata, buf)?;
+
+    types::write_int(rows.rows.len().try_into()?, buf);
+    for row in &rows.rows {
+        if row.columns.len() != metadata.col_count || metadata.col_specs.len() != metadata.col_count
+        {
+            return Err(ParseError::BadData(
+                "Number of values in a row doesn't match result metadata".to_string(),
+            ));
+        }
+        for (col_spec, value) in metadata.col_specs.iter().zip(&row.columns) {
+            match value {
+                Some(value) => ser_cql_value_bytes(&col_spec.typ, value, buf)?,
+                None => types::write_int(-1, buf),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn ser_prepared(
+    version: ProtocolVersion,
+    prepared: &Prepared,
+    buf: &mut impl BufMut,
+) -> StdResult<(), ParseError> {
+    types::write_short_bytes(&prepared.id, buf)?;
+    if version >= ProtocolVersion::V5 {
+        let result_metadata_id: &[u8] = prepared.result_metadata_id.as_deref().unwrap_or(&[]);
+        types::write_short_bytes(result_metadata_id, buf)?;
+    }
+    ser_prepared_metadata(version, &prepared.prepared_metadata, buf)?;
+    ser_result_metadata(version, &prepared.result_metadata, buf)
+}
+
+pub(crate) fn ser_schema_change(
+    schema_change: &SchemaChange,
+    buf: &mut impl BufMut,
+) -> StdResult<(), ParseError> {
+    let change_type = match schema_change.change_type {
+        SchemaChangeType::Created => "CREATED",
+        SchemaChangeType::Updated => "UPDATED",
+        SchemaChangeType::Dropped => "DROPPED",
+    };
+    types::write_string(change_type, buf)?;
+
+    match &schema_change.target {
+        SchemaChangeTarget::Keyspace { keyspace_name } => {
+            types::write_string("KEYSPACE", buf)?;
+            types::write_string(keyspace_name, buf)?;
+        }
+        SchemaChangeTarget::Table {
+            keyspace_name,
+            table_name,
+        } => {
+            types::write_string("TABLE", buf)?;
+            types::write_string(keyspace_name, buf)?;
+            types::write_string(table_name, buf)?;
+        }
+        SchemaChangeTarget::Type {
+            keyspace_name,
+            type_name,
+        } => {
+            types::write_string("TYPE", buf)?;
+            types::write_string(keyspace_name, buf)?;
+            types::write_string(type_name, buf)?;
+        }
+        SchemaChangeTarget::Function {
+            keyspace_name,
+            function_name,
+            arguments,
+        } => {
+            types::write_string("FUNCTION", buf)?;
+            types::write_string(keyspace_name, buf)?;
+            types::write_string(function_name, buf)?;
+            types::write_string_list(arguments, buf)?;
+        }
+        SchemaChangeTarget::Aggregate {
+            keyspace_name,
+            aggregate_name,
+            arguments,
+        } => {
+            types::write_string("AGGREGATE", buf)?;
+            types::write_string(keyspace_name, buf)?;
+            types::write_string(aggregate_name, buf)?;
+            types::write_string_list(arguments, buf)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Serializes a RESULT response, e.g. when acting as a server
+pub fn serialize(
+    version: ProtocolVersion,
+    result: &Result,
+    buf: &mut impl BufMut,
+) -> StdResult<(), ParseError> {
+    match result {
+        Result::Void => types::write_int(0x0001, buf),
+        Result::Rows(rows) => {
+            types::write_int(0x0002, buf);
+            ser_rows(version, rows, buf)?;
+        }
+        Result::SetKeyspace(set_keyspace) => {
+            types::write_int(0x0003, buf);
+            types::write_string(&set_keyspace.keyspace_name, buf)?;
+        }
+        Result::Prepared(prepared) => {
+            types::write_int(0x0004, buf);
+            ser_prepared(version, prepared, buf)?;
+        }
+        Result::SchemaChange(schema_change) => {
+            types::write_int(0x0005, buf);
+            ser_schema_change(schema_change, buf)?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate as scylla;
+    use scylla::frame::frame_errors::ParseError;
     use scylla::frame::response::result::CQLValue;
+    use scylla::frame::types;
+    use scylla::frame::ProtocolVersion;
+    use scylla::transport::protocol_features::ProtocolFeatures;
+
+    #[test]
+    fn test_set_keyspace_deserialization() {
+        let mut buf = Vec::new();
+        types::write_int(0x0003, &mut buf);
+        types::write_string("ks", &mut buf).unwrap();
+
+        let result = super::deserialize(
+            Default::default(),
+            &ProtocolFeatures::default(),
+            &mut &buf[..],
+            None,
+        )
+        .unwrap();
+        match result {
+            super::Result::SetKeyspace(set_keyspace) => {
+                assert_eq!(set_keyspace.keyspace_name, "ks")
+            }
+            _ => panic!("Expected SetKeyspace, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_rows_with_skipped_metadata() {
+        use super::{ColumnSpec, ColumnType, ResultMetadata, TableSpec};
+
+        let mut buf = Vec::new();
+        types::write_int(0x0002, &mut buf); // Rows
+        types::write_int(0x0004, &mut buf); // No metadata flag
+        types::write_int(1, &mut buf); // Column count
+        types::write_int(1, &mut buf); // Rows count
+        types::write_bytes(&7_i32.to_be_bytes(), &mut buf).unwrap();
+
+        let no_cache = super::deserialize(
+            Default::default(),
+            &ProtocolFeatures::default(),
+            &mut &buf[..],
+            None,
+        );
+        assert!(matches!(no_cache, Err(ParseError::NoCachedResultMetadata)));
+
+        let cached = ResultMetadata {
+            col_count: 1,
+            paging_state: None,
+            new_metadata_id: None,
+            col_specs: vec![ColumnSpec {
+                table_spec: TableSpec {
+                    ks_name: "ks".to_string(),
+                    table_name: "t".to_string(),
+                },
+                name: "a".to_string(),
+                typ: ColumnType::Int,
+            }],
+        };
+
+        let result = super::deserialize(
+            Default::default(),
+            &ProtocolFeatures::default(),
+            &mut &buf[..],
+            Some(&cached),
+        )
+        .unwrap();
+        match result {
+            super::Result::Rows(rows) => {
+                assert_eq!(rows.rows[0].columns, vec![Some(CQLValue::Int(7))]);
+                assert_eq!(rows.metadata.col_specs[0].name(), "a");
+            }
+            _ => panic!("Expected Rows, got {:?}", result),
+        }
+
+        // Cached metadata of a different statement, e.g. before the table was altered
+        let outdated = ResultMetadata {
+            col_count: 2,
+            col_specs: vec![cached.col_specs[0].clone(), cached.col_specs[0].clone()],
+            ..cached.clone()
+        };
+        let mismatch = super::deserialize(
+            Default::default(),
+            &ProtocolFeatures::default(),
+            &mut &buf[..],
+            Some(&outdated),
+        );
+        assert!(matches!(mismatch, Err(ParseError::NoCachedResultMetadata)));
+    }
+
+    #[test]
+    fn test_schema_change_deserialization() {
+        use super::{SchemaChangeTarget, SchemaChangeType};
+
+        let mut buf = Vec::new();
+        types::write_int(0x0005, &mut buf);
+        types::write_string("CREATED", &mut buf).unwrap();
+        types::write_string("FUNCTION", &mut buf).unwrap();
+        types::write_string("ks", &mut buf).unwrap();
+        types::write_string("func", &mut buf).unwrap();
+        types::write_string_list(&["int".to_string(), "text".to_string()], &mut buf).unwrap();
+
+        let result = super::deserialize(
+            Default::default(),
+            &ProtocolFeatures::default(),
+            &mut &buf[..],
+            None,
+        )
+        .unwrap();
+        match result {
+            super::Result::SchemaChange(schema_change) => {
+                assert_eq!(schema_change.change_type, SchemaChangeType::Created);
+                assert_eq!(
+                    schema_change.target,
+                    SchemaChangeTarget::Function {
+                        keyspace_name: "ks".to_string(),
+                        function_name: "func".to_string(),
+                        arguments: vec!["int".to_string(), "text".to_string()],
+                    }
+                );
+                assert_eq!(schema_change.target.keyspace_name(), "ks");
+            }
+            _ => panic!("Expected SchemaChange, got {:?}", result),
+        }
+    }
+
+    fn col_spec(name: &str, typ: super::ColumnType) -> super::ColumnSpec {
+        super::ColumnSpec {
+            table_spec: super::TableSpec {
+                ks_name: "ks".to_string(),
+                table_name: "t".to_string(),
+            },
+            name: name.to_string(),
+            typ,
+        }
+    }
+
+    #[test]
+    fn test_rows_serialization_roundtrip() {
+        use super::{ColumnType, ResultMetadata, Row, Rows};
+        use bytes::Bytes;
+        use std::collections::BTreeMap;
+
+        let col_specs = vec![
+            col_spec("a", ColumnType::Int),
+            col_spec("b", ColumnType::Text),
+            col_spec(
+                "c",
+                ColumnType::Map(Box::new(ColumnType::Ascii), Box::new(ColumnType::BigInt)),
+            ),
+            col_spec("d", ColumnType::Inet),
+            col_spec(
+                "e",
+                ColumnType::UserDefinedType {
+                    type_name: "udt".to_string(),
+                    keyspace: "ks".to_string(),
+                    field_types: vec![
+                        ("x".to_string(), ColumnType::Boolean),
+                        ("y".to_string(), ColumnType::Set(Box::new(ColumnType::Int))),
+                    ],
+                },
+            ),
+        ];
+
+        let mut fields = BTreeMap::new();
+        fields.insert("x".to_string(), Some(CQLValue::Boolean(true)));
+        fields.insert("y".to_string(), None);
+        let row = Row {
+            columns: vec![
+                Some(CQLValue::Int(1)),
+                None,
+                Some(CQLValue::Map(vec![(
+                    CQLValue::Ascii("key".to_string()),
+                    CQLValue::BigInt(2),
+                )])),
+                Some(CQLValue::Inet("::1".parse().unwrap())),
+                Some(CQLValue::UserDefinedType {
+                    keyspace: "ks".to_string(),
+                    type_name: "udt".to_string(),
+                    fields,
+                }),
+            ],
+        };
+
+        let metadata = ResultMetadata {
+            col_count: col_specs.len(),
+            paging_state: Some(Bytes::from_static(b"next page")),
+            new_metadata_id: Some(Bytes::from_static(b"new id")),
+            col_specs,
+        };
+        let rows = super::Result::Rows(Rows::new(metadata, vec![row]));
+
+        for version in &[ProtocolVersion::V4, ProtocolVersion::V5] {
+            let mut buf = Vec::new();
+            super::serialize(*version, &rows, &mut buf).unwrap();
+            let parsed = match super::deserialize(
+                *version,
+                &ProtocolFeatures::default(),
+                &mut &buf[..],
+                None,
+            )
+            .unwrap()
+            {
+                super::Result::Rows(parsed) => parsed,
+                other => panic!("Expected Rows, got {:?}", other),
+            };
+            let expected = match &rows {
+                super::Result::Rows(expected) => expected,
+                _ => unreachable!(),
+            };
+
+            assert_eq!(parsed.rows[0].columns, expected.rows[0].columns);
+            assert_eq!(parsed.metadata.col_specs, expected.metadata.col_specs);
+            assert_eq!(parsed.metadata.paging_state, expected.metadata.paging_state);
+            // New metadata id is sent only since v5
+            assert_eq!(
+                parsed.metadata.new_metadata_id.is_some(),
+                *version >= ProtocolVersion::V5
+            );
+        }
+    }
+
+    #[test]
+    fn test_rows_serialization_type_mismatch() {
+        use super::{ColumnType, ResultMetadata, Row, Rows};
+
+        let metadata = ResultMetadata {
+            col_count: 1,
+            col_specs: vec![col_spec("a", ColumnType::Int)],
+            ..Default::default()
+        };
+        let row = Row {
+            columns: vec![Some(CQLValue::Text("not an int".to_string()))],
+        };
+        let rows = super::Result::Rows(Rows::new(metadata, vec![row]));
+
+        assert!(super::serialize(ProtocolVersion::V4, &rows, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_prepared_serialization_roundtrip() {
+        use super::{ColumnType, Prepared, PreparedMetadata, ResultMetadata};
+        use bytes::Bytes;
+
+        let prepared = super::Result::Prepared(Prepared {
+            id: Bytes::from_static(b"id"),
+            result_metadata_id: Some(Bytes::from_static(b"metadata id")),
+            prepared_metadata: PreparedMetadata {
+                flags: 0,
+                col_count: 2,
+                pk_indexes: vec![1],
+                col_specs: vec![
+                    col_spec("a", ColumnType::Uuid),
+                    col_spec("b", ColumnType::List(Box::new(ColumnType::Timestamp))),
+                ],
+            },
+            result_metadata: ResultMetadata::default(),
+        });
+
+        for version in &[
+            ProtocolVersion::V3,
+            ProtocolVersion::V4,
+            ProtocolVersion::V5,
+        ] {
+            let mut buf = Vec::new();
+            super::serialize(*version, &prepared, &mut buf).unwrap();
+            let mut parsed_buf = &buf[..];
+            let parsed = match super::deserialize(
+                *version,
+                &ProtocolFeatures::default(),
+                &mut parsed_buf,
+                None,
+            )
+            .unwrap()
+            {
+                super::Result::Prepared(parsed) => parsed,
+                other => panic!("Expected Prepared, got {:?}", other),
+            };
+            assert!(parsed_buf.is_empty());
+
+            assert_eq!(parsed.id, Bytes::from_static(b"id"));
+            assert_eq!(parsed.prepared_metadata.col_specs[1].name(), "b");
+            // Partition key indexes are sent since v4
+            let expected_pk_indexes: &[u16] = if *version >= ProtocolVersion::V4 {
+                &[1]
+            } else {
+                &[]
+            };
+            assert_eq!(parsed.prepared_metadata.pk_indexes, expected_pk_indexes);
+        }
+    }
+
+    #[test]
+    fn test_metadata_ids_with_scylla_extension() {
+        use super::{ColumnType, Prepared, PreparedMetadata, ResultMetadata, Rows};
+        use bytes::Bytes;
+
+        // With the extension Scylla sends metadata ids in v4 the same way as in v5
+        let features = ProtocolFeatures {
+            use_metadata_id: true,
+            ..Default::default()
+        };
+
+        let prepared = super::Result::Prepared(Prepared {
+            id: Bytes::from_static(b"id"),
+            result_metadata_id: Some(Bytes::from_static(b"metadata id")),
+            prepared_metadata: PreparedMetadata {
+                flags: 0,
+                col_count: 0,
+                pk_indexes: vec![],
+                col_specs: vec![],
+            },
+            result_metadata: ResultMetadata::default(),
+        });
+        let mut buf = Vec::new();
+        super::serialize(ProtocolVersion::V5, &prepared, &mut buf).unwrap();
+        let mut parsed_buf = &buf[..];
+        match super::deserialize(ProtocolVersion::V4, &features, &mut parsed_buf, None).unwrap() {
+            super::Result::Prepared(parsed) => assert_eq!(
+                parsed.result_metadata_id,
+                Some(Bytes::from_static(b"metadata id"))
+            ),
+            other => panic!("Expected Prepared, got {:?}", other),
+        }
+        assert!(parsed_buf.is_empty());
+
+        let rows = super::Result::Rows(Rows {
+            metadata: ResultMetadata {
+                col_count: 1,
+                paging_state: None,
+                new_metadata_id: Some(Bytes::from_static(b"new id")),
+                col_specs: vec![col_spec("a", ColumnType::Int)],
+            },
+            rows_count: 0,
+            rows: vec![],
+        });
+        let mut buf = Vec::new();
+        super::serialize(ProtocolVersion::V5, &rows, &mut buf).unwrap();
+        let mut parsed_buf = &buf[..];
+        match super::deserialize(ProtocolVersion::V4, &features, &mut parsed_buf, None).unwrap() {
+            super::Result::Rows(parsed) => assert_eq!(
+                parsed.metadata.new_metadata_id,
+                Some(Bytes::from_static(b"new id"))
+            ),
+            other => panic!("Expected Rows, got {:?}", other),
+        }
+        assert!(parsed_buf.is_empty());
+    }
 
     #[test]
     fn test_list_from_cql() {
@@ -1,5 +1,6 @@
 use crate::frame::frame_errors::ParseError;
 use crate::frame::types;
+use bytes::BufMut;
 use std::collections::HashMap;
 
 #[derive(Debug)]
@@ -13,4 +14,8 @@ impl Supported {
 
         Ok(Supported { options })
     }
+
+    pub fn serialize(&self, buf: &mut impl BufMut) -> Result<(), ParseError> {
+        types::write_string_multimap(&self.options, buf)
+    }
 }
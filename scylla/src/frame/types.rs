@@ -7,6 +7,7 @@ use num_enum::TryFromPrimitive;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::net::{IpAddr, SocketAddr};
 use std::str;
 use uuid::Uuid;
 
@@ -63,6 +64,11 @@ fn read_raw_bytes<'a>(count: usize, buf: &mut &'a [u8]) -> Result<&'a [u8], Pars
     Ok(ret)
 }
 
+pub fn read_byte(buf: &mut &[u8]) -> Result<u8, ParseError> {
+    let v = buf.read_u8()?;
+    Ok(v)
+}
+
 pub fn read_int(buf: &mut &[u8]) -> Result<i32, ParseError> {
     let v = buf.read_i32::<BigEndian>()?;
     Ok(v)
@@ -170,6 +176,12 @@ pub fn write_bytes(v: &[u8], buf: &mut impl BufMut) -> Result<(), ParseError> {
     Ok(())
 }
 
+pub fn read_short_bytes<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], ParseError> {
+    let len = read_short_length(buf)?;
+    let v = read_raw_bytes(len, buf)?;
+    Ok(v)
+}
+
 pub fn write_short_bytes(v: &[u8], buf: &mut impl BufMut) -> Result<(), ParseError> {
     write_short_length(v.len(), buf)?;
     buf.put_slice(v);
@@ -383,6 +395,51 @@ fn type_uuid() {
     assert_eq!(u, u2);
 }
 
+// [inet] - an [inetaddr] (a length byte followed by the address bytes) and an [int] port
+pub fn read_inet(buf: &mut &[u8]) -> Result<SocketAddr, ParseError> {
+    let len = read_byte(buf)?;
+    let ip_addr = match len {
+        4 => IpAddr::from(<[u8; 4]>::try_from(read_raw_bytes(4, buf)?)?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(read_raw_bytes(16, buf)?)?),
+        v => {
+            return Err(ParseError::BadData(format!(
+                "Invalid inet bytes length: {}",
+                v
+            )))
+        }
+    };
+    let port = read_int(buf)?;
+
+    Ok(SocketAddr::new(ip_addr, port.try_into()?))
+}
+
+pub fn write_inet(addr: SocketAddr, buf: &mut impl BufMut) {
+    match addr.ip() {
+        IpAddr::V4(v4) => {
+            buf.put_u8(4);
+            buf.put_slice(&v4.octets());
+        }
+        IpAddr::V6(v6) => {
+            buf.put_u8(16);
+            buf.put_slice(&v6.octets());
+        }
+    }
+    write_int(addr.port() as i32, buf);
+}
+
+#[test]
+fn type_inet() {
+    let addrs: [SocketAddr; 2] = [
+        "127.0.0.1:9042".parse().unwrap(),
+        "[::1]:19042".parse().unwrap(),
+    ];
+    for addr in addrs.iter() {
+        let mut buf = Vec::new();
+        write_inet(*addr, &mut buf);
+        assert_eq!(read_inet(&mut &buf[..]).unwrap(), *addr);
+    }
+}
+
 pub fn read_consistency(buf: &mut &[u8]) -> Result<Consistency, ParseError> {
     let raw = read_short(buf)?;
     let parsed = Consistency::try_from(raw)
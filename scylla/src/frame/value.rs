@@ -1,8 +1,11 @@
+use crate::frame::frame_errors::ParseError;
+use crate::frame::types;
 use bytes::BufMut;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use thiserror::Error;
+use uuid::Uuid;
 
 /// Every value being sent in a query must implement this trait
 /// serialize() should write the Value as [bytes] to the provided buffer
@@ -91,6 +94,35 @@ impl SerializedValues {
         Ok(())
     }
 
+    /// Reads a list of values sent in a request, as written by [write_to_request](Self::write_to_request)
+    pub fn new_from_frame(buf: &mut &[u8]) -> Result<Self, ParseError> {
+        let values_num = types::read_short(buf)?;
+        let mut serialized_values = Vec::new();
+
+        for _ in 0..values_num {
+            // Negative length means null (-1) or unset (-2) value, which has no content
+            let len = types::read_int(buf)?;
+            types::write_int(len, &mut serialized_values);
+            if len > 0 {
+                let len = len as usize;
+                if buf.len() < len {
+                    return Err(ParseError::BadData(format!(
+                        "Not enough bytes for a value! expected: {} received: {}",
+                        len,
+                        buf.len(),
+                    )));
+                }
+                serialized_values.extend_from_slice(&buf[..len]);
+                *buf = &buf[len..];
+            }
+        }
+
+        Ok(SerializedValues {
+            serialized_values,
+            values_num,
+        })
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = Option<&[u8]>> {
         SerializedValuesIterator {
             serialized_values: &self.serialized_values,
@@ -171,6 +203,14 @@ impl Value for i16 {
     }
 }
 
+impl Value for bool {
+    fn serialize(&self, buf: &mut Vec<u8>) -> Result<(), ValueTooBig> {
+        buf.put_i32(1);
+        buf.put_u8(*self as u8);
+        Ok(())
+    }
+}
+
 impl Value for i32 {
     fn serialize(&self, buf: &mut Vec<u8>) -> Result<(), ValueTooBig> {
         buf.put_i32(4);
@@ -205,6 +245,14 @@ impl Value for String {
     }
 }
 
+impl Value for Uuid {
+    fn serialize(&self, buf: &mut Vec<u8>) -> Result<(), ValueTooBig> {
+        buf.put_i32(16);
+        buf.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+}
+
 /// Every Option<T> can be serialized as None -> NULL, Some(val) -> val.serialize()
 impl<T: Value> Value for Option<T> {
     fn serialize(&self, buf: &mut Vec<u8>) -> Result<(), ValueTooBig> {
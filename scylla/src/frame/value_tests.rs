@@ -5,6 +5,7 @@ use super::value::{
 use bytes::BufMut;
 use std::borrow::Cow;
 use std::convert::TryInto;
+use uuid::Uuid;
 
 fn serialized(val: impl Value) -> Vec<u8> {
     let mut result: Vec<u8> = Vec::new();
@@ -14,6 +15,7 @@ fn serialized(val: impl Value) -> Vec<u8> {
 
 #[test]
 fn basic_serialization() {
+    assert_eq!(serialized(true), vec![0, 0, 0, 1, 1]);
     assert_eq!(serialized(8_i8), vec![0, 0, 0, 1, 8]);
     assert_eq!(serialized(16_i16), vec![0, 0, 0, 2, 0, 16]);
     assert_eq!(serialized(32_i32), vec![0, 0, 0, 4, 0, 0, 0, 32]);
@@ -26,6 +28,14 @@ fn basic_serialization() {
     assert_eq!(serialized("abc".to_string()), vec![0, 0, 0, 3, 97, 98, 99]);
 }
 
+#[test]
+fn uuid_serialization() {
+    let uuid = Uuid::parse_str("8e14e760-7fa8-11eb-bc66-000000000001").unwrap();
+    let mut expected: Vec<u8> = vec![0, 0, 0, 16];
+    expected.extend_from_slice(uuid.as_bytes());
+    assert_eq!(serialized(uuid), expected);
+}
+
 #[test]
 fn option_value() {
     assert_eq!(serialized(Some(32_i32)), vec![0, 0, 0, 4, 0, 0, 0, 32]);
@@ -8,6 +8,9 @@ pub mod routing;
 pub mod statement;
 pub mod transport;
 
+#[cfg(any(test, feature = "test-utilities"))]
+pub mod test_utils;
+
 pub use macros::*;
 pub use statement::batch;
 pub use statement::prepared_statement;
@@ -15,5 +18,6 @@ pub use statement::query;
 
 pub use frame::response::cql_to_rust;
 
+pub use transport::connection::{BatchResult, LwtResult, QueryResult};
 pub use transport::session::{IntoTypedRows, Session, SessionConfig};
 pub use transport::session_builder::SessionBuilder;
@@ -71,6 +71,20 @@ impl ShardInfo {
             + shard as u16
     }
 
+    /// Iterates over all source ports `p` such that `shard == shard_of_source_port(p)`,
+    /// starting from a random one. Used to find a free port when the drawn one is taken.
+    pub fn iter_source_ports_for_shard(&self, shard: Shard) -> impl Iterator<Item = u16> {
+        let nr_shards = self.nr_shards as usize;
+        let first = self.draw_source_port_for_shard(shard);
+        // Lowest port in the range which belongs to the shard
+        let lowest =
+            (49152 + nr_shards - 1 - shard as usize) / nr_shards * nr_shards + shard as usize;
+
+        (first..=65535)
+            .step_by(nr_shards)
+            .chain((lowest as u16..first).step_by(nr_shards))
+    }
+
     pub fn get_nr_shards(&self) -> u16 {
         self.nr_shards
     }
@@ -126,6 +140,27 @@ fn test_shard_of() {
     );
 }
 
+#[test]
+fn test_iter_source_ports_for_shard() {
+    let shard_info = ShardInfo::new(0, 7, 12);
+
+    for shard in 0..7 {
+        let ports: Vec<u16> = shard_info.iter_source_ports_for_shard(shard).collect();
+        assert!(ports.iter().all(|port| *port >= 49152));
+        assert!(ports
+            .iter()
+            .all(|port| shard_info.shard_of_source_port(*port) == shard));
+
+        // Every port of the shard is visited exactly once
+        let mut sorted = ports.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(sorted.len(), ports.len());
+        let expected = (49152..=65535u32).filter(|p| p % 7 == shard).count();
+        assert_eq!(ports.len(), expected);
+    }
+}
+
 // An implementation of MurmurHash3 ported from Scylla. Please note that this
 // is not a "correct" implementation of MurmurHash3 - it replicates the same
 // bugs made in the original Cassandra implementation in order to be compatible.
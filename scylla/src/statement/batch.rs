@@ -1,4 +1,6 @@
 use crate::statement::{prepared_statement::PreparedStatement, query::Query};
+use std::collections::HashMap;
+use std::time::Duration;
 
 pub use super::Consistency;
 pub use crate::frame::request::batch::BatchType;
@@ -11,6 +13,11 @@ pub struct Batch {
     statements: Vec<BatchStatement>,
     batch_type: BatchType,
     consistency: Consistency,
+    serial_consistency: Option<Consistency>,
+    timestamp: Option<i64>,
+    tracing: bool,
+    custom_payload: Option<HashMap<String, Vec<u8>>>,
+    request_timeout: Option<Duration>,
 }
 
 impl Batch {
@@ -46,6 +53,65 @@ impl Batch {
     pub fn get_consistency(&self) -> Consistency {
         self.consistency
     }
+
+    /// Sets the serial consistency to be used when executing this batch.
+    /// Applies only to lightweight transactions, should be `Serial` or `LocalSerial`.
+    /// If not set, the database uses `Serial`.
+    pub fn set_serial_consistency(&mut self, sc: Option<Consistency>) {
+        self.serial_consistency = sc;
+    }
+
+    /// Gets the serial consistency to be used when executing this batch.
+    pub fn get_serial_consistency(&self) -> Option<Consistency> {
+        self.serial_consistency
+    }
+
+    /// Sets the default timestamp (in microseconds since unix epoch) for writes in this batch.
+    /// Overrides the timestamp generated by the session's timestamp generator.
+    pub fn set_timestamp(&mut self, timestamp: Option<i64>) {
+        self.timestamp = timestamp;
+    }
+
+    /// Gets the default timestamp set for this batch.
+    pub fn get_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    /// Enable or disable CQL Tracing for this batch
+    /// If enabled session.batch() will return a BatchResult containing tracing_id
+    /// which can be used to query tracing information about the execution of this batch
+    pub fn set_tracing(&mut self, should_trace: bool) {
+        self.tracing = should_trace;
+    }
+
+    /// Gets whether tracing is enabled for this batch
+    pub fn get_tracing(&self) -> bool {
+        self.tracing
+    }
+
+    /// Sets the custom payload sent along with this batch.
+    /// The payload is a map of opaque byte values, interpreted by the server
+    /// or by a proxy standing between the driver and the server.
+    pub fn set_custom_payload(&mut self, custom_payload: Option<HashMap<String, Vec<u8>>>) {
+        self.custom_payload = custom_payload;
+    }
+
+    /// Gets the custom payload sent along with this batch
+    pub fn get_custom_payload(&self) -> Option<&HashMap<String, Vec<u8>>> {
+        self.custom_payload.as_ref()
+    }
+
+    /// Sets the client-side timeout for this batch.
+    /// If the response doesn't arrive in time, the request fails with [RequestTimeout](crate::transport::errors::QueryError::RequestTimeout).
+    /// If not set, the session's default request timeout is used.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Gets the client-side timeout set for this batch
+    pub fn get_request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
 }
 
 impl Default for Batch {
@@ -54,6 +120,11 @@ impl Default for Batch {
             statements: Vec::new(),
             batch_type: BatchType::Logged,
             consistency: Default::default(),
+            serial_consistency: None,
+            timestamp: None,
+            tracing: false,
+            custom_payload: None,
+            request_timeout: None,
         }
     }
 }
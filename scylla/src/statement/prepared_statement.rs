@@ -1,28 +1,52 @@
 use super::Consistency;
-use crate::frame::response::result::PreparedMetadata;
+use crate::frame::response::result::{PreparedMetadata, ResultMetadata};
 use crate::frame::value::SerializedValues;
 use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use thiserror::Error;
 
 /// Represents a statement prepared on the server.
 #[derive(Debug, Clone)]
 pub struct PreparedStatement {
     id: Bytes,
+    // Shared between clones, so that a metadata update is visible to all of them
+    result_metadata: Arc<RwLock<CachedResultMetadata>>,
     metadata: PreparedMetadata,
+    is_lwt: bool,
     statement: String,
     page_size: Option<i32>,
     consistency: Consistency,
+    serial_consistency: Option<Consistency>,
+    timestamp: Option<i64>,
+    tracing: bool,
+    custom_payload: Option<HashMap<String, Vec<u8>>>,
+    request_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct CachedResultMetadata {
+    id: Option<Bytes>,
+    metadata: Arc<ResultMetadata>,
 }
 
 impl PreparedStatement {
     pub fn new(id: Bytes, metadata: PreparedMetadata, statement: String) -> Self {
         Self {
             id,
+            result_metadata: Default::default(),
             metadata,
+            is_lwt: false,
             statement,
             page_size: None,
             consistency: Default::default(),
+            serial_consistency: None,
+            timestamp: None,
+            tracing: false,
+            custom_payload: None,
+            request_timeout: None,
         }
     }
 
@@ -30,6 +54,46 @@ impl PreparedStatement {
         &self.id
     }
 
+    /// Returns the id of the result metadata, which is sent by the server since protocol v5
+    pub fn get_result_metadata_id(&self) -> Option<Bytes> {
+        self.result_metadata.read().unwrap().id.clone()
+    }
+
+    /// Returns metadata of rows returned by this statement.\
+    /// It's cached so that the server can skip sending it with every result.
+    pub fn get_result_metadata(&self) -> Arc<ResultMetadata> {
+        self.result_metadata.read().unwrap().metadata.clone()
+    }
+
+    /// Replaces cached result metadata, e.g. after the server reported that it changed
+    pub(crate) fn set_result_metadata(&self, id: Option<Bytes>, mut metadata: ResultMetadata) {
+        // Paging state is specific to a single result
+        metadata.paging_state = None;
+        metadata.new_metadata_id = None;
+
+        *self.result_metadata.write().unwrap() = CachedResultMetadata {
+            id,
+            metadata: Arc::new(metadata),
+        };
+    }
+
+    /// Takes cached result metadata from another preparation of the same statement
+    pub(crate) fn update_result_metadata_from(&self, other: &PreparedStatement) {
+        let other_metadata = other.result_metadata.read().unwrap().clone();
+        *self.result_metadata.write().unwrap() = other_metadata;
+    }
+
+    /// Returns true if the statement is a lightweight transaction (e.g. `INSERT ... IF NOT EXISTS`).\
+    /// It's known only when connected to Scylla supporting the `SCYLLA_LWT_ADD_METADATA_MARK` extension,
+    /// otherwise false is returned.
+    pub fn is_lwt(&self) -> bool {
+        self.is_lwt
+    }
+
+    pub(crate) fn set_is_lwt(&mut self, is_lwt: bool) {
+        self.is_lwt = is_lwt;
+    }
+
     pub fn get_statement(&self) -> &str {
         &self.statement
     }
@@ -60,6 +124,65 @@ impl PreparedStatement {
         self.consistency
     }
 
+    /// Sets the serial consistency to be used when executing this statement.
+    /// Applies only to lightweight transactions, should be `Serial` or `LocalSerial`.
+    /// If not set, the database uses `Serial`.
+    pub fn set_serial_consistency(&mut self, sc: Option<Consistency>) {
+        self.serial_consistency = sc;
+    }
+
+    /// Gets the serial consistency to be used when executing this statement.
+    pub fn get_serial_consistency(&self) -> Option<Consistency> {
+        self.serial_consistency
+    }
+
+    /// Sets the default timestamp (in microseconds since unix epoch) for writes in this statement.
+    /// Overrides the timestamp generated by the session's timestamp generator.
+    pub fn set_timestamp(&mut self, timestamp: Option<i64>) {
+        self.timestamp = timestamp;
+    }
+
+    /// Gets the default timestamp set for this statement.
+    pub fn get_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    /// Enable or disable CQL Tracing for this statement
+    /// If enabled session.execute() will return a QueryResult containing tracing_id
+    /// which can be used to query tracing information about the execution of this statement
+    pub fn set_tracing(&mut self, should_trace: bool) {
+        self.tracing = should_trace;
+    }
+
+    /// Gets whether tracing is enabled for this statement
+    pub fn get_tracing(&self) -> bool {
+        self.tracing
+    }
+
+    /// Sets the custom payload sent along with this statement.
+    /// The payload is a map of opaque byte values, interpreted by the server
+    /// or by a proxy standing between the driver and the server.
+    pub fn set_custom_payload(&mut self, custom_payload: Option<HashMap<String, Vec<u8>>>) {
+        self.custom_payload = custom_payload;
+    }
+
+    /// Gets the custom payload sent along with this statement
+    pub fn get_custom_payload(&self) -> Option<&HashMap<String, Vec<u8>>> {
+        self.custom_payload.as_ref()
+    }
+
+    /// Sets the client-side timeout for this statement.
+    /// If the response doesn't arrive in time, the request fails with [RequestTimeout](crate::transport::errors::QueryError::RequestTimeout).
+    /// If not set, the session's default request timeout is used.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Gets the client-side timeout set for this statement
+    pub fn get_request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+
     /// Computes the partition key of the target table from given values
     /// Partition keys have a specific serialization rules.
     /// Ref: https://github.com/scylladb/scylla/blob/40adf38915b6d8f5314c621a94d694d172360833/compound_compat.hh#L33-L47
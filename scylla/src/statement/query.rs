@@ -1,4 +1,6 @@
 use super::Consistency;
+use std::collections::HashMap;
+use std::time::Duration;
 
 /// CQL query statement.
 ///
@@ -8,6 +10,11 @@ pub struct Query {
     contents: String,
     page_size: Option<i32>,
     consistency: Consistency,
+    serial_consistency: Option<Consistency>,
+    timestamp: Option<i64>,
+    tracing: bool,
+    custom_payload: Option<HashMap<String, Vec<u8>>>,
+    request_timeout: Option<Duration>,
 }
 
 impl Query {
@@ -17,6 +24,11 @@ impl Query {
             contents,
             page_size: None,
             consistency: Default::default(),
+            serial_consistency: None,
+            timestamp: None,
+            tracing: false,
+            custom_payload: None,
+            request_timeout: None,
         }
     }
 
@@ -50,6 +62,65 @@ impl Query {
     pub fn get_consistency(&self) -> Consistency {
         self.consistency
     }
+
+    /// Sets the serial consistency to be used when executing this query.
+    /// Applies only to lightweight transactions, should be `Serial` or `LocalSerial`.
+    /// If not set, the database uses `Serial`.
+    pub fn set_serial_consistency(&mut self, sc: Option<Consistency>) {
+        self.serial_consistency = sc;
+    }
+
+    /// Gets the serial consistency to be used when executing this query.
+    pub fn get_serial_consistency(&self) -> Option<Consistency> {
+        self.serial_consistency
+    }
+
+    /// Sets the default timestamp (in microseconds since unix epoch) for writes in this query.
+    /// Overrides the timestamp generated by the session's timestamp generator.
+    pub fn set_timestamp(&mut self, timestamp: Option<i64>) {
+        self.timestamp = timestamp;
+    }
+
+    /// Gets the default timestamp set for this query.
+    pub fn get_timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    /// Enable or disable CQL Tracing for this query
+    /// If enabled session.query() will return a QueryResult containing tracing_id
+    /// which can be used to query tracing information about the execution of this query
+    pub fn set_tracing(&mut self, should_trace: bool) {
+        self.tracing = should_trace;
+    }
+
+    /// Gets whether tracing is enabled for this query
+    pub fn get_tracing(&self) -> bool {
+        self.tracing
+    }
+
+    /// Sets the custom payload sent along with this query.
+    /// The payload is a map of opaque byte values, interpreted by the server
+    /// or by a proxy standing between the driver and the server.
+    pub fn set_custom_payload(&mut self, custom_payload: Option<HashMap<String, Vec<u8>>>) {
+        self.custom_payload = custom_payload;
+    }
+
+    /// Gets the custom payload sent along with this query
+    pub fn get_custom_payload(&self) -> Option<&HashMap<String, Vec<u8>>> {
+        self.custom_payload.as_ref()
+    }
+
+    /// Sets the client-side timeout for this query.
+    /// If the response doesn't arrive in time, the request fails with [RequestTimeout](crate::transport::errors::QueryError::RequestTimeout).
+    /// If not set, the session's default request timeout is used.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Gets the client-side timeout set for this query
+    pub fn get_request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
 }
 
 impl From<String> for Query {
@@ -0,0 +1,818 @@
+//! In-process server speaking the CQL protocol, which answers statements with scripted responses
+use crate::frame::frame_errors::FrameError;
+use crate::frame::request::{batch::BatchStatement, ParsedRequest};
+use crate::frame::response::error::UNPREPARED_CODE;
+use crate::frame::response::result::{
+    self, CQLValue, ColumnSpec, ColumnType, Prepared, PreparedMetadata, ResultMetadata, Row, Rows,
+    TableSpec,
+};
+use crate::frame::response::{Error, Response, Supported};
+use crate::frame::{
+    parse_request_body_extensions, prepare_response_body_with_extensions, read_request_frame,
+    read_segment, take_request_frame, write_response_frame, write_response_frame_in_segments,
+    FrameParams, ProtocolVersion, ResponseBodyWithExtensions, FLAG_TRACING,
+};
+use crate::transport::connector::{ConnectOptions, ConnectedStream, Connector};
+use crate::transport::errors::DBError;
+use crate::transport::Compression;
+
+use bytes::{Bytes, BytesMut};
+use futures::{
+    future::{BoxFuture, RemoteHandle},
+    stream::FuturesUnordered,
+    FutureExt, StreamExt,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// Error code used by the fake server, besides UNPREPARED
+const PROTOCOL_ERROR_CODE: i32 = 0x000A;
+
+/// Fake CQL server listening on a local port.\
+/// Statements are matched against [Rules](Rule) in the order in which they were added,
+/// the first matching rule decides the response. Statements not matching any rule
+/// receive a Void result, except for queries to `system.local`, `system.peers` and
+/// `system_schema.keyspaces`, which are answered as if the server was a single node cluster,
+/// so that [Session::connect](crate::Session::connect) and topology discovery work.
+///
+/// By default the server supports protocol versions 3 and 4 and rejects other versions,
+/// just like Scylla does, so the driver negotiates protocol v4.
+/// Protocol v5 can be enabled with [FakeServer::set_max_protocol_version].
+///
+/// A server started with [FakeServer::start_sharded] reports Scylla's sharding parameters
+/// and listens on a shard-aware port as well. Connections to the shard-aware port are assigned
+/// to shards based on their source port, connections to the regular port - in turns.
+///
+/// Besides TCP, the server can be reached through in-memory streams opened by
+/// the connector returned from [FakeServer::in_memory_connector].
+///
+/// The server stops when dropped.
+///
+/// # Example
+/// ```rust
+/// # use std::error::Error;
+/// # async fn check_only_compiles() -> Result<(), Box<dyn Error>> {
+/// use scylla::frame::response::result::{CQLValue, ColumnType};
+/// use scylla::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+/// use scylla::{IntoTypedRows, SessionBuilder};
+///
+/// let server = FakeServer::start().await?;
+/// server.add_rule(Rule::new(
+///     StatementPattern::Prefix("SELECT a FROM ks.t".to_string()),
+///     FakeResponse::rows(&[("a", ColumnType::Int)], vec![vec![Some(CQLValue::Int(7))]]),
+/// ));
+///
+/// let session = SessionBuilder::new()
+///     .known_node_addr(server.address())
+///     .build()
+///     .await?;
+///
+/// if let Some(rows) = session.query("SELECT a FROM ks.t", &[]).await?.rows {
+///     for row in rows.into_typed::<(i32,)>() {
+///         assert_eq!(row?.0, 7);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct FakeServer {
+    address: SocketAddr,
+    state: Arc<ServerState>,
+    _worker_handle: RemoteHandle<()>,
+    _shard_aware_worker_handle: Option<RemoteHandle<()>>,
+}
+
+/// Decides which statements are matched by a [Rule]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatementPattern {
+    /// Matches every statement
+    Any,
+    /// Matches statements equal to the given text
+    Exact(String),
+    /// Matches statements starting with the given text
+    Prefix(String),
+    /// Matches statements containing the given text
+    Contains(String),
+}
+
+/// Response sent by the [FakeServer] to a matched statement
+#[derive(Debug, Clone)]
+pub enum FakeResponse {
+    Void,
+    Rows(Rows),
+    Error { code: i32, message: String },
+}
+
+/// Scripted response of a [FakeServer] to statements matching the pattern
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub pattern: StatementPattern,
+    pub response: FakeResponse,
+    /// Time to wait before responding, other requests are handled in the meantime
+    pub delay: Option<Duration>,
+}
+
+/// [Connector] opening in-memory streams to a [FakeServer], regardless of the node address.\
+/// Connections are assigned to shards in turns, like connections to the regular port.
+pub struct InMemoryConnector {
+    state: Weak<ServerState>,
+}
+
+struct ServerState {
+    rules: Mutex<Vec<Rule>>,
+    // Statement texts of prepared statements, by statement id
+    prepared: Mutex<HashMap<Bytes, String>>,
+    received_statements: Mutex<Vec<String>>,
+
+    // Sharding parameters, None if the server doesn't report them
+    nr_shards: Option<u16>,
+    shard_aware_port: Option<u16>,
+    // Connections to the shard-aware port aren't answered when it's blocked
+    shard_aware_port_blocked: AtomicBool,
+    // Shard of the next connection to the regular port
+    next_shard: AtomicUsize,
+    // Number of open connections handled by each shard
+    shard_connections: Mutex<Vec<usize>>,
+    // Highest protocol version accepted by the server
+    max_protocol_version: Mutex<ProtocolVersion>,
+    // Protocol versions of received STARTUP requests
+    startup_versions: Mutex<Vec<ProtocolVersion>>,
+}
+
+// Port on which a connection was accepted
+#[derive(Clone, Copy)]
+enum Port {
+    Regular,
+    ShardAware,
+}
+
+impl StatementPattern {
+    pub fn matches(&self, statement: &str) -> bool {
+        let statement = statement.trim();
+        match self {
+            StatementPattern::Any => true,
+            StatementPattern::Exact(text) => statement == text,
+            StatementPattern::Prefix(prefix) => statement.starts_with(prefix.as_str()),
+            StatementPattern::Contains(text) => statement.contains(text.as_str()),
+        }
+    }
+}
+
+impl FakeResponse {
+    /// Creates rows with columns of given names and types
+    pub fn rows(columns: &[(&str, ColumnType)], rows: Vec<Vec<Option<CQLValue>>>) -> Self {
+        let col_specs: Vec<ColumnSpec> = columns
+            .iter()
+            .map(|(name, typ)| ColumnSpec {
+                table_spec: TableSpec {
+                    ks_name: "fake".to_string(),
+                    table_name: "fake".to_string(),
+                },
+                name: name.to_string(),
+                typ: typ.clone(),
+            })
+            .collect();
+
+        let metadata = ResultMetadata {
+            col_count: col_specs.len(),
+            col_specs,
+            ..Default::default()
+        };
+        let rows = rows.into_iter().map(|columns| Row { columns }).collect();
+
+        FakeResponse::Rows(Rows::new(metadata, rows))
+    }
+
+    /// Creates an error response with the given code, e.g. 0x1001 for Overloaded
+    pub fn error(code: i32, message: &str) -> Self {
+        FakeResponse::Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn into_response(self) -> Response {
+        match self {
+            FakeResponse::Void => Response::Result(result::Result::Void),
+            FakeResponse::Rows(rows) => Response::Result(result::Result::Rows(rows)),
+            FakeResponse::Error { code, message } => Response::Error(Error::new(code, message)),
+        }
+    }
+}
+
+impl Rule {
+    pub fn new(pattern: StatementPattern, response: FakeResponse) -> Self {
+        Rule {
+            pattern,
+            response,
+            delay: None,
+        }
+    }
+
+    /// Delays the response by the given duration
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+impl FakeServer {
+    /// Starts a server listening on a random port of 127.0.0.1
+    pub async fn start() -> Result<FakeServer, std::io::Error> {
+        FakeServer::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    /// Starts a server listening on the given address
+    pub async fn bind(address: SocketAddr) -> Result<FakeServer, std::io::Error> {
+        FakeServer::listen(address, None).await
+    }
+
+    /// Starts a server with `nr_shards` shards, listening on random ports of 127.0.0.1.\
+    /// Besides the regular port, the server listens on a shard-aware port.
+    pub async fn start_sharded(nr_shards: u16) -> Result<FakeServer, std::io::Error> {
+        assert!(nr_shards > 0);
+        FakeServer::listen(SocketAddr::from(([127, 0, 0, 1], 0)), Some(nr_shards)).await
+    }
+
+    async fn listen(
+        address: SocketAddr,
+        nr_shards: Option<u16>,
+    ) -> Result<FakeServer, std::io::Error> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+
+        let shard_aware_listener = match nr_shards {
+            Some(_) => Some(TcpListener::bind(SocketAddr::new(address.ip(), 0)).await?),
+            None => None,
+        };
+        let shard_aware_port = match &shard_aware_listener {
+            Some(listener) => Some(listener.local_addr()?.port()),
+            None => None,
+        };
+
+        let state = Arc::new(ServerState {
+            rules: Default::default(),
+            prepared: Default::default(),
+            received_statements: Default::default(),
+            nr_shards,
+            shard_aware_port,
+            shard_aware_port_blocked: AtomicBool::new(false),
+            next_shard: AtomicUsize::new(0),
+            shard_connections: Mutex::new(vec![0; nr_shards.unwrap_or(1) as usize]),
+            max_protocol_version: Mutex::new(ProtocolVersion::V4),
+            startup_versions: Default::default(),
+        });
+
+        let (fut, worker_handle) =
+            accept_connections(listener, state.clone(), Port::Regular).remote_handle();
+        tokio::spawn(fut);
+
+        let shard_aware_worker_handle = shard_aware_listener.map(|listener| {
+            let (fut, handle) =
+                accept_connections(listener, state.clone(), Port::ShardAware).remote_handle();
+            tokio::spawn(fut);
+            handle
+        });
+
+        Ok(FakeServer {
+            address,
+            state,
+            _worker_handle: worker_handle,
+            _shard_aware_worker_handle: shard_aware_worker_handle,
+        })
+    }
+
+    /// Address on which the server listens
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Address of the shard-aware port, if the server is sharded
+    pub fn shard_aware_address(&self) -> Option<SocketAddr> {
+        self.state
+            .shard_aware_port
+            .map(|port| SocketAddr::new(self.address.ip(), port))
+    }
+
+    /// Emulates a firewall blocking the shard-aware port.\
+    /// While blocked, new connections to the port are never answered, like when a firewall
+    /// drops their packets. The driver gives up on them after the shard-aware port connect timeout.
+    pub fn block_shard_aware_port(&self, blocked: bool) {
+        self.state
+            .shard_aware_port_blocked
+            .store(blocked, Ordering::Relaxed);
+    }
+
+    /// Sets the highest protocol version accepted by the server, V4 by default.\
+    /// Applies to connections opened afterwards. In protocol v5 frames are exchanged
+    /// in segments once STARTUP completes, LZ4 compression is applied to the segments.
+    pub fn set_max_protocol_version(&self, version: ProtocolVersion) {
+        *self.state.max_protocol_version.lock().unwrap() = version;
+    }
+
+    /// Protocol versions in which connections were started, in order of their STARTUP requests
+    pub fn startup_protocol_versions(&self) -> Vec<ProtocolVersion> {
+        self.state.startup_versions.lock().unwrap().clone()
+    }
+
+    /// Number of open connections handled by each shard.\
+    /// A server which isn't sharded has a single shard.
+    pub fn connections_per_shard(&self) -> Vec<usize> {
+        self.state.shard_connections.lock().unwrap().clone()
+    }
+
+    /// Connector through which the driver talks to this server over in-memory streams
+    pub fn in_memory_connector(&self) -> InMemoryConnector {
+        InMemoryConnector {
+            state: Arc::downgrade(&self.state),
+        }
+    }
+
+    /// Adds a rule, which is checked after all previously added rules
+    pub fn add_rule(&self, rule: Rule) {
+        self.state.rules.lock().unwrap().push(rule);
+    }
+
+    /// Removes all rules
+    pub fn clear_rules(&self) {
+        self.state.rules.lock().unwrap().clear();
+    }
+
+    /// Texts of all statements received in QUERY, EXECUTE and BATCH requests, in order
+    pub fn received_statements(&self) -> Vec<String> {
+        self.state.received_statements.lock().unwrap().clone()
+    }
+}
+
+async fn accept_connections(listener: TcpListener, state: Arc<ServerState>, port: Port) {
+    // Connections are closed along with the server, when their handles are dropped
+    let mut connection_handles: FuturesUnordered<RemoteHandle<()>> = FuturesUnordered::new();
+    // Connections accepted while the shard-aware port is blocked, kept open without answering
+    let mut unanswered: Vec<TcpStream> = Vec::new();
+
+    while let Ok((stream, peer_address)) = listener.accept().await {
+        // Handles of closed connections are dropped, so that they don't pile up
+        while let Some(Some(())) = connection_handles.next().now_or_never() {}
+
+        let shard = match port {
+            Port::Regular => state.next_shard.fetch_add(1, Ordering::Relaxed),
+            Port::ShardAware if state.shard_aware_port_blocked.load(Ordering::Relaxed) => {
+                unanswered.push(stream);
+                continue;
+            }
+            Port::ShardAware => peer_address.port() as usize,
+        } % state.nr_shards.unwrap_or(1) as usize;
+
+        let local_ip = match stream.local_addr() {
+            Ok(address) => address.ip(),
+            Err(_) => continue,
+        };
+        let (fut, handle) = handle_connection(stream, state.clone(), shard, local_ip)
+            .map(|_| ())
+            .remote_handle();
+        tokio::spawn(fut);
+        connection_handles.push(handle);
+    }
+}
+
+// Response waiting to be written, along with the framing to use
+type PendingResponse = (FrameParams, Response, Framing);
+
+// Format of frames exchanged over a connection
+#[derive(Clone, Copy)]
+enum Framing {
+    // Frames are sent as they are, their bodies compressed with the given compression
+    Frames(Option<Compression>),
+    // Frames are wrapped in protocol v5 segments, which are LZ4 compressed if `compressed`
+    Segments { compressed: bool },
+}
+
+// Counts a connection as open for as long as it's alive
+struct ShardConnectionGuard {
+    state: Arc<ServerState>,
+    shard: usize,
+}
+
+impl Drop for ShardConnectionGuard {
+    fn drop(&mut self) {
+        self.state.shard_connections.lock().unwrap()[self.shard] -= 1;
+    }
+}
+
+impl Connector for InMemoryConnector {
+    fn connect<'a>(
+        &'a self,
+        address: SocketAddr,
+        _options: &'a ConnectOptions,
+    ) -> BoxFuture<'a, Result<ConnectedStream, std::io::Error>> {
+        Box::pin(async move {
+            let state = self.state.upgrade().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "server stopped")
+            })?;
+            let shard = state.next_shard.fetch_add(1, Ordering::Relaxed)
+                % state.nr_shards.unwrap_or(1) as usize;
+
+            // The connection stops once the driver closes its end of the stream
+            let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(handle_connection(server_stream, state, shard, address.ip()));
+
+            Ok(ConnectedStream {
+                stream: Box::new(client_stream),
+                source_port: None,
+            })
+        })
+    }
+}
+
+async fn handle_connection(
+    stream: impl AsyncRead + AsyncWrite + Send + 'static,
+    state: Arc<ServerState>,
+    shard: usize,
+    local_ip: IpAddr,
+) -> Result<(), FrameError> {
+    state.shard_connections.lock().unwrap()[shard] += 1;
+    let _guard = ShardConnectionGuard {
+        state: state.clone(),
+        shard,
+    };
+
+    let (mut read_half, write_half) = tokio::io::split(stream);
+
+    let (sender, receiver) = mpsc::unbounded_channel::<PendingResponse>();
+    let (writer, _writer_handle) = write_responses(write_half, receiver).remote_handle();
+    tokio::spawn(writer);
+
+    let max_version = *state.max_protocol_version.lock().unwrap();
+    let mut framing = Framing::Frames(None);
+    // Data received in segments which doesn't form a complete frame yet
+    let mut segment_buf = BytesMut::new();
+
+    loop {
+        let (params, opcode, body) = match framing {
+            Framing::Frames(_) => read_request_frame(&mut read_half).await?,
+            Framing::Segments { compressed } => loop {
+                if let Some(frame) = take_request_frame(&mut segment_buf)? {
+                    break frame;
+                }
+                let payload = read_segment(&mut read_half, compressed).await?;
+                segment_buf.extend_from_slice(&payload);
+            },
+        };
+
+        let version = match ProtocolVersion::try_from(params.version) {
+            Ok(version) if version <= max_version => version,
+            _ => {
+                // Respond in the highest supported version, the client should downgrade
+                let error = Error::new(
+                    PROTOCOL_ERROR_CODE,
+                    "Invalid or unsupported protocol version".to_string(),
+                );
+                let params = FrameParams {
+                    version: max_version as u8,
+                    flags: 0,
+                    stream: params.stream,
+                };
+                let _ = sender.send((params, Response::Error(error), Framing::Frames(None)));
+                continue;
+            }
+        };
+
+        let compression = match framing {
+            Framing::Frames(compression) => compression,
+            Framing::Segments { .. } => None,
+        };
+        let body = parse_request_body_extensions(params.flags, compression, body)?.body;
+        let request = ParsedRequest::deserialize(opcode, version, &mut &*body)?;
+
+        let (response, delay) = match request {
+            ParsedRequest::Startup(startup) => {
+                state.startup_versions.lock().unwrap().push(version);
+                // READY is never compressed, the new framing applies to the following frames
+                let params = response_params(version, params);
+                let _ = sender.send((params, Response::Ready, Framing::Frames(None)));
+                let compression = match startup.options.get("COMPRESSION").map(String::as_str) {
+                    Some("lz4") => Some(Compression::LZ4),
+                    Some("snappy") => Some(Compression::Snappy),
+                    _ => None,
+                };
+                framing = if version >= ProtocolVersion::V5 {
+                    Framing::Segments {
+                        compressed: compression == Some(Compression::LZ4),
+                    }
+                } else {
+                    Framing::Frames(compression)
+                };
+                continue;
+            }
+            ParsedRequest::Options(_) => (state.supported(shard), None),
+            ParsedRequest::Register(_) => (Response::Ready, None),
+            ParsedRequest::AuthResponse(_) => (
+                Response::Error(Error::new(
+                    PROTOCOL_ERROR_CODE,
+                    "Authentication is not supported".to_string(),
+                )),
+                None,
+            ),
+            ParsedRequest::Prepare(prepare) => (state.prepare(prepare.query), None),
+            ParsedRequest::Query(query) => state.respond(&query.contents, local_ip),
+            ParsedRequest::Execute(execute) => {
+                let statement = state.prepared.lock().unwrap().get(&execute.id).cloned();
+                match statement {
+                    Some(statement) => state.respond(&statement, local_ip),
+                    // The unknown id is sent back, so that the client knows what to prepare again
+                    None => (
+                        Response::Error(Error {
+                            code: UNPREPARED_CODE,
+                            reason: "Unknown prepared statement id".to_string(),
+                            error: DBError::Unprepared {
+                                statement_id: execute.id,
+                            },
+                        }),
+                        None,
+                    ),
+                }
+            }
+            ParsedRequest::Batch(batch) => {
+                let prepared = state.prepared.lock().unwrap().clone();
+                let mut response = (Response::Result(result::Result::Void), None);
+                for statement in batch.statements {
+                    let text = match statement {
+                        BatchStatement::Query { text } => text.to_string(),
+                        BatchStatement::Prepared { id } => {
+                            prepared.get(id).cloned().unwrap_or_default()
+                        }
+                    };
+                    state.received_statements.lock().unwrap().push(text.clone());
+                    // Rows are returned by conditional batches, an error fails the whole batch
+                    match state.find_rule(&text) {
+                        Some((FakeResponse::Error { code, message }, delay)) => {
+                            response = (Response::Error(Error::new(code, message)), delay);
+                        }
+                        Some((FakeResponse::Rows(rows), delay))
+                            if !matches!(response.0, Response::Error(_)) =>
+                        {
+                            response = (Response::Result(result::Result::Rows(rows)), delay);
+                        }
+                        _ => {}
+                    }
+                }
+                response
+            }
+        };
+
+        let params = response_params(version, params);
+        match delay {
+            None => {
+                let _ = sender.send((params, response, framing));
+            }
+            Some(delay) => {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = sender.send((params, response, framing));
+                });
+            }
+        }
+    }
+}
+
+// Only the tracing flag is kept, so that traced requests get a tracing id in the response
+fn response_params(version: ProtocolVersion, request_params: FrameParams) -> FrameParams {
+    FrameParams {
+        version: version as u8,
+        flags: request_params.flags & FLAG_TRACING,
+        stream: request_params.stream,
+    }
+}
+
+// Tracing ids are unique, but there are no tracing sessions stored behind them
+fn next_tracing_id() -> Uuid {
+    static NEXT_TRACING_ID: AtomicU64 = AtomicU64::new(1);
+    Uuid::from_u128(NEXT_TRACING_ID.fetch_add(1, Ordering::Relaxed).into())
+}
+
+async fn write_responses(
+    mut write_half: impl AsyncWrite + Unpin,
+    mut receiver: mpsc::UnboundedReceiver<PendingResponse>,
+) -> Result<(), FrameError> {
+    while let Some((params, response, framing)) = receiver.recv().await {
+        let version = ProtocolVersion::try_from(params.version)?;
+        let body_with_ext = ResponseBodyWithExtensions {
+            trace_id: if params.flags & FLAG_TRACING != 0 {
+                Some(next_tracing_id())
+            } else {
+                None
+            },
+            warnings: Vec::new(),
+            custom_payload: None,
+            body: response.to_bytes(version)?,
+        };
+        let compression = match framing {
+            Framing::Frames(compression) => compression,
+            Framing::Segments { .. } => None,
+        };
+        let (flags, body) = prepare_response_body_with_extensions(body_with_ext, compression)?;
+
+        let params = FrameParams { flags, ..params };
+        match framing {
+            Framing::Frames(_) => {
+                write_response_frame(&mut write_half, params, response.opcode(), body).await?
+            }
+            Framing::Segments { compressed } => {
+                write_response_frame_in_segments(
+                    &mut write_half,
+                    params,
+                    response.opcode(),
+                    body,
+                    compressed,
+                )
+                .await?
+            }
+        }
+    }
+    Ok(())
+}
+
+impl ServerState {
+    fn supported(&self, shard: usize) -> Response {
+        let mut options = HashMap::new();
+        options.insert("CQL_VERSION".to_string(), vec!["3.3.1".to_string()]);
+        options.insert(
+            "COMPRESSION".to_string(),
+            vec!["lz4".to_string(), "snappy".to_string()],
+        );
+
+        if let Some(nr_shards) = self.nr_shards {
+            let mut insert = |key: &str, value: String| {
+                options.insert(key.to_string(), vec![value]);
+            };
+            insert("SCYLLA_SHARD", shard.to_string());
+            insert("SCYLLA_NR_SHARDS", nr_shards.to_string());
+            insert("SCYLLA_SHARDING_IGNORE_MSB", "12".to_string());
+            insert(
+                "SCYLLA_PARTITIONER",
+                "org.apache.cassandra.dht.Murmur3Partitioner".to_string(),
+            );
+            insert(
+                "SCYLLA_SHARDING_ALGORITHM",
+                "biased-token-round-robin".to_string(),
+            );
+            if let Some(port) = self.shard_aware_port {
+                insert("SCYLLA_SHARD_AWARE_PORT", port.to_string());
+            }
+        }
+
+        Response::Supported(Supported { options })
+    }
+
+    fn find_rule(&self, statement: &str) -> Option<(FakeResponse, Option<Duration>)> {
+        self.rules
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|rule| rule.pattern.matches(statement))
+            .map(|rule| (rule.response.clone(), rule.delay))
+    }
+
+    fn respond(&self, statement: &str, local_ip: IpAddr) -> (Response, Option<Duration>) {
+        self.received_statements
+            .lock()
+            .unwrap()
+            .push(statement.to_string());
+
+        if let Some((response, delay)) = self.find_rule(statement) {
+            return (response.into_response(), delay);
+        }
+
+        (system_response(statement, local_ip), None)
+    }
+
+    fn prepare(&self, statement: &str) -> Response {
+        let mut hasher = DefaultHasher::new();
+        statement.hash(&mut hasher);
+        let id = Bytes::copy_from_slice(&hasher.finish().to_be_bytes());
+
+        self.prepared
+            .lock()
+            .unwrap()
+            .insert(id.clone(), statement.to_string());
+
+        Response::Result(result::Result::Prepared(Prepared {
+            id,
+            result_metadata_id: None,
+            prepared_metadata: PreparedMetadata {
+                flags: 0,
+                col_count: 0,
+                pk_indexes: Vec::new(),
+                col_specs: Vec::new(),
+            },
+            result_metadata: ResultMetadata::default(),
+        }))
+    }
+}
+
+// Responses to statements which aren't matched by any rule
+fn system_response(statement: &str, local_ip: IpAddr) -> Response {
+    let rows = if statement.contains("system.local") {
+        FakeResponse::rows(
+            &[
+                ("listen_address", ColumnType::Inet),
+                ("data_center", ColumnType::Text),
+                ("rack", ColumnType::Text),
+                ("tokens", ColumnType::Set(Box::new(ColumnType::Text))),
+            ],
+            vec![vec![
+                Some(CQLValue::Inet(local_ip)),
+                Some(CQLValue::Text("datacenter1".to_string())),
+                Some(CQLValue::Text("rack1".to_string())),
+                Some(CQLValue::Set(vec![CQLValue::Text("0".to_string())])),
+            ]],
+        )
+    } else if statement.contains("system.peers") {
+        FakeResponse::rows(
+            &[
+                ("peer", ColumnType::Inet),
+                ("data_center", ColumnType::Text),
+                ("rack", ColumnType::Text),
+                ("tokens", ColumnType::Set(Box::new(ColumnType::Text))),
+            ],
+            Vec::new(),
+        )
+    } else if statement.contains("system_schema.keyspaces") {
+        FakeResponse::rows(
+            &[
+                ("keyspace_name", ColumnType::Text),
+                ("replication", ColumnType::Text),
+            ],
+            Vec::new(),
+        )
+    } else if let Some(keyspace) = use_statement_keyspace(statement) {
+        return Response::Result(result::Result::SetKeyspace(result::SetKeyspace {
+            keyspace_name: keyspace,
+        }));
+    } else {
+        FakeResponse::Void
+    };
+
+    rows.into_response()
+}
+
+fn use_statement_keyspace(statement: &str) -> Option<String> {
+    let mut words = statement.trim().trim_end_matches(';').split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some(use_word), Some(keyspace), None) if use_word.eq_ignore_ascii_case("USE") => {
+            if keyspace.starts_with('"') && keyspace.ends_with('"') && keyspace.len() >= 2 {
+                Some(keyspace[1..keyspace.len() - 1].to_string())
+            } else {
+                Some(keyspace.to_lowercase())
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{use_statement_keyspace, StatementPattern};
+
+    #[test]
+    fn statement_patterns() {
+        let statement = "  SELECT a FROM ks.t WHERE a = ?";
+
+        assert!(StatementPattern::Any.matches(statement));
+        assert!(
+            StatementPattern::Exact("SELECT a FROM ks.t WHERE a = ?".to_string())
+                .matches(statement)
+        );
+        assert!(!StatementPattern::Exact("SELECT a FROM ks.t".to_string()).matches(statement));
+        assert!(StatementPattern::Prefix("SELECT a FROM".to_string()).matches(statement));
+        assert!(!StatementPattern::Prefix("INSERT".to_string()).matches(statement));
+        assert!(StatementPattern::Contains("ks.t".to_string()).matches(statement));
+        assert!(!StatementPattern::Contains("ks.other".to_string()).matches(statement));
+    }
+
+    #[test]
+    fn use_statements() {
+        assert_eq!(use_statement_keyspace("USE ks"), Some("ks".to_string()));
+        assert_eq!(
+            use_statement_keyspace("use MyKs;"),
+            Some("myks".to_string())
+        );
+        assert_eq!(
+            use_statement_keyspace("USE \"MyKs\""),
+            Some("MyKs".to_string())
+        );
+        assert_eq!(use_statement_keyspace("SELECT * FROM ks.t"), None);
+        assert_eq!(use_statement_keyspace("USE"), None);
+    }
+}
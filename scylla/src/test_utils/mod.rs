@@ -0,0 +1,8 @@
+//! Utilities for testing applications and the driver itself without a running database.\
+//! Available with the `test-utilities` feature.
+
+pub mod fake_server;
+pub mod proxy;
+
+pub use fake_server::{FakeResponse, FakeServer, InMemoryConnector, Rule, StatementPattern};
+pub use proxy::{FaultAction, InjectedError, Proxy, ProxyRule, RequestCondition};
@@ -0,0 +1,563 @@
+//! Proxy between the driver and a node, injecting faults into the forwarded traffic
+use crate::frame::frame_errors::FrameError;
+use crate::frame::request::{batch::BatchStatement, ParsedRequest, RequestOpcode};
+use crate::frame::response::{Error, Response, ResponseOpcode};
+use crate::frame::{
+    parse_request_body_extensions, read_request_frame, read_response_frame, types,
+    write_request_frame, write_response_frame, FrameParams, ProtocolVersion,
+};
+use crate::statement::Consistency;
+use crate::test_utils::StatementPattern;
+use crate::transport::Compression;
+
+use bytes::{BufMut, Bytes};
+use futures::{future::RemoteHandle, stream::FuturesUnordered, FutureExt, StreamExt};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{tcp::OwnedWriteHalf, TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+// Kind of a RESULT response to PREPARE
+const PREPARED_RESULT_KIND: i32 = 0x0004;
+
+/// Proxy forwarding connections to a single node.\
+/// Requests matching a [ProxyRule] trigger its [FaultAction], the first matching rule wins.
+/// Everything else is forwarded unchanged.
+///
+/// The driver connects to all peers on the port of the contact point, so to put
+/// several nodes behind proxies each proxy has to listen on the same port on the address
+/// reported by its node, e.g. on different loopback addresses (127.0.0.1, 127.0.0.2, ...).
+///
+/// Only protocol versions up to v4 are supported, the proxy makes the driver
+/// downgrade from v5 by rejecting it, just like an older node would.
+///
+/// The proxy and all connections going through it are closed when it is dropped.
+///
+/// # Example
+/// ```rust
+/// # use std::error::Error;
+/// # async fn check_only_compiles() -> Result<(), Box<dyn Error>> {
+/// use scylla::test_utils::{FaultAction, InjectedError, Proxy, ProxyRule, RequestCondition};
+/// use scylla::test_utils::StatementPattern;
+/// use scylla::SessionBuilder;
+///
+/// let proxy = Proxy::start("127.0.0.1:9042".parse()?).await?;
+///
+/// // The first read from ks.t fails with Overloaded, the next ones reach the node
+/// proxy.add_rule(
+///     ProxyRule::new(
+///         RequestCondition::Statement(StatementPattern::Prefix("SELECT".to_string())),
+///         FaultAction::RespondWithError(InjectedError::Overloaded),
+///     )
+///     .times(1),
+/// );
+///
+/// let session = SessionBuilder::new()
+///     .known_node_addr(proxy.address())
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Proxy {
+    address: SocketAddr,
+    node_address: SocketAddr,
+    state: Arc<ProxyState>,
+    _worker_handle: RemoteHandle<()>,
+}
+
+/// Decides which requests trigger a [ProxyRule]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestCondition {
+    /// Matches every request
+    Any,
+    /// Matches requests with the given opcode
+    Opcode(RequestOpcode),
+    /// Matches QUERY and PREPARE requests with matching statement text,
+    /// EXECUTE requests of statements prepared through the proxy
+    /// and BATCH requests containing at least one matching statement
+    Statement(StatementPattern),
+}
+
+/// Fault injected when a request matches a [ProxyRule]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Closes the connection on which the request was sent, without forwarding it
+    DropConnection,
+    /// Forwards the request, but sends the response to the driver after a delay.
+    /// Other responses on the connection aren't held back
+    DelayResponse(Duration),
+    /// Forwards the request, but never sends the response to the driver
+    DropResponse,
+    /// Forwards the request, but replaces the response with an error
+    RespondWithError(InjectedError),
+}
+
+/// Errors which the proxy can send instead of a node's response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectedError {
+    /// The coordinator is overloaded (code 0x1001)
+    Overloaded,
+    /// Not enough replicas were alive to achieve the consistency (code 0x1000)
+    Unavailable {
+        consistency: Consistency,
+        required: i32,
+        alive: i32,
+    },
+    /// Replicas didn't respond to a read in time (code 0x1200)
+    ReadTimeout {
+        consistency: Consistency,
+        received: i32,
+        required: i32,
+        data_present: bool,
+    },
+}
+
+/// Fault injected by a [Proxy] into requests matching a condition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyRule {
+    pub condition: RequestCondition,
+    pub action: FaultAction,
+    /// How many more times the rule is triggered, None means no limit
+    pub remaining: Option<usize>,
+}
+
+#[derive(Default)]
+struct ProxyState {
+    rules: Mutex<Vec<ProxyRule>>,
+    // Statement texts of statements prepared through the proxy, by statement id
+    prepared: Mutex<HashMap<Bytes, String>>,
+}
+
+// What to do with the response to a request
+enum ResponseFault {
+    Delay(Duration),
+    Drop,
+    Replace(InjectedError),
+}
+
+// Per connection information passed from the request to the response direction, by stream id
+#[derive(Default)]
+struct PendingRequests {
+    faults: HashMap<i16, ResponseFault>,
+    prepares: HashMap<i16, String>,
+}
+
+impl RequestCondition {
+    fn matches(&self, opcode: RequestOpcode, statements: &[String]) -> bool {
+        match self {
+            RequestCondition::Any => true,
+            RequestCondition::Opcode(expected) => opcode == *expected,
+            RequestCondition::Statement(pattern) => statements
+                .iter()
+                .any(|statement| pattern.matches(statement)),
+        }
+    }
+}
+
+impl InjectedError {
+    fn code(&self) -> i32 {
+        match self {
+            InjectedError::Overloaded => 0x1001,
+            InjectedError::Unavailable { .. } => 0x1000,
+            InjectedError::ReadTimeout { .. } => 0x1200,
+        }
+    }
+
+    fn serialize(&self, buf: &mut impl BufMut) -> Result<(), FrameError> {
+        types::write_int(self.code(), buf);
+        match self {
+            InjectedError::Overloaded => {
+                types::write_string("Injected error: Overloaded", buf)?;
+            }
+            InjectedError::Unavailable {
+                consistency,
+                required,
+                alive,
+            } => {
+                types::write_string("Injected error: Unavailable", buf)?;
+                types::write_consistency(*consistency, buf);
+                types::write_int(*required, buf);
+                types::write_int(*alive, buf);
+            }
+            InjectedError::ReadTimeout {
+                consistency,
+                received,
+                required,
+                data_present,
+            } => {
+                types::write_string("Injected error: ReadTimeout", buf)?;
+                types::write_consistency(*consistency, buf);
+                types::write_int(*received, buf);
+                types::write_int(*required, buf);
+                buf.put_u8(*data_present as u8);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProxyRule {
+    pub fn new(condition: RequestCondition, action: FaultAction) -> Self {
+        ProxyRule {
+            condition,
+            action,
+            remaining: None,
+        }
+    }
+
+    /// Triggers the rule only for the first `times` matching requests
+    pub fn times(mut self, times: usize) -> Self {
+        self.remaining = Some(times);
+        self
+    }
+}
+
+impl Proxy {
+    /// Starts a proxy to the given node, listening on a random port of 127.0.0.1
+    pub async fn start(node_address: SocketAddr) -> Result<Proxy, std::io::Error> {
+        Proxy::bind(SocketAddr::from(([127, 0, 0, 1], 0)), node_address).await
+    }
+
+    /// Starts a proxy to the given node, listening on the given address
+    pub async fn bind(
+        address: SocketAddr,
+        node_address: SocketAddr,
+    ) -> Result<Proxy, std::io::Error> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(ProxyState::default());
+
+        let (fut, worker_handle) =
+            accept_connections(listener, node_address, state.clone()).remote_handle();
+        tokio::spawn(fut);
+
+        Ok(Proxy {
+            address,
+            node_address,
+            state,
+            _worker_handle: worker_handle,
+        })
+    }
+
+    /// Address on which the proxy listens
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Address of the node to which connections are forwarded
+    pub fn node_address(&self) -> SocketAddr {
+        self.node_address
+    }
+
+    /// Adds a rule, which is checked after all previously added rules
+    pub fn add_rule(&self, rule: ProxyRule) {
+        self.state.rules.lock().unwrap().push(rule);
+    }
+
+    /// Removes all rules, the proxy forwards everything unchanged from now on
+    pub fn clear_rules(&self) {
+        self.state.rules.lock().unwrap().clear();
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    node_address: SocketAddr,
+    state: Arc<ProxyState>,
+) {
+    // Connections are closed along with the proxy, when their handles are dropped
+    let mut connection_handles: FuturesUnordered<RemoteHandle<()>> = FuturesUnordered::new();
+
+    while let Ok((driver_stream, _)) = listener.accept().await {
+        // Handles of closed connections are dropped, so that they don't pile up
+        while let Some(Some(())) = connection_handles.next().now_or_never() {}
+
+        let (fut, handle) = proxy_connection(driver_stream, node_address, state.clone())
+            .map(|_| ())
+            .remote_handle();
+        tokio::spawn(fut);
+        connection_handles.push(handle);
+    }
+}
+
+// Response waiting to be written to the driver
+type PendingResponse = (FrameParams, ResponseOpcode, Bytes);
+
+async fn proxy_connection(
+    driver_stream: TcpStream,
+    node_address: SocketAddr,
+    state: Arc<ProxyState>,
+) -> Result<(), FrameError> {
+    let node_stream = TcpStream::connect(node_address).await?;
+    let (driver_read, driver_write) = driver_stream.into_split();
+    let (node_read, node_write) = node_stream.into_split();
+
+    let pending = Arc::new(Mutex::new(PendingRequests::default()));
+    let (sender, receiver) = mpsc::unbounded_channel::<PendingResponse>();
+
+    let requests = forward_requests(driver_read, node_write, sender.clone(), &state, &pending);
+    let responses = forward_responses(node_read, sender, &state, &pending);
+    let writer = write_responses(driver_write, receiver);
+
+    // Dropping any direction drops the whole connection
+    tokio::select! {
+        res = requests => res,
+        res = responses => res,
+        res = writer => res,
+    }
+}
+
+async fn forward_requests(
+    mut driver_read: impl tokio::io::AsyncRead + Unpin,
+    mut node_write: OwnedWriteHalf,
+    sender: mpsc::UnboundedSender<PendingResponse>,
+    state: &ProxyState,
+    pending: &Mutex<PendingRequests>,
+) -> Result<(), FrameError> {
+    let mut compression: Option<Compression> = None;
+
+    loop {
+        let (params, opcode, body) = read_request_frame(&mut driver_read).await?;
+
+        let version = match ProtocolVersion::try_from(params.version) {
+            Ok(version) if version <= ProtocolVersion::V4 => version,
+            _ => {
+                // Frames of newer versions are wrapped in segments, which the proxy doesn't parse
+                let error = Error::new(
+                    0x000A,
+                    "Protocol version not supported by the proxy".to_string(),
+                );
+                let params = FrameParams {
+                    version: ProtocolVersion::V4 as u8,
+                    flags: 0,
+                    stream: params.stream,
+                };
+                let response = Response::Error(error);
+                let body = response.to_bytes(ProtocolVersion::V4)?;
+                let _ = sender.send((params, response.opcode(), body));
+                continue;
+            }
+        };
+
+        let parsed_body = parse_request_body_extensions(params.flags, compression, body.clone())?;
+        let request = ParsedRequest::deserialize(opcode, version, &mut &*parsed_body.body)?;
+
+        let statements = state.statements(&request);
+        match &request {
+            ParsedRequest::Startup(startup) => {
+                compression = match startup.options.get("COMPRESSION").map(String::as_str) {
+                    Some("lz4") => Some(Compression::LZ4),
+                    Some("snappy") => Some(Compression::Snappy),
+                    _ => None,
+                };
+            }
+            ParsedRequest::Prepare(prepare) => {
+                pending
+                    .lock()
+                    .unwrap()
+                    .prepares
+                    .insert(params.stream, prepare.query.to_string());
+            }
+            _ => {}
+        }
+
+        let fault = match state.find_action(opcode, &statements) {
+            None => None,
+            Some(FaultAction::DropConnection) => return Ok(()),
+            Some(FaultAction::DelayResponse(delay)) => Some(ResponseFault::Delay(delay)),
+            Some(FaultAction::DropResponse) => Some(ResponseFault::Drop),
+            Some(FaultAction::RespondWithError(error)) => Some(ResponseFault::Replace(error)),
+        };
+        if let Some(fault) = fault {
+            pending.lock().unwrap().faults.insert(params.stream, fault);
+        }
+
+        write_request_frame(&mut node_write, params, opcode, body).await?;
+    }
+}
+
+async fn forward_responses(
+    mut node_read: impl tokio::io::AsyncRead + Unpin,
+    sender: mpsc::UnboundedSender<PendingResponse>,
+    state: &ProxyState,
+    pending: &Mutex<PendingRequests>,
+) -> Result<(), FrameError> {
+    loop {
+        let (params, opcode, body) = read_response_frame(&mut node_read).await?;
+
+        let (fault, prepared_statement) = {
+            let mut pending = pending.lock().unwrap();
+            (
+                pending.faults.remove(&params.stream),
+                pending.prepares.remove(&params.stream),
+            )
+        };
+
+        if let Some(statement) = prepared_statement {
+            if let Some(id) = prepared_id(opcode, params.flags, &body) {
+                state.prepared.lock().unwrap().insert(id, statement);
+            }
+        }
+
+        match fault {
+            None => {
+                let _ = sender.send((params, opcode, body));
+            }
+            Some(ResponseFault::Drop) => {}
+            Some(ResponseFault::Delay(delay)) => {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = sender.send((params, opcode, body));
+                });
+            }
+            Some(ResponseFault::Replace(error)) => {
+                let mut error_body = Vec::new();
+                error.serialize(&mut error_body)?;
+                let params = FrameParams { flags: 0, ..params };
+                let _ = sender.send((params, ResponseOpcode::Error, error_body.into()));
+            }
+        }
+    }
+}
+
+async fn write_responses(
+    mut driver_write: OwnedWriteHalf,
+    mut receiver: mpsc::UnboundedReceiver<PendingResponse>,
+) -> Result<(), FrameError> {
+    while let Some((params, opcode, body)) = receiver.recv().await {
+        write_response_frame(&mut driver_write, params, opcode, body).await?;
+    }
+    Ok(())
+}
+
+// Reads the statement id from a response to PREPARE.
+// Compressed or extended responses are skipped, EXECUTE of such statements can't be matched.
+fn prepared_id(opcode: ResponseOpcode, flags: u8, body: &[u8]) -> Option<Bytes> {
+    if opcode != ResponseOpcode::Result || flags != 0 {
+        return None;
+    }
+    let buf = &mut &*body;
+    if types::read_int(buf).ok()? != PREPARED_RESULT_KIND {
+        return None;
+    }
+    types::read_short_bytes(buf)
+        .ok()
+        .map(Bytes::copy_from_slice)
+}
+
+impl ProxyState {
+    // Texts of statements in the request, used to match RequestCondition::Statement
+    fn statements(&self, request: &ParsedRequest) -> Vec<String> {
+        match request {
+            ParsedRequest::Execute(execute) => {
+                let prepared = self.prepared.lock().unwrap();
+                prepared.get(&execute.id).cloned().into_iter().collect()
+            }
+            ParsedRequest::Batch(batch) => {
+                let prepared = self.prepared.lock().unwrap();
+                batch
+                    .statements
+                    .clone()
+                    .filter_map(|statement| match statement {
+                        BatchStatement::Query { text } => Some(text.to_string()),
+                        BatchStatement::Prepared { id } => prepared.get(id).cloned(),
+                    })
+                    .collect()
+            }
+            other => other
+                .statement_text()
+                .map(str::to_string)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn find_action(&self, opcode: RequestOpcode, statements: &[String]) -> Option<FaultAction> {
+        let mut rules = self.rules.lock().unwrap();
+        let rule = rules
+            .iter_mut()
+            .find(|rule| rule.remaining != Some(0) && rule.condition.matches(opcode, statements))?;
+
+        if let Some(remaining) = &mut rule.remaining {
+            *remaining -= 1;
+        }
+        Some(rule.action.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FaultAction, InjectedError, ProxyRule, ProxyState, RequestCondition};
+    use crate::frame::request::RequestOpcode;
+    use crate::frame::response::Error;
+    use crate::frame::types;
+    use crate::statement::Consistency;
+    use crate::test_utils::StatementPattern;
+    use crate::transport::protocol_features::ProtocolFeatures;
+
+    #[test]
+    fn rule_matching() {
+        let state = ProxyState::default();
+        state.rules.lock().unwrap().push(
+            ProxyRule::new(
+                RequestCondition::Statement(StatementPattern::Contains("ks.t".to_string())),
+                FaultAction::DropResponse,
+            )
+            .times(1),
+        );
+        state.rules.lock().unwrap().push(ProxyRule::new(
+            RequestCondition::Opcode(RequestOpcode::Options),
+            FaultAction::DropConnection,
+        ));
+
+        let statements = vec!["SELECT * FROM ks.t".to_string()];
+        assert_eq!(
+            state.find_action(RequestOpcode::Query, &statements),
+            Some(FaultAction::DropResponse)
+        );
+        // The first rule was used up
+        assert_eq!(state.find_action(RequestOpcode::Query, &statements), None);
+        assert_eq!(
+            state.find_action(RequestOpcode::Options, &[]),
+            Some(FaultAction::DropConnection)
+        );
+        assert_eq!(state.find_action(RequestOpcode::Startup, &[]), None);
+    }
+
+    #[test]
+    fn injected_errors() {
+        let errors = vec![
+            InjectedError::Overloaded,
+            InjectedError::Unavailable {
+                consistency: Consistency::Quorum,
+                required: 2,
+                alive: 1,
+            },
+            InjectedError::ReadTimeout {
+                consistency: Consistency::One,
+                received: 0,
+                required: 1,
+                data_present: false,
+            },
+        ];
+        let lengths = [0, 10, 11];
+
+        for (error, additional_length) in errors.iter().zip(lengths.iter()) {
+            let mut buf = Vec::new();
+            error.serialize(&mut buf).unwrap();
+
+            let mut body = &buf[..];
+            let parsed = Error::deserialize(&ProtocolFeatures::default(), &mut body).unwrap();
+            assert_eq!(parsed.code, error.code());
+            assert_eq!(body.len(), *additional_length);
+            if let InjectedError::Unavailable { consistency, .. } = error {
+                assert_eq!(types::read_consistency(&mut body).unwrap(), *consistency);
+            }
+        }
+    }
+}
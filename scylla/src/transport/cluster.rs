@@ -109,22 +109,22 @@ impl Cluster {
 
         let mut last_error: Option<QueryError> = None;
 
-        // Takes result of ConnectionKeeper::get_connection() and pushes it onto result list or sets last_error
-        let mut push_to_result = |get_conn_res: Result<Arc<Connection>, QueryError>| {
-            match get_conn_res {
-                Ok(conn) => result.push(conn),
+        // Takes result of ConnectionPool::get_working_connections() and appends it to result list or sets last_error
+        let mut push_to_result = |get_conns_res: Result<Vec<Arc<Connection>>, QueryError>| {
+            match get_conns_res {
+                Ok(conns) => result.extend(conns),
                 Err(e) => last_error = Some(e),
             };
         };
 
         for node in peers.values() {
             match &*node.connections.read().await {
-                NodeConnections::Single(conn_keeper) => {
-                    push_to_result(conn_keeper.get_connection().await)
+                NodeConnections::Single(pool) => {
+                    push_to_result(pool.get_working_connections().await)
                 }
                 NodeConnections::Sharded { shard_conns, .. } => {
-                    for conn_keeper in shard_conns {
-                        push_to_result(conn_keeper.get_connection().await);
+                    for pool in shard_conns {
+                        push_to_result(pool.get_working_connections().await);
                     }
                 }
             }
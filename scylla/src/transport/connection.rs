@@ -1,47 +1,95 @@
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::{future::RemoteHandle, FutureExt};
-use tokio::net::{tcp, TcpSocket, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::convert::TryFrom;
+use std::collections::{HashMap, HashSet};
 use std::io::ErrorKind;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::sync::Mutex as StdMutex;
+use std::sync::RwLock;
+use std::time::Duration;
+use uuid::Uuid;
 
-use super::errors::{BadQuery, QueryError};
+use super::errors::{BadQuery, DBError, QueryError};
 
 use crate::batch::{Batch, BatchStatement};
 use crate::frame::{
     self,
+    frame_errors::ParseError,
     request::{self, batch, execute, query, Request, RequestOpcode},
     response::{result, Response, ResponseOpcode},
     value::{BatchValues, ValueList},
-    FrameParams, RequestBodyWithExtensions,
+    FrameParams, ProtocolVersion, RequestBodyWithExtensions, ResponseBodyWithExtensions,
 };
 use crate::query::Query;
 use crate::routing::ShardInfo;
 use crate::statement::prepared_statement::PreparedStatement;
+use crate::transport::connection_pool::PoolSize;
+use crate::transport::connector::{ConnectOptions, ConnectedStream, Connector, TransportStream};
+use crate::transport::protocol_features::ProtocolFeatures;
+use crate::transport::reconnection_policy::ReconnectionPolicy;
+use crate::transport::timestamp_generator::TimestampGenerator;
 use crate::transport::Compression;
 
 pub struct Connection {
     submit_channel: mpsc::Sender<Task>,
+    orphan_notification_sender: mpsc::UnboundedSender<RequestId>,
+    next_request_id: Arc<AtomicU64>,
+    // Requests sent with send_request which haven't finished yet
+    in_flight_requests: AtomicUsize,
     _worker_handle: RemoteHandle<()>,
-    source_port: u16,
-    shard_info: Option<ShardInfo>,
+    connect_address: SocketAddr,
+    source_port: Option<u16>,
+    features: ProtocolFeatures,
     config: ConnectionConfig,
+    protocol_version: ProtocolVersion,
     is_shard_aware: bool,
 }
 
-type ResponseHandler = oneshot::Sender<TaskResponse>;
+type ResponseHandler = oneshot::Sender<Result<TaskResponse, QueryError>>;
+
+// Identifies a request, so that the router can find its stream id once the request is abandoned
+type RequestId = u64;
+
+// A connection is broken when more stream ids than this are held by abandoned requests
+const MAX_ORPHANED_STREAM_IDS: usize = 1024;
+
+/// Number of stream ids available on a connection, which limits the number of in-flight requests
+pub const MAX_STREAM_IDS: usize = i16::MAX as usize + 1;
+
+/// Receives the error which broke a connection, once it stops working
+pub type ErrorReceiver = oneshot::Receiver<QueryError>;
 
 struct Task {
+    request_id: RequestId,
     request_flags: u8,
     request_opcode: RequestOpcode,
     request_body: Bytes,
     response_handler: ResponseHandler,
+    // Framing to switch to once the request is sent and its response is received
+    framing_switch: Option<Framing>,
+}
+
+/// Format in which frames are sent over the wire
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Framing {
+    /// Frames are sent as they are - up to protocol v4, and in v5 until STARTUP completes
+    Frames,
+    /// Frames are wrapped in protocol v5 segments, which are LZ4 compressed if `compressed`
+    Segments { compressed: bool },
+}
+
+// Sends heartbeats on an idle connection, see Connection::heartbeater
+struct Heartbeat {
+    submit_channel: mpsc::Sender<Task>,
+    next_request_id: Arc<AtomicU64>,
+    interval: Duration,
+    timeout: Duration,
 }
 
 struct TaskResponse {
@@ -50,10 +98,198 @@ struct TaskResponse {
     body: Bytes,
 }
 
+pub struct QueryResponse {
+    pub response: Response,
+    pub tracing_id: Option<Uuid>,
+    pub warnings: Vec<String>,
+    pub custom_payload: Option<HashMap<String, Vec<u8>>>,
+}
+
+/// Result of a single query\
+/// Contains all rows returned by the database and some more information
+#[derive(Default, Debug)]
+pub struct QueryResult {
+    /// Rows returned by the database
+    pub rows: Option<Vec<result::Row>>,
+    /// Specifications of columns in the returned rows
+    pub col_specs: Vec<result::ColumnSpec>,
+    /// Keyspace set by a `USE` statement
+    pub set_keyspace: Option<result::SetKeyspace>,
+    /// Schema change caused by a DDL statement
+    pub schema_change: Option<result::SchemaChange>,
+    /// Warnings returned by the database
+    pub warnings: Vec<String>,
+    /// CQL Tracing uuid - can only be Some if tracing is enabled for this query
+    pub tracing_id: Option<Uuid>,
+    /// Custom payload attached by the server (or a proxy) to the response
+    pub custom_payload: Option<HashMap<String, Vec<u8>>>,
+}
+
+/// Result of Session::batch()\
+/// Contains rows only if the batch is conditional, see [BatchResult::into_lwt_result]
+#[derive(Default, Debug)]
+pub struct BatchResult {
+    /// Rows returned by the database for a conditional batch
+    pub rows: Option<Vec<result::Row>>,
+    /// Specifications of columns in the returned rows
+    pub col_specs: Vec<result::ColumnSpec>,
+    /// Warnings returned by the database
+    pub warnings: Vec<String>,
+    /// CQL Tracing uuid - can only be Some if tracing is enabled for this batch
+    pub tracing_id: Option<Uuid>,
+    /// Custom payload attached by the server (or a proxy) to the response
+    pub custom_payload: Option<HashMap<String, Vec<u8>>>,
+}
+
+impl QueryResponse {
+    pub fn into_query_result(self) -> Result<QueryResult, QueryError> {
+        let mut query_result = QueryResult {
+            warnings: self.warnings,
+            tracing_id: self.tracing_id,
+            custom_payload: self.custom_payload,
+            ..Default::default()
+        };
+
+        match self.response {
+            Response::Error(err) => return Err(err.into()),
+            Response::Result(result::Result::Rows(rs)) => {
+                query_result.rows = Some(rs.rows);
+                query_result.col_specs = rs.metadata.col_specs;
+            }
+            Response::Result(result::Result::SetKeyspace(set_keyspace)) => {
+                query_result.set_keyspace = Some(set_keyspace)
+            }
+            Response::Result(result::Result::SchemaChange(schema_change)) => {
+                query_result.schema_change = Some(schema_change)
+            }
+            Response::Result(_) => {}
+            _ => {
+                return Err(QueryError::ProtocolError(
+                    "Unexpected server response, expected Result or Error",
+                ))
+            }
+        };
+
+        Ok(query_result)
+    }
+}
+
+// Name of the column added by the database to results of lightweight transactions
+const LWT_APPLIED_COLUMN: &str = "[applied]";
+
+impl QueryResult {
+    /// Interprets the result of a lightweight transaction (e.g. `INSERT ... IF NOT EXISTS`).\
+    /// Returns None if the result doesn't contain the `[applied]` column.
+    pub fn into_lwt_result(self) -> Option<LwtResult> {
+        into_lwt_result(self.col_specs, self.rows?)
+    }
+}
+
+impl BatchResult {
+    /// Interprets the result of a batch containing conditional statements.\
+    /// Returns None if the result doesn't contain the `[applied]` column.\
+    /// When conditions on several rows fail, the database returns one row for each of them,
+    /// only the first one becomes `existing_row` - all of them are available in `rows`.
+    pub fn into_lwt_result(self) -> Option<LwtResult> {
+        into_lwt_result(self.col_specs, self.rows?)
+    }
+}
+
+fn into_lwt_result(
+    mut col_specs: Vec<result::ColumnSpec>,
+    rows: Vec<result::Row>,
+) -> Option<LwtResult> {
+    let applied_idx = col_specs
+        .iter()
+        .position(|spec| spec.name() == LWT_APPLIED_COLUMN)?;
+
+    let mut row = rows.into_iter().next()?;
+    if applied_idx >= row.columns.len() {
+        return None;
+    }
+    let applied = row.columns.remove(applied_idx)?.as_boolean()?;
+
+    col_specs.remove(applied_idx);
+
+    Some(LwtResult {
+        applied,
+        existing_row: if applied { None } else { Some(row) },
+        col_specs,
+    })
+}
+
+/// Result of a lightweight transaction,
+/// created with [QueryResult::into_lwt_result] or [BatchResult::into_lwt_result]
+#[derive(Debug)]
+pub struct LwtResult {
+    /// Whether the condition was met and the transaction was applied
+    pub applied: bool,
+    /// Values of the existing row which caused the condition to fail, without the `[applied]` column.\
+    /// None if the transaction was applied
+    pub existing_row: Option<result::Row>,
+    /// Specifications of columns in `existing_row`
+    pub col_specs: Vec<result::ColumnSpec>,
+}
+
+/// Receives warnings attached by the database to its responses,
+/// for example about a batch being too large or a tombstone threshold being hit.
+/// Warnings are also returned in [QueryResult] and [BatchResult],
+/// the handler additionally sees warnings for requests which don't return them (e.g. paged queries).
+pub trait WarningHandler: Send + Sync {
+    /// Called with all warnings received in a single response
+    /// # Arguments
+    ///
+    /// * `addr` - address of the node which sent the response
+    /// * `warnings` - nonempty list of warnings
+    fn on_warnings(&self, addr: SocketAddr, warnings: &[String]);
+}
+
+impl<F: Fn(SocketAddr, &[String]) + Send + Sync> WarningHandler for F {
+    fn on_warnings(&self, addr: SocketAddr, warnings: &[String]) {
+        self(addr, warnings)
+    }
+}
+
 #[derive(Clone)]
 pub struct ConnectionConfig {
     pub compression: Option<Compression>,
     pub tcp_nodelay: bool,
+    /// Enables TCP keepalive probes with timings configured in the operating system
+    pub tcp_keepalive: bool,
+    pub warning_handler: Option<Arc<dyn WarningHandler>>,
+    /// Highest protocol version to try, lower versions are tried
+    /// if the server doesn't support it
+    pub protocol_version: ProtocolVersion,
+    /// Lowest protocol version negotiated by connections sharing this config.\
+    /// New connections start from it, so that the version is negotiated once per session.
+    pub negotiated_protocol_version: Arc<RwLock<Option<ProtocolVersion>>>,
+    /// Generates default timestamps for requests which don't have one set explicitly
+    pub timestamp_generator: Option<Arc<dyn TimestampGenerator>>,
+    /// Keyspace set with `USE` on every new connection.\
+    /// Shared by all connections of a session, so that `Session::use_keyspace` affects them all.
+    pub used_keyspace: Arc<RwLock<Option<String>>>,
+    /// Decides how long to wait before reopening a broken connection
+    pub reconnection_policy: Arc<dyn ReconnectionPolicy>,
+    /// Default client-side timeout of requests, None means no timeout
+    pub request_timeout: Option<Duration>,
+    /// Number of requests waiting to be sent, callers wait when the queue is full
+    pub submit_queue_size: usize,
+    /// Number of requests awaiting responses, more requests fail with `TooManyInFlightRequests`
+    pub max_in_flight_requests: usize,
+    /// Idle time after which a heartbeat (OPTIONS request) is sent, None disables heartbeats
+    pub heartbeat_interval: Option<Duration>,
+    /// The connection is broken if the response to a heartbeat doesn't arrive within this time
+    pub heartbeat_timeout: Duration,
+    /// Number of connections kept to each node or shard
+    pub pool_size: PoolSize,
+    /// Connections to the regular port opened while looking for one handled by a given shard
+    pub shard_connection_attempts: usize,
+    /// Time after which connecting to the shard-aware port is abandoned for the regular port
+    pub shard_aware_port_connect_timeout: Duration,
+    /// Local address to bind connections to, used only for nodes of the same address family
+    pub local_ip_address: Option<IpAddr>,
+    /// Opens the streams over which connections talk to nodes
+    pub connector: Arc<dyn Connector>,
     /*
     These configuration options will be added in the future:
 
@@ -63,8 +299,6 @@ pub struct ConnectionConfig {
     pub use_tls: bool,
     pub tls_certificate_path: Option<String>,
 
-    pub tcp_keepalive: bool,
-
     pub load_balancing: Option<String>,
     pub retry_policy: Option<String>,
 
@@ -77,60 +311,121 @@ impl Connection {
         addr: SocketAddr,
         source_port: Option<u16>,
         config: ConnectionConfig,
-    ) -> Result<Self, std::io::Error> {
-        let stream = match source_port {
-            Some(p) => connect_with_source_port(addr, p).await?,
-            None => TcpStream::connect(addr).await?,
+    ) -> Result<(Self, ErrorReceiver), std::io::Error> {
+        let options = ConnectOptions {
+            source_port,
+            local_ip_address: config.local_ip_address,
+            tcp_nodelay: config.tcp_nodelay,
+            tcp_keepalive: config.tcp_keepalive,
         };
-        let source_port = stream.local_addr()?.port();
-        stream.set_nodelay(config.tcp_nodelay)?;
-
-        // TODO: What should be the size of the channel?
-        let (sender, receiver) = mpsc::channel(128);
+        let ConnectedStream {
+            stream,
+            source_port,
+        } = config.connector.connect(addr, &options).await?;
+
+        let (sender, receiver) = mpsc::channel(config.submit_queue_size);
+        let (error_sender, error_receiver) = oneshot::channel();
+        let (orphan_notification_sender, orphan_notification_receiver) = mpsc::unbounded_channel();
+        let next_request_id = Arc::new(AtomicU64::new(0));
+
+        let heartbeat = config.heartbeat_interval.map(|interval| Heartbeat {
+            submit_channel: sender.clone(),
+            next_request_id: next_request_id.clone(),
+            interval,
+            timeout: config.heartbeat_timeout,
+        });
 
-        let (fut, _worker_handle) = Self::router(stream, receiver).remote_handle();
+        let protocol_version = config.protocol_version;
+        let (fut, _worker_handle) = Self::router(
+            stream,
+            receiver,
+            error_sender,
+            orphan_notification_receiver,
+            heartbeat,
+            protocol_version,
+            config.max_in_flight_requests,
+        )
+        .remote_handle();
         tokio::task::spawn(fut);
 
-        Ok(Self {
+        let connection = Self {
             submit_channel: sender,
+            orphan_notification_sender,
+            next_request_id,
+            in_flight_requests: AtomicUsize::new(0),
             _worker_handle,
+            connect_address: addr,
             source_port,
-            shard_info: None,
+            features: ProtocolFeatures::default(),
             config,
+            protocol_version,
             is_shard_aware: false,
-        })
+        };
+
+        Ok((connection, error_receiver))
     }
 
     pub async fn startup(&self, options: HashMap<String, String>) -> Result<Response, QueryError> {
-        self.send_request(&request::Startup { options }, false)
-            .await
+        Ok(self
+            .send_request(
+                &request::Startup { options },
+                false,
+                false,
+                None,
+                None,
+                None,
+            )
+            .await?
+            .response)
     }
 
     pub async fn get_options(&self) -> Result<Response, QueryError> {
-        self.send_request(&request::Options {}, false).await
+        Ok(self
+            .send_request(&request::Options {}, false, false, None, None, None)
+            .await?
+            .response)
     }
 
     pub async fn prepare(&self, query: &str) -> Result<PreparedStatement, QueryError> {
-        let result = self.send_request(&request::Prepare { query }, true).await?;
+        let result = self
+            .send_request(&request::Prepare { query }, true, false, None, None, None)
+            .await?
+            .response;
         match result {
             Response::Error(err) => Err(err.into()),
-            Response::Result(result::Result::Prepared(p)) => Ok(PreparedStatement::new(
-                p.id,
-                p.prepared_metadata,
-                query.to_owned(),
-            )),
+            Response::Result(result::Result::Prepared(p)) => {
+                let is_lwt = matches!(self.features.lwt_optimization_mask,
+                    Some(mask) if p.prepared_metadata.flags & mask != 0);
+                let mut prepared =
+                    PreparedStatement::new(p.id, p.prepared_metadata, query.to_owned());
+                prepared.set_result_metadata(p.result_metadata_id, p.result_metadata);
+                prepared.set_is_lwt(is_lwt);
+                Ok(prepared)
+            }
             _ => Err(QueryError::ProtocolError(
                 "PREPARE: Unexpected server response",
             )),
         }
     }
 
+    /// Sets the keyspace used by this connection.\
+    /// `keyspace_name` is sent quoted, so it's case sensitive and has to be a valid keyspace name.
+    pub async fn use_keyspace(&self, keyspace_name: &str) -> Result<(), QueryError> {
+        let query = Query::new(format!("USE \"{}\"", keyspace_name));
+        let result = self.query(&query, &[], None).await?.response;
+        match result {
+            Response::Error(err) => Err(err.into()),
+            Response::Result(result::Result::SetKeyspace(_)) => Ok(()),
+            _ => Err(QueryError::ProtocolError("USE: Unexpected server response")),
+        }
+    }
+
     pub async fn query_single_page(
         &self,
         query: impl Into<Query>,
         values: impl ValueList,
     ) -> Result<Option<Vec<result::Row>>, QueryError> {
-        let result = self.query(&query.into(), values, None).await?;
+        let result = self.query(&query.into(), values, None).await?.response;
         match result {
             Response::Error(err) => Err(err.into()),
             Response::Result(result::Result::Rows(rs)) => Ok(Some(rs.rows)),
@@ -146,20 +441,31 @@ impl Connection {
         query: &Query,
         values: impl ValueList,
         paging_state: Option<Bytes>,
-    ) -> Result<Response, QueryError> {
+    ) -> Result<QueryResponse, QueryError> {
         let serialized_values = values.serialized()?;
 
         let query_frame = query::Query {
             contents: query.get_contents().to_owned(),
             parameters: query::QueryParameters {
                 consistency: query.get_consistency(),
-                values: &serialized_values,
+                serial_consistency: query.get_serial_consistency(),
+                timestamp: self.get_timestamp(query.get_timestamp()),
+                skip_metadata: false,
+                values: serialized_values,
                 page_size: query.get_page_size(),
                 paging_state,
             },
         };
 
-        self.send_request(&query_frame, true).await
+        self.send_request(
+            &query_frame,
+            true,
+            query.get_tracing(),
+            query.get_custom_payload(),
+            None,
+            query.get_request_timeout(),
+        )
+        .await
     }
 
     pub async fn execute(
@@ -167,27 +473,85 @@ impl Connection {
         prepared_statement: &PreparedStatement,
         values: impl ValueList,
         paging_state: Option<Bytes>,
-    ) -> Result<Response, QueryError> {
+    ) -> Result<QueryResponse, QueryError> {
         let serialized_values = values.serialized()?;
+        let result_metadata = prepared_statement.get_result_metadata();
+        let result_metadata_id = prepared_statement.get_result_metadata_id();
 
-        let execute_frame = execute::Execute {
+        // Cached metadata can be used only if the server reports its changes, which requires
+        // metadata ids. Results of LWTs depend on whether they were applied, so they're always sent.
+        let skip_metadata = self.uses_metadata_ids()
+            && result_metadata_id.is_some()
+            && result_metadata.col_count > 0
+            && !prepared_statement.is_lwt();
+
+        let execute_frame = |skip_metadata| execute::Execute {
             id: prepared_statement.get_id().to_owned(),
+            // With Scylla's extension the id is sent in protocol v4 as well, even if it's unknown
+            result_metadata_id: Some(result_metadata_id.clone().unwrap_or_default())
+                .filter(|_| self.uses_metadata_ids()),
             parameters: query::QueryParameters {
                 consistency: prepared_statement.get_consistency(),
-                values: &serialized_values,
+                serial_consistency: prepared_statement.get_serial_consistency(),
+                timestamp: self.get_timestamp(prepared_statement.get_timestamp()),
+                skip_metadata,
+                values: serialized_values.clone(),
                 page_size: prepared_statement.get_page_size(),
-                paging_state,
+                paging_state: paging_state.clone(),
             },
         };
 
-        self.send_request(&execute_frame, true).await
+        let (opcode, body_with_ext) = self
+            .send_request_raw(
+                &execute_frame(skip_metadata),
+                true,
+                prepared_statement.get_tracing(),
+                prepared_statement.get_custom_payload(),
+                prepared_statement.get_request_timeout(),
+            )
+            .await?;
+
+        let response = match self.parse_response(opcode, body_with_ext, Some(&result_metadata)) {
+            Ok(response) => response,
+            // Rows returned without metadata don't match the cached one, so the statement
+            // is executed again with full metadata, which replaces the cached one
+            Err(ParseError::NoCachedResultMetadata) => {
+                let response = self
+                    .send_request(
+                        &execute_frame(false),
+                        true,
+                        prepared_statement.get_tracing(),
+                        prepared_statement.get_custom_payload(),
+                        None,
+                        prepared_statement.get_request_timeout(),
+                    )
+                    .await?;
+                if let Response::Result(result::Result::Rows(rows)) = &response.response {
+                    prepared_statement
+                        .set_result_metadata(result_metadata_id, rows.metadata.clone());
+                }
+                response
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        // The server reports (since protocol v5) that result metadata has changed,
+        // e.g. because of an ALTER TABLE, and sends the new one along with its id
+        if let Response::Result(result::Result::Rows(rows)) = &response.response {
+            if let Some(new_metadata_id) = &rows.metadata.new_metadata_id {
+                prepared_statement
+                    .set_result_metadata(Some(new_metadata_id.clone()), rows.metadata.clone());
+            }
+        }
+
+        Ok(response)
     }
 
     pub async fn batch(
         &self,
         batch: &Batch,
         values: impl BatchValues,
-    ) -> Result<Response, QueryError> {
+    ) -> Result<QueryResponse, QueryError> {
         let statements_count = batch.get_statements().len();
         if statements_count != values.len() {
             return Err(QueryError::BadQuery(BadQuery::ValueLenMismatch(
@@ -211,70 +575,182 @@ impl Connection {
             values,
             batch_type: batch.get_type(),
             consistency: batch.get_consistency(),
+            serial_consistency: batch.get_serial_consistency(),
+            timestamp: self.get_timestamp(batch.get_timestamp()),
         };
 
-        self.send_request(&batch_frame, true).await
+        self.send_request(
+            &batch_frame,
+            true,
+            batch.get_tracing(),
+            batch.get_custom_payload(),
+            None,
+            batch.get_request_timeout(),
+        )
+        .await
+    }
+
+    // Timestamp set on the statement takes precedence over the generated one
+    fn get_timestamp(&self, statement_timestamp: Option<i64>) -> Option<i64> {
+        statement_timestamp.or_else(|| {
+            self.config
+                .timestamp_generator
+                .as_ref()
+                .map(|generator| generator.next_timestamp())
+        })
     }
 
-    // TODO: Return the response associated with that frame
     async fn send_request<R: Request>(
         &self,
         request: &R,
         compress: bool,
-    ) -> Result<Response, QueryError> {
-        let body = request.to_bytes()?;
-        let compression = if compress {
+        tracing: bool,
+        custom_payload: Option<&HashMap<String, Vec<u8>>>,
+        cached_metadata: Option<&result::ResultMetadata>,
+        request_timeout: Option<Duration>,
+    ) -> Result<QueryResponse, QueryError> {
+        let (opcode, body_with_ext) = self
+            .send_request_raw(request, compress, tracing, custom_payload, request_timeout)
+            .await?;
+        Ok(self.parse_response(opcode, body_with_ext, cached_metadata)?)
+    }
+
+    // Sends a request and returns the response body, which isn't parsed yet
+    async fn send_request_raw<R: Request>(
+        &self,
+        request: &R,
+        compress: bool,
+        tracing: bool,
+        custom_payload: Option<&HashMap<String, Vec<u8>>>,
+        request_timeout: Option<Duration>,
+    ) -> Result<(ResponseOpcode, ResponseBodyWithExtensions), QueryError> {
+        let body = request.to_bytes(self.protocol_version)?;
+        // In protocol v5 compression is applied to whole segments instead of frames
+        let compression = if compress && self.protocol_version < ProtocolVersion::V5 {
             self.config.compression
         } else {
             None
         };
-        let body_with_ext = RequestBodyWithExtensions { body };
+
+        // In protocol v5 frames are wrapped in segments once STARTUP completes
+        let framing_switch = if R::OPCODE == RequestOpcode::Startup
+            && self.protocol_version >= ProtocolVersion::V5
+        {
+            Some(Framing::Segments {
+                compressed: self.config.compression.is_some(),
+            })
+        } else {
+            None
+        };
+        let body_with_ext = RequestBodyWithExtensions {
+            body,
+            tracing,
+            custom_payload,
+        };
 
         let (flags, raw_request) =
             frame::prepare_request_body_with_extensions(body_with_ext, compression)?;
 
         let (sender, receiver) = oneshot::channel();
 
-        self.submit_channel
-            .send(Task {
-                request_flags: flags,
-                request_opcode: R::OPCODE,
-                request_body: raw_request,
-                response_handler: sender,
-            })
-            .await
-            .map_err(|_| {
-                QueryError::IOError(Arc::new(std::io::Error::new(
-                    ErrorKind::Other,
-                    "Connection broken",
-                )))
-            })?;
-
-        let task_response = receiver.await.map_err(|_| {
-            QueryError::IOError(Arc::new(std::io::Error::new(
-                ErrorKind::Other,
-                "Connection broken",
-            )))
-        })?;
+        let _in_flight = InFlightGuard::new(&self.in_flight_requests);
+        let request_id = self.next_request_id.fetch_add(1, AtomicOrdering::Relaxed);
+        let task = Task {
+            request_id,
+            request_flags: flags,
+            request_opcode: R::OPCODE,
+            request_body: raw_request,
+            response_handler: sender,
+            framing_switch,
+        };
+        let response = self.submit_and_wait(task, receiver);
+
+        // Timeout set on the statement takes precedence over the default one
+        let task_response = match request_timeout.or(self.config.request_timeout) {
+            Some(timeout) => tokio::time::timeout(timeout, response)
+                .await
+                .map_err(|_| QueryError::RequestTimeout(timeout))??,
+            None => response.await?,
+        };
+
         let body_with_ext = frame::parse_response_body_extensions(
             task_response.params.flags,
             self.config.compression,
             task_response.body,
         )?;
 
-        // TODO: Do something more sensible with warnings
-        // For now, just print them to stderr
-        for warning in body_with_ext.warnings {
-            eprintln!("Warning: {}", warning);
+        if let Some(handler) = &self.config.warning_handler {
+            if !body_with_ext.warnings.is_empty() {
+                handler.on_warnings(self.connect_address, &body_with_ext.warnings);
+            }
         }
 
-        let response = Response::deserialize(task_response.opcode, &mut &*body_with_ext.body)?;
+        Ok((task_response.opcode, body_with_ext))
+    }
 
-        Ok(response)
+    // `cached_metadata` is used to decode rows sent without result metadata
+    fn parse_response(
+        &self,
+        opcode: ResponseOpcode,
+        body_with_ext: ResponseBodyWithExtensions,
+        cached_metadata: Option<&result::ResultMetadata>,
+    ) -> Result<QueryResponse, ParseError> {
+        let response = Response::deserialize(
+            opcode,
+            self.protocol_version,
+            &self.features,
+            &mut &*body_with_ext.body,
+            cached_metadata,
+        )?;
+
+        Ok(QueryResponse {
+            response,
+            tracing_id: body_with_ext.trace_id,
+            warnings: body_with_ext.warnings,
+            custom_payload: body_with_ext.custom_payload,
+        })
     }
 
-    async fn router(mut stream: TcpStream, receiver: mpsc::Receiver<Task>) {
-        let (read_half, write_half) = stream.split();
+    async fn submit_and_wait(
+        &self,
+        task: Task,
+        receiver: oneshot::Receiver<Result<TaskResponse, QueryError>>,
+    ) -> Result<TaskResponse, QueryError> {
+        let connection_broken = || {
+            QueryError::IOError(Arc::new(std::io::Error::new(
+                ErrorKind::Other,
+                "Connection broken",
+            )))
+        };
+
+        // If the response isn't awaited until the end, e.g. because of a timeout,
+        // the router is notified that the request's stream id is orphaned
+        let mut notifier = OrphanhoodNotifier {
+            request_id: task.request_id,
+            sender: &self.orphan_notification_sender,
+            enabled: true,
+        };
+
+        self.submit_channel
+            .send(task)
+            .await
+            .map_err(|_| connection_broken())?;
+        let task_response = receiver.await.map_err(|_| connection_broken())?;
+
+        notifier.enabled = false;
+        task_response
+    }
+
+    async fn router(
+        stream: Box<dyn TransportStream>,
+        receiver: mpsc::Receiver<Task>,
+        error_sender: oneshot::Sender<QueryError>,
+        orphan_notification_receiver: mpsc::UnboundedReceiver<RequestId>,
+        heartbeat: Option<Heartbeat>,
+        protocol_version: ProtocolVersion,
+        max_in_flight_requests: usize,
+    ) {
+        let (read_half, write_half) = tokio::io::split(stream);
 
         // Why are using a mutex here?
         //
@@ -288,21 +764,63 @@ impl Connection {
         // and writer futures are run on the same fiber, and both of them
         // are carefully written in such a way that they do not hold the lock
         // across .await points. Therefore, it should not be too expensive.
-        let handler_map = StdMutex::new(ResponseHandlerMap::new());
-
-        let r = Self::reader(read_half, &handler_map);
-        let w = Self::writer(write_half, &handler_map, receiver);
-
-        // TODO: What to do with this error?
-        let _ = futures::try_join!(r, w);
+        let handler_map = StdMutex::new(ResponseHandlerMap::new(max_in_flight_requests));
+
+        // Framing the reader should switch to after receiving the response to STARTUP.
+        // Set by the writer, protected by a mutex for the same reason as handler_map.
+        let pending_framing: StdMutex<Option<Framing>> = StdMutex::new(None);
+
+        // Time when the last frame was received, updated by the reader.
+        // Protected by a mutex for the same reason as handler_map.
+        let last_activity = StdMutex::new(Instant::now());
+
+        let r = Self::reader(read_half, &handler_map, &pending_framing, &last_activity);
+        let w = Self::writer(
+            write_half,
+            &handler_map,
+            &pending_framing,
+            receiver,
+            protocol_version,
+        );
+        let o = Self::orphaner(&handler_map, orphan_notification_receiver);
+        let h = Self::heartbeater(heartbeat, &last_activity, protocol_version);
+
+        // The connection is broken once any of the futures fails.
+        // Nobody might be waiting for the error, e.g. when the Connection was dropped.
+        if let Err(error) = futures::try_join!(r, w, o, h) {
+            let _ = error_sender.send(error);
+        }
     }
 
     async fn reader(
-        mut read_half: tcp::ReadHalf<'_>,
+        mut read_half: impl AsyncRead + Unpin,
         handler_map: &StdMutex<ResponseHandlerMap>,
+        pending_framing: &StdMutex<Option<Framing>>,
+        last_activity: &StdMutex<Instant>,
     ) -> Result<(), QueryError> {
+        let mut framing = Framing::Frames;
+        // Data received in segments which doesn't form a complete frame yet
+        let mut segment_buf = BytesMut::new();
+
         loop {
-            let (params, opcode, body) = frame::read_response_frame(&mut read_half).await?;
+            let (params, opcode, body) = match framing {
+                Framing::Frames => frame::read_response_frame(&mut read_half).await?,
+                Framing::Segments { compressed } => loop {
+                    if let Some(frame) = frame::take_response_frame(&mut segment_buf)? {
+                        break frame;
+                    }
+                    let payload = frame::read_segment(&mut read_half, compressed).await?;
+                    segment_buf.extend_from_slice(&payload);
+                },
+            };
+            *last_activity.try_lock().unwrap() = Instant::now();
+
+            if opcode == ResponseOpcode::Ready || opcode == ResponseOpcode::Authenticate {
+                // STARTUP has completed, the server will now use the new framing
+                if let Some(new_framing) = pending_framing.try_lock().unwrap().take() {
+                    framing = new_framing;
+                }
+            }
 
             match params.stream.cmp(&-1) {
                 Ordering::Less => {
@@ -329,11 +847,11 @@ impl Connection {
                 // Don't care if sending of the response fails. This must
                 // mean that the receiver side was impatient and is not
                 // waiting for the result anymore.
-                let _ = handler.send(TaskResponse {
+                let _ = handler.send(Ok(TaskResponse {
                     params,
                     opcode,
                     body,
-                });
+                }));
             } else {
                 // Unsolicited frame. This should not happen and indicates
                 // a bug either in the driver, or in the database
@@ -345,48 +863,163 @@ impl Connection {
     }
 
     async fn writer(
-        mut write_half: tcp::WriteHalf<'_>,
+        mut write_half: impl AsyncWrite + Unpin,
         handler_map: &StdMutex<ResponseHandlerMap>,
+        pending_framing: &StdMutex<Option<Framing>>,
         mut task_receiver: mpsc::Receiver<Task>,
+        protocol_version: ProtocolVersion,
     ) -> Result<(), QueryError> {
+        let mut framing = Framing::Frames;
+
         // When the Connection object is dropped, the sender half
         // of the channel will be dropped, this task will return an error
         // and the whole worker will be stopped
         while let Some(task) = task_receiver.recv().await {
+            if task.response_handler.is_closed() {
+                // The request was abandoned (e.g. timed out) before it was sent
+                continue;
+            }
+
             let stream_id = {
                 // We are guaranteed here that handler_map will not be locked
                 // by anybody else, so we can do try_lock().unwrap()
                 let mut lock = handler_map.try_lock().unwrap();
-
-                if let Some(stream_id) = lock.allocate(task.response_handler) {
-                    stream_id
-                } else {
-                    // TODO: Handle this error better, for now we drop this
-                    // request and return an error to the receiver
-                    continue;
+                let max_in_flight_requests = lock.max_in_flight_requests;
+
+                match lock.allocate(task.request_id, task.response_handler) {
+                    Ok(stream_id) => stream_id,
+                    Err(response_handler) => {
+                        // Fail fast, the caller can retry on another connection or back off
+                        let _ = response_handler.send(Err(QueryError::TooManyInFlightRequests(
+                            max_in_flight_requests,
+                        )));
+                        continue;
+                    }
                 }
             };
 
             let params = frame::FrameParams {
+                version: protocol_version as u8,
                 stream: stream_id,
                 flags: task.request_flags,
-                ..Default::default()
             };
 
-            frame::write_request_frame(
-                &mut write_half,
-                params,
-                task.request_opcode,
-                task.request_body,
-            )
-            .await?;
+            match framing {
+                Framing::Frames => {
+                    frame::write_request_frame(
+                        &mut write_half,
+                        params,
+                        task.request_opcode,
+                        task.request_body,
+                    )
+                    .await?
+                }
+                Framing::Segments { compressed } => {
+                    frame::write_request_frame_in_segments(
+                        &mut write_half,
+                        params,
+                        task.request_opcode,
+                        task.request_body,
+                        compressed,
+                    )
+                    .await?
+                }
+            }
+
+            if let Some(new_framing) = task.framing_switch {
+                // Requests sent after STARTUP use the new framing,
+                // the reader switches once the server responds
+                framing = new_framing;
+                *pending_framing.try_lock().unwrap() = Some(new_framing);
+            }
         }
 
         Err(std::io::Error::new(ErrorKind::Other, "connection broken").into())
     }
 
+    // Tracks stream ids of abandoned requests. They can't be reused until their late
+    // responses arrive, so the connection is broken when there are too many of them.
+    async fn orphaner(
+        handler_map: &StdMutex<ResponseHandlerMap>,
+        mut orphan_notification_receiver: mpsc::UnboundedReceiver<RequestId>,
+    ) -> Result<(), QueryError> {
+        // The sender is owned by the Connection, the channel is closed when it's dropped
+        while let Some(request_id) = orphan_notification_receiver.recv().await {
+            // We are guaranteed here that handler_map will not be locked
+            // by anybody else, so we can do try_lock().unwrap()
+            let orphaned_count = handler_map.try_lock().unwrap().mark_orphaned(request_id);
+
+            if orphaned_count > MAX_ORPHANED_STREAM_IDS {
+                return Err(QueryError::TooManyOrphanedStreamIds(orphaned_count));
+            }
+        }
+        Ok(())
+    }
+
+    // Sends OPTIONS when no frame was received for the heartbeat interval.
+    // If the response doesn't arrive in time, the connection is assumed to be dead,
+    // e.g. silently dropped by a NAT or a load balancer.
+    async fn heartbeater(
+        heartbeat: Option<Heartbeat>,
+        last_activity: &StdMutex<Instant>,
+        protocol_version: ProtocolVersion,
+    ) -> Result<(), QueryError> {
+        let heartbeat = match heartbeat {
+            Some(heartbeat) => heartbeat,
+            None => return Ok(()),
+        };
+
+        loop {
+            let idle_since = *last_activity.try_lock().unwrap();
+            tokio::time::sleep_until(idle_since + heartbeat.interval).await;
+            if *last_activity.try_lock().unwrap() != idle_since {
+                // A frame was received in the meantime, the connection isn't idle
+                continue;
+            }
+
+            let (response_handler, receiver) = oneshot::channel();
+            let task = Task {
+                request_id: heartbeat
+                    .next_request_id
+                    .fetch_add(1, AtomicOrdering::Relaxed),
+                request_flags: 0,
+                request_opcode: RequestOpcode::Options,
+                request_body: request::Options {}.to_bytes(protocol_version)?,
+                response_handler,
+                framing_switch: None,
+            };
+
+            let response = async {
+                // The router owns the receiving end, so sending can't fail
+                let _ = heartbeat.submit_channel.send(task).await;
+                receiver.await
+            };
+            // An error response (e.g. TooManyInFlightRequests) means the connection is busy, not dead
+            if tokio::time::timeout(heartbeat.timeout, response)
+                .await
+                .is_err()
+            {
+                return Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    "Heartbeat response didn't arrive in time",
+                )
+                .into());
+            }
+        }
+    }
+
     pub fn get_shard_info(&self) -> &Option<ShardInfo> {
-        &self.shard_info
+        &self.features.shard_info
+    }
+
+    /// Number of requests sent on this connection which haven't finished yet
+    pub fn get_in_flight_requests(&self) -> usize {
+        self.in_flight_requests.load(AtomicOrdering::Relaxed)
+    }
+
+    /// Features reported by the server in response to OPTIONS
+    pub fn get_protocol_features(&self) -> &ProtocolFeatures {
+        &self.features
     }
 
     /// Are we connected to Scylla's shard aware port?
@@ -395,12 +1028,28 @@ impl Connection {
         self.is_shard_aware
     }
 
-    pub fn get_source_port(&self) -> u16 {
+    pub fn get_connect_address(&self) -> SocketAddr {
+        self.connect_address
+    }
+
+    /// Local port of the connection, None if the transport doesn't have ports
+    pub fn get_source_port(&self) -> Option<u16> {
         self.source_port
     }
 
-    fn set_shard_info(&mut self, shard_info: Option<ShardInfo>) {
-        self.shard_info = shard_info
+    /// Whether result metadata ids are used, in protocol v5 or through Scylla's extension.\
+    /// Only then the server reports that result metadata of a prepared statement has changed.
+    pub fn uses_metadata_ids(&self) -> bool {
+        self.features.uses_metadata_ids(self.protocol_version)
+    }
+
+    /// Protocol version negotiated with the server
+    pub fn get_protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    fn set_protocol_features(&mut self, features: ProtocolFeatures) {
+        self.features = features;
     }
 
     fn set_is_shard_aware(&mut self, is_shard_aware: bool) {
@@ -412,7 +1061,7 @@ pub async fn open_connection(
     addr: SocketAddr,
     source_port: Option<u16>,
     config: ConnectionConfig,
-) -> Result<Connection, QueryError> {
+) -> Result<(Connection, ErrorReceiver), QueryError> {
     open_named_connection(
         addr,
         source_port,
@@ -427,41 +1076,85 @@ pub async fn open_named_connection(
     source_port: Option<u16>,
     config: ConnectionConfig,
     driver_name: Option<String>,
-) -> Result<Connection, QueryError> {
+) -> Result<(Connection, ErrorReceiver), QueryError> {
+    let mut config = config;
+    let negotiated_version = *config.negotiated_protocol_version.read().unwrap();
+
+    match negotiated_version {
+        Some(negotiated) => config.protocol_version = config.protocol_version.min(negotiated),
+        // A connection rejected because of its protocol version leaves its source port
+        // in TIME_WAIT, so lower versions can't be tried from the same port.
+        // The version is negotiated on a connection from a port chosen by the system first.
+        None if source_port.is_some() => {
+            let (connection, _) =
+                negotiate_connection(addr, None, config.clone(), driver_name.clone()).await?;
+            config.protocol_version = connection.get_protocol_version();
+        }
+        None => {}
+    }
+
+    negotiate_connection(addr, source_port, config, driver_name).await
+}
+
+// Opens a connection with the highest version accepted by the server, not higher than
+// the configured one. Lower versions are tried only on connections from system-chosen ports.
+async fn negotiate_connection(
+    addr: SocketAddr,
+    source_port: Option<u16>,
+    mut config: ConnectionConfig,
+    driver_name: Option<String>,
+) -> Result<(Connection, ErrorReceiver), QueryError> {
+    loop {
+        let result = setup_connection(addr, source_port, config.clone(), driver_name.clone()).await;
+
+        // The server responds with a protocol error if it doesn't support the requested
+        // version. In such case reconnect and try again with a lower version.
+        match (result, config.protocol_version.downgrade()) {
+            (Err(QueryError::DBError(DBError::ErrorMsg(PROTOCOL_ERROR_CODE, _))), Some(lower))
+                if source_port.is_none() =>
+            {
+                config.protocol_version = lower;
+            }
+            (Ok((connection, error_receiver)), _) => {
+                let mut negotiated = config.negotiated_protocol_version.write().unwrap();
+                let version = connection.get_protocol_version();
+                *negotiated = Some(negotiated.map_or(version, |other| other.min(version)));
+                return Ok((connection, error_receiver));
+            }
+            (result, _) => return result,
+        }
+    }
+}
+
+async fn setup_connection(
+    addr: SocketAddr,
+    source_port: Option<u16>,
+    config: ConnectionConfig,
+    driver_name: Option<String>,
+) -> Result<(Connection, ErrorReceiver), QueryError> {
     // TODO: shouldn't all this logic be in Connection::new?
-    let mut connection = Connection::new(addr, source_port, config.clone()).await?;
+    let (mut connection, error_receiver) =
+        Connection::new(addr, source_port, config.clone()).await?;
 
     let options_result = connection.get_options().await?;
 
-    let (shard_info, supported_compression, shard_aware_port) = match options_result {
-        Response::Supported(mut supported) => {
-            let shard_info = ShardInfo::try_from(&supported.options).ok();
-            let supported_compression = supported
-                .options
-                .remove("COMPRESSION")
-                .unwrap_or_else(Vec::new);
-            let shard_aware_port = supported
-                .options
-                .remove("SCYLLA_SHARD_AWARE_PORT")
-                .unwrap_or_else(Vec::new)
-                .into_iter()
-                .next()
-                .and_then(|p| p.parse::<u16>().ok());
-            (shard_info, supported_compression, shard_aware_port)
-        }
-        _ => (None, Vec::new(), None),
+    let features = match options_result {
+        Response::Supported(supported) => ProtocolFeatures::parse(&supported.options),
+        Response::Error(err) => return Err(err.into()),
+        _ => ProtocolFeatures::default(),
     };
-    connection.set_shard_info(shard_info);
-    connection.set_is_shard_aware(Some(addr.port()) == shard_aware_port);
+    connection.set_is_shard_aware(Some(addr.port()) == features.shard_aware_port);
 
     let mut options = HashMap::new();
-    options.insert("CQL_VERSION".to_string(), "4.0.0".to_string()); // FIXME: hardcoded values
+    features.add_startup_options(&mut options);
     if let Some(name) = driver_name {
         options.insert("DRIVER_NAME".to_string(), name);
     }
-    if let Some(compression) = &config.compression {
-        let compression_str = compression.to_string();
-        if supported_compression.iter().any(|c| c == &compression_str) {
+    if let Some(compression) = config.compression {
+        // Protocol v5 only allows LZ4 compression of segments
+        let allowed_by_protocol =
+            connection.protocol_version < ProtocolVersion::V5 || compression == Compression::LZ4;
+        if allowed_by_protocol && features.supports_compression(compression) {
             // Compression is reported to be supported by the server,
             // request it from the server
             options.insert("COMPRESSION".to_string(), compression.to_string());
@@ -470,10 +1163,13 @@ pub async fn open_named_connection(
             connection.config.compression = None;
         }
     }
+    connection.set_protocol_features(features);
+
     let result = connection.startup(options).await?;
     match result {
         Response::Ready => {}
         Response::Authenticate => unimplemented!("Authentication is not yet implemented"),
+        Response::Error(err) => return Err(err.into()),
         _ => {
             return Err(QueryError::ProtocolError(
                 "Unexpected response to STARTUP message",
@@ -481,43 +1177,110 @@ pub async fn open_named_connection(
         }
     }
 
-    Ok(connection)
+    // Connections opened after Session::use_keyspace() have to use the keyspace as well
+    let used_keyspace = config.used_keyspace.read().unwrap().clone();
+    if let Some(keyspace_name) = used_keyspace {
+        connection.use_keyspace(&keyspace_name).await?;
+    }
+
+    Ok((connection, error_receiver))
+}
+
+// Error code sent by the server when e.g. the requested protocol version is not supported
+const PROTOCOL_ERROR_CODE: i32 = 0x000A;
+
+// Notifies the router when dropped, unless disabled once the response is received
+struct OrphanhoodNotifier<'a> {
+    request_id: RequestId,
+    sender: &'a mpsc::UnboundedSender<RequestId>,
+    enabled: bool,
+}
+
+impl Drop for OrphanhoodNotifier<'_> {
+    fn drop(&mut self) {
+        if self.enabled {
+            // The router might have already stopped, then there is nothing to track
+            let _ = self.sender.send(self.request_id);
+        }
+    }
+}
+
+// Counts a request as in flight for as long as it's alive
+struct InFlightGuard<'a> {
+    counter: &'a AtomicUsize,
 }
 
-async fn connect_with_source_port(
-    addr: SocketAddr,
-    source_port: u16,
-) -> Result<TcpStream, std::io::Error> {
-    // TODO: handle ipv6?
-    let socket = TcpSocket::new_v4()?;
-    let source_addr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
-    socket.bind(SocketAddr::new(source_addr, source_port))?;
-    Ok(socket.connect(addr).await?)
+impl<'a> InFlightGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, AtomicOrdering::Relaxed);
+        InFlightGuard { counter }
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, AtomicOrdering::Relaxed);
+    }
 }
 
 struct ResponseHandlerMap {
     stream_set: StreamIDSet,
-    handlers: HashMap<i16, ResponseHandler>,
+    handlers: HashMap<i16, (RequestId, ResponseHandler)>,
+    request_to_stream: HashMap<RequestId, i16>,
+    // Streams of abandoned requests, freed once their responses arrive
+    orphaned: HashSet<i16>,
+    // Orphaned streams count as in-flight, they can't be used until their responses arrive
+    max_in_flight_requests: usize,
 }
 
 impl ResponseHandlerMap {
-    pub fn new() -> Self {
+    pub fn new(max_in_flight_requests: usize) -> Self {
         Self {
             stream_set: StreamIDSet::new(),
             handlers: HashMap::new(),
+            request_to_stream: HashMap::new(),
+            orphaned: HashSet::new(),
+            max_in_flight_requests: std::cmp::min(max_in_flight_requests, MAX_STREAM_IDS),
         }
     }
 
-    pub fn allocate(&mut self, response_handler: ResponseHandler) -> Option<i16> {
-        let stream_id = self.stream_set.allocate()?;
-        let prev_handler = self.handlers.insert(stream_id, response_handler);
+    /// Allocates a stream id for the request, returns the handler back if the limit
+    /// of in-flight requests is reached
+    pub fn allocate(
+        &mut self,
+        request_id: RequestId,
+        response_handler: ResponseHandler,
+    ) -> Result<i16, ResponseHandler> {
+        if self.handlers.len() >= self.max_in_flight_requests {
+            return Err(response_handler);
+        }
+        let stream_id = match self.stream_set.allocate() {
+            Some(stream_id) => stream_id,
+            None => return Err(response_handler),
+        };
+        let prev_handler = self
+            .handlers
+            .insert(stream_id, (request_id, response_handler));
         assert!(prev_handler.is_none());
-        Some(stream_id)
+        self.request_to_stream.insert(request_id, stream_id);
+        Ok(stream_id)
     }
 
     pub fn take(&mut self, stream_id: i16) -> Option<ResponseHandler> {
         self.stream_set.free(stream_id);
-        self.handlers.remove(&stream_id)
+        self.orphaned.remove(&stream_id);
+        let (request_id, handler) = self.handlers.remove(&stream_id)?;
+        self.request_to_stream.remove(&request_id);
+        Some(handler)
+    }
+
+    /// Marks the stream of an abandoned request as orphaned, returns the number of orphaned streams.\
+    /// Requests which weren't sent or already got their response are ignored.
+    pub fn mark_orphaned(&mut self, request_id: RequestId) -> usize {
+        if let Some(stream_id) = self.request_to_stream.get(&request_id) {
+            self.orphaned.insert(*stream_id);
+        }
+        self.orphaned.len()
     }
 }
 
@@ -527,7 +1290,7 @@ struct StreamIDSet {
 
 impl StreamIDSet {
     pub fn new() -> Self {
-        const BITMAP_SIZE: usize = (std::i16::MAX as usize + 1) / 64;
+        const BITMAP_SIZE: usize = MAX_STREAM_IDS / 64;
         Self {
             used_bitmap: vec![0; BITMAP_SIZE].into_boxed_slice(),
         }
@@ -551,3 +1314,129 @@ impl StreamIDSet {
         self.used_bitmap[block_id] &= !(1 << off);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{open_connection, ResponseHandlerMap, MAX_STREAM_IDS};
+    use crate::frame::ProtocolVersion;
+    use crate::test_utils::FakeServer;
+    use crate::transport::session::SessionConfig;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn orphaned_streams() {
+        let mut map = ResponseHandlerMap::new(MAX_STREAM_IDS);
+
+        let (sender, receiver) = oneshot::channel();
+        let stream_0 = map.allocate(0, sender).ok().unwrap();
+        let (sender, _receiver) = oneshot::channel();
+        let stream_1 = map.allocate(1, sender).ok().unwrap();
+
+        // Request 0 was abandoned
+        drop(receiver);
+        assert_eq!(map.mark_orphaned(0), 1);
+        // Unknown requests, e.g. abandoned before being sent, aren't orphaned
+        assert_eq!(map.mark_orphaned(7), 1);
+
+        // The orphaned stream isn't reused until its response arrives
+        let (sender, _receiver) = oneshot::channel();
+        let stream_2 = map.allocate(2, sender).ok().unwrap();
+        assert!(stream_2 != stream_0 && stream_2 != stream_1);
+
+        assert!(map.take(stream_0).is_some());
+        assert_eq!(map.mark_orphaned(1), 1);
+        assert!(map.take(stream_1).is_some());
+        assert_eq!(map.mark_orphaned(2), 1);
+        assert!(map.take(stream_2).is_some());
+        assert_eq!(map.orphaned.len(), 0);
+
+        // Freed stream ids are reused
+        let (sender, _receiver) = oneshot::channel();
+        assert_eq!(map.allocate(3, sender).ok(), Some(stream_0));
+        // Requests which already got their response aren't orphaned
+        assert_eq!(map.mark_orphaned(0), 0);
+    }
+
+    #[test]
+    fn in_flight_requests_limit() {
+        let mut map = ResponseHandlerMap::new(2);
+
+        let (sender, _receiver_0) = oneshot::channel();
+        let stream_0 = map.allocate(0, sender).ok().unwrap();
+        let (sender, _receiver_1) = oneshot::channel();
+        assert!(map.allocate(1, sender).is_ok());
+
+        let (sender, _receiver_2) = oneshot::channel();
+        assert!(map.allocate(2, sender).is_err());
+
+        // Orphaned streams still count, until their responses arrive
+        map.mark_orphaned(0);
+        let (sender, _receiver_2) = oneshot::channel();
+        assert!(map.allocate(2, sender).is_err());
+
+        map.take(stream_0);
+        let (sender, _receiver_2) = oneshot::channel();
+        assert!(map.allocate(2, sender).is_ok());
+    }
+
+    #[tokio::test]
+    async fn protocol_version_negotiation() {
+        // The server supports versions up to 4, the driver starts with 5
+        let server = FakeServer::start().await.unwrap();
+        let config = SessionConfig::new().get_connection_config();
+        assert_eq!(config.protocol_version, ProtocolVersion::V5);
+
+        // A rejected connection leaves its source port in TIME_WAIT,
+        // so the version is negotiated from a port chosen by the system first
+        let mut bound_connection = None;
+        for source_port in 21000..21100 {
+            if let Ok((connection, _)) =
+                open_connection(server.address(), Some(source_port), config.clone()).await
+            {
+                assert_eq!(connection.get_source_port(), Some(source_port));
+                bound_connection = Some(connection);
+                break;
+            }
+        }
+        let bound_connection = bound_connection.unwrap();
+        assert_eq!(bound_connection.get_protocol_version(), ProtocolVersion::V4);
+
+        // Other connections sharing the config start from the negotiated version
+        assert_eq!(
+            *config.negotiated_protocol_version.read().unwrap(),
+            Some(ProtocolVersion::V4)
+        );
+        let (connection, _) = open_connection(server.address(), None, config)
+            .await
+            .unwrap();
+        assert_eq!(connection.get_protocol_version(), ProtocolVersion::V4);
+    }
+
+    #[tokio::test]
+    async fn ipv6_connections() {
+        let server = FakeServer::bind("[::1]:0".parse().unwrap()).await.unwrap();
+        let mut config = SessionConfig::new().get_connection_config();
+
+        // Connections are bound to the local address and the source port, if they are given.
+        // Some ports might be taken, so a few of them are tried.
+        config.local_ip_address = Some(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        let mut bound_connection = None;
+        for source_port in 20000..20100 {
+            if let Ok((connection, _)) =
+                open_connection(server.address(), Some(source_port), config.clone()).await
+            {
+                assert_eq!(connection.get_source_port(), Some(source_port));
+                bound_connection = Some(connection);
+                break;
+            }
+        }
+        assert!(bound_connection.is_some());
+
+        // Local address of the other family isn't used
+        config.local_ip_address = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        open_connection(server.address(), None, config)
+            .await
+            .unwrap();
+    }
+}
@@ -3,16 +3,21 @@ use crate::routing::ShardInfo;
 use crate::transport::errors::QueryError;
 use crate::transport::{
     connection,
-    connection::{Connection, ConnectionConfig},
+    connection::{Connection, ConnectionConfig, ErrorReceiver},
 };
 
 use futures::{future::RemoteHandle, FutureExt};
+use std::collections::HashMap;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-/// ConnectionKeeper keeps a Connection to some address and works to keep it open
+/// ConnectionKeeper keeps a Connection to some address and works to keep it open.\
+/// A broken connection is reopened after a delay decided by the
+/// [ReconnectionPolicy](crate::transport::reconnection_policy::ReconnectionPolicy).
 pub struct ConnectionKeeper {
     conn_state_receiver: tokio::sync::watch::Receiver<ConnectionState>,
+    shard_target: Arc<Mutex<Option<ShardTarget>>>,
     _worker_handle: RemoteHandle<()>,
 }
 
@@ -20,20 +25,65 @@ pub struct ConnectionKeeper {
 pub enum ConnectionState {
     Initializing, // First connect attempt ongoing
     Connected(Arc<Connection>),
-    Broken(QueryError),
+    Broken(QueryError), // Waiting to reconnect
 }
 
 /// Works in the background to keep the connection open
 struct ConnectionKeeperWorker {
     address: SocketAddr,
     config: ConnectionConfig,
-    shard_info: Option<ShardInfo>,
+    // Shared with the ConnectionKeeper, which can change it
+    shard_target: Arc<Mutex<Option<ShardTarget>>>,
 
     shard_info_sender: Option<ShardInfoSender>,
     conn_state_sender: tokio::sync::watch::Sender<ConnectionState>,
 }
 
-pub type ShardInfoSender = Arc<std::sync::Mutex<tokio::sync::watch::Sender<Option<ShardInfo>>>>;
+/// Shard of a node to which a [ConnectionKeeper] connects
+#[derive(Clone)]
+pub struct ShardTarget {
+    /// Sharding parameters of the node, the keeper connects to shard number `shard_info.shard`
+    pub shard_info: ShardInfo,
+    /// Port on which the shard is chosen based on the source port, used to connect
+    /// to the right shard. If it seems blocked by a firewall, the regular port is used
+    /// and the shard-aware one is tried again when reconnecting.
+    pub shard_aware_port: Option<u16>,
+    /// Connections exchanged with keepers of other shards of the node,
+    /// used to fill all shards when the shard-aware port can't be used
+    pub spare_connections: Arc<SpareConnections>,
+}
+
+/// Sharding parameters of a node, reported after opening each connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeShardingInfo {
+    /// Includes the shard handling the reporting connection
+    pub shard_info: ShardInfo,
+    pub shard_aware_port: Option<u16>,
+}
+
+pub type ShardInfoSender = Arc<Mutex<tokio::sync::watch::Sender<Option<NodeShardingInfo>>>>;
+
+/// Connections to the regular port of a node, which landed on a shard other than the one
+/// needed by the keeper which opened them.\
+/// They are handed over to keepers looking for a connection to their shard,
+/// connections which aren't needed by any keeper are closed.
+#[derive(Default)]
+pub struct SpareConnections {
+    shards: Mutex<HashMap<u16, SpareShard>>,
+}
+
+#[derive(Default)]
+struct SpareShard {
+    // Keepers currently looking for a connection to this shard
+    searching: usize,
+    connections: Vec<(Arc<Connection>, ErrorReceiver)>,
+}
+
+// Registers a keeper as looking for a connection to the shard for as long as it's alive
+struct ShardSearch<'a> {
+    spares: &'a SpareConnections,
+    shard: u16,
+}
 
 impl ConnectionKeeper {
     /// Creates new ConnectionKeeper that starts a connection in the background
@@ -41,21 +91,22 @@ impl ConnectionKeeper {
     ///
     /// * `address` - IP address to connect to
     /// * `compression` - preferred compression method to use
-    /// * `shard_info` - ShardInfo to use, will connect to shard number `shard_info.shard`
+    /// * `shard_target` - shard to connect to, None if the node isn't sharded
     /// * `shard_info_sender` - channel to send new ShardInfo after each connection creation
     pub fn new(
         address: SocketAddr,
         config: ConnectionConfig,
-        shard_info: Option<ShardInfo>,
+        shard_target: Option<ShardTarget>,
         shard_info_sender: Option<ShardInfoSender>,
     ) -> Self {
         let (conn_state_sender, conn_state_receiver) =
             tokio::sync::watch::channel(ConnectionState::Initializing);
+        let shard_target = Arc::new(Mutex::new(shard_target));
 
         let worker = ConnectionKeeperWorker {
             address,
             config,
-            shard_info,
+            shard_target: shard_target.clone(),
             shard_info_sender,
             conn_state_sender,
         };
@@ -65,10 +116,17 @@ impl ConnectionKeeper {
 
         ConnectionKeeper {
             conn_state_receiver,
+            shard_target,
             _worker_handle: worker_handle,
         }
     }
 
+    /// Changes the shard to connect to, e.g. after the node has resharded.\
+    /// The current connection is kept, the new target is used when reconnecting.
+    pub fn set_shard_target(&self, shard_target: Option<ShardTarget>) {
+        *self.shard_target.lock().unwrap() = shard_target;
+    }
+
     /// Get current connection state, returns immediately
     pub fn connection_state(&self) -> ConnectionState {
         self.conn_state_receiver.borrow().clone()
@@ -109,42 +167,229 @@ impl ConnectionKeeper {
 
 impl ConnectionKeeperWorker {
     pub async fn work(self) {
-        let cur_connection = self.open_new_connection().await;
-
-        match &cur_connection {
-            Ok(conn) => {
-                let _ = self
-                    .conn_state_sender
-                    .send(ConnectionState::Connected(conn.clone()));
-
-                let new_shard_info: Option<ShardInfo> = conn.get_shard_info().clone();
-
-                if let Some(sender) = &self.shard_info_sender {
-                    // Ignore sending error
-                    // If no one wants to get shard_info that's OK
-                    // If lock is poisoned do nothing
-                    if let Ok(sender_locked) = sender.lock() {
-                        let _ = sender_locked.send(new_shard_info);
-                    }
+        // Failed attempts since the connection last worked
+        let mut attempt: u32 = 0;
+
+        loop {
+            match self.open_new_connection().await {
+                Ok((conn, error_receiver)) => {
+                    attempt = 0;
+
+                    let _ = self
+                        .conn_state_sender
+                        .send(ConnectionState::Connected(conn.clone()));
+                    self.send_shard_info(&conn);
+
+                    // Wait for the connection to fail. The sender is dropped without sending
+                    // an error only when the router stops, which doesn't happen while we hold conn
+                    let error = error_receiver.await.unwrap_or_else(|_| {
+                        QueryError::IOError(Arc::new(std::io::ErrorKind::ConnectionAborted.into()))
+                    });
+                    let _ = self.conn_state_sender.send(ConnectionState::Broken(error));
+                }
+                Err(e) => {
+                    let _ = self.conn_state_sender.send(ConnectionState::Broken(e));
                 }
+            };
+
+            let delay = self.config.reconnection_policy.next_delay(attempt);
+            attempt = attempt.saturating_add(1);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn send_shard_info(&self, conn: &Connection) {
+        let features = conn.get_protocol_features();
+        let new_shard_info = features
+            .shard_info
+            .clone()
+            .map(|shard_info| NodeShardingInfo {
+                shard_info,
+                shard_aware_port: features.shard_aware_port,
+            });
+
+        if let Some(sender) = &self.shard_info_sender {
+            // Ignore sending error
+            // If no one wants to get shard_info that's OK
+            // If lock is poisoned do nothing
+            if let Ok(sender_locked) = sender.lock() {
+                let _ = sender_locked.send(new_shard_info);
             }
-            Err(e) => {
-                let _ = self
-                    .conn_state_sender
-                    .send(ConnectionState::Broken(e.clone()));
-            } // TODO: Wait for connection to fail, then create new, loop it
-        };
+        }
     }
 
-    async fn open_new_connection(&self) -> Result<Arc<Connection>, QueryError> {
-        let mut source_port: Option<u16> = None;
-        if let Some(info) = &self.shard_info {
-            source_port = Some(info.draw_source_port_for_shard(info.shard.into()));
+    async fn open_new_connection(&self) -> Result<(Arc<Connection>, ErrorReceiver), QueryError> {
+        let shard_target: Option<ShardTarget> = self.shard_target.lock().unwrap().clone();
+
+        if let Some(ShardTarget {
+            shard_info,
+            shard_aware_port: Some(port),
+            ..
+        }) = &shard_target
+        {
+            let shard_aware_address = SocketAddr::new(self.address.ip(), *port);
+            let connect_to_shard = tokio::time::timeout(
+                self.config.shard_aware_port_connect_timeout,
+                self.open_connection_to_shard(shard_aware_address, shard_info),
+            );
+
+            match connect_to_shard.await {
+                Ok(Ok((new_conn, error_receiver))) => {
+                    return Ok((Arc::new(new_conn), error_receiver))
+                }
+                // A firewall blocking the port drops or rejects connections, the regular port
+                // is used instead. The shard-aware port is tried again on the next reconnect.
+                Err(_) => {}
+                Ok(Err(QueryError::IOError(e))) if e.kind() == ErrorKind::ConnectionRefused => {}
+                // All source ports of the shard are taken, which doesn't last
+                Ok(Err(QueryError::IOError(e)))
+                    if e.kind() == ErrorKind::AddrInUse
+                        || e.kind() == ErrorKind::AddrNotAvailable => {}
+                Ok(Err(e)) => return Err(e),
+            }
         }
 
-        let new_conn =
-            connection::open_connection(self.address, source_port, self.config.clone()).await?;
+        match &shard_target {
+            Some(target) => self.open_connection_filling_shard(target).await,
+            None => {
+                let (new_conn, error_receiver) =
+                    connection::open_connection(self.address, None, self.config.clone()).await?;
+                Ok((Arc::new(new_conn), error_receiver))
+            }
+        }
+    }
+
+    // The node assigns connections to the regular port to shards on its own.
+    // Opens connections until one lands on the shard, or takes one opened by another keeper.
+    // Connections landing on other shards are handed over to their keepers.
+    async fn open_connection_filling_shard(
+        &self,
+        target: &ShardTarget,
+    ) -> Result<(Arc<Connection>, ErrorReceiver), QueryError> {
+        let shard = target.shard_info.shard;
+        let spares = &target.spare_connections;
+        let _search = spares.start_search(shard);
+
+        for _ in 0..self.config.shard_connection_attempts {
+            if let Some(spare) = spares.take(shard) {
+                return Ok(spare);
+            }
+
+            let (new_conn, error_receiver) =
+                connection::open_connection(self.address, None, self.config.clone()).await?;
+            // The node might have resharded, then the connection is used right away
+            // and the node's connections are rebuilt
+            let new_conn_shard = new_conn
+                .get_shard_info()
+                .as_ref()
+                .filter(|info| {
+                    info.nr_shards == target.shard_info.nr_shards
+                        && info.msb_ignore == target.shard_info.msb_ignore
+                })
+                .map(|info| info.shard);
+            let new_connection = (Arc::new(new_conn), error_receiver);
+
+            match new_conn_shard {
+                Some(other_shard) if other_shard != shard => {
+                    // The connection is closed unless another keeper needs it
+                    let _ = spares.offer(other_shard, new_connection);
+                }
+                _ => return Ok(new_connection),
+            }
+        }
+
+        // None of the connections landed on the shard. A connection to another shard isn't used
+        // in its place, it would stay connected there for good. No more connections are opened,
+        // the reconnection policy decides when to try again.
+        Err(QueryError::IOError(Arc::new(std::io::Error::new(
+            ErrorKind::NotConnected,
+            format!(
+                "None of {} connections to the regular port landed on shard {}",
+                self.config.shard_connection_attempts, shard
+            ),
+        ))))
+    }
+
+    // Connects to the shard-aware port from a source port belonging to the shard,
+    // trying other source ports if the drawn one is already in use
+    async fn open_connection_to_shard(
+        &self,
+        shard_aware_address: SocketAddr,
+        shard_info: &ShardInfo,
+    ) -> Result<(Connection, ErrorReceiver), QueryError> {
+        let mut last_error: Option<QueryError> = None;
+
+        for source_port in shard_info.iter_source_ports_for_shard(shard_info.shard.into()) {
+            match connection::open_connection(
+                shard_aware_address,
+                Some(source_port),
+                self.config.clone(),
+            )
+            .await
+            {
+                Err(QueryError::IOError(e))
+                    if e.kind() == ErrorKind::AddrInUse
+                        || e.kind() == ErrorKind::AddrNotAvailable =>
+                {
+                    last_error = Some(QueryError::IOError(e));
+                }
+                result => return result,
+            }
+        }
+
+        // All source ports of the shard are taken
+        Err(last_error.unwrap())
+    }
+}
+
+impl SpareConnections {
+    fn start_search(&self, shard: u16) -> ShardSearch<'_> {
+        self.shards
+            .lock()
+            .unwrap()
+            .entry(shard)
+            .or_default()
+            .searching += 1;
+
+        ShardSearch {
+            spares: self,
+            shard,
+        }
+    }
 
-        Ok(Arc::new(new_conn))
+    // Takes a connection to the shard opened by another keeper
+    fn take(&self, shard: u16) -> Option<(Arc<Connection>, ErrorReceiver)> {
+        self.shards
+            .lock()
+            .unwrap()
+            .get_mut(&shard)
+            .and_then(|spare_shard| spare_shard.connections.pop())
+    }
+
+    // Keeps the connection for a keeper looking for its shard, returns it back if there is none
+    fn offer(
+        &self,
+        shard: u16,
+        connection: (Arc<Connection>, ErrorReceiver),
+    ) -> Option<(Arc<Connection>, ErrorReceiver)> {
+        let mut shards = self.shards.lock().unwrap();
+        match shards.get_mut(&shard) {
+            Some(spare_shard) if spare_shard.connections.len() < spare_shard.searching => {
+                spare_shard.connections.push(connection);
+                None
+            }
+            _ => Some(connection),
+        }
+    }
+}
+
+impl Drop for ShardSearch<'_> {
+    fn drop(&mut self) {
+        let mut shards = self.spares.shards.lock().unwrap();
+        if let Some(spare_shard) = shards.get_mut(&self.shard) {
+            // Connections not needed by the remaining keepers are closed
+            spare_shard.searching -= 1;
+            spare_shard.connections.truncate(spare_shard.searching);
+        }
     }
 }
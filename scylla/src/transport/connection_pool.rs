@@ -0,0 +1,156 @@
+/// Pools of connections kept to a node or to one of its shards
+use crate::transport::connection::{Connection, ConnectionConfig};
+use crate::transport::connection_keeper::{
+    ConnectionKeeper, ConnectionState, ShardInfoSender, ShardTarget,
+};
+use crate::transport::errors::QueryError;
+
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+/// Number of connections opened to each node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSize {
+    /// Number of connections to each node, divided evenly between the node's shards.\
+    /// Each shard gets at least one connection, so a node can get more connections than requested.
+    PerHost(NonZeroUsize),
+    /// Number of connections to each shard of a node.\
+    /// Nodes which aren't shard aware (e.g. Cassandra) are treated as having a single shard.
+    PerShard(NonZeroUsize),
+}
+
+impl Default for PoolSize {
+    /// One connection per shard
+    fn default() -> Self {
+        PoolSize::PerShard(NonZeroUsize::new(1).unwrap())
+    }
+}
+
+impl PoolSize {
+    /// Number of connections to open to each shard of a node with `nr_shards` shards
+    pub(crate) fn connections_per_shard(&self, nr_shards: usize) -> usize {
+        match self {
+            PoolSize::PerHost(per_host) => {
+                // Rounded up, so that every shard has a connection
+                let nr_shards = std::cmp::max(nr_shards, 1);
+                (per_host.get() + nr_shards - 1) / nr_shards
+            }
+            PoolSize::PerShard(per_shard) => per_shard.get(),
+        }
+    }
+}
+
+/// Connections to a node (or to one of its shards), each kept open by a ConnectionKeeper.\
+/// Broken connections are reopened in the background, so the pool refills itself.
+pub(crate) struct ConnectionPool {
+    /// Never empty
+    keepers: Vec<ConnectionKeeper>,
+}
+
+impl ConnectionPool {
+    /// Creates a pool of `size` connections, opened in the background.
+    /// Arguments are passed to each [ConnectionKeeper].
+    pub fn new(
+        address: SocketAddr,
+        config: ConnectionConfig,
+        shard_target: Option<ShardTarget>,
+        shard_info_sender: Option<ShardInfoSender>,
+        size: usize,
+    ) -> Self {
+        let keepers = (0..std::cmp::max(size, 1))
+            .map(|_| {
+                ConnectionKeeper::new(
+                    address,
+                    config.clone(),
+                    shard_target.clone(),
+                    shard_info_sender.clone(),
+                )
+            })
+            .collect();
+
+        ConnectionPool { keepers }
+    }
+
+    /// Creates a pool out of already running keepers, `keepers` must not be empty
+    pub fn from_keepers(keepers: Vec<ConnectionKeeper>) -> Self {
+        assert!(!keepers.is_empty());
+        ConnectionPool { keepers }
+    }
+
+    /// Takes all keepers out of the pool, leaving it empty.
+    /// Used to move connections to new pools, the pool mustn't be used afterwards.
+    pub fn take_keepers(&mut self) -> Vec<ConnectionKeeper> {
+        std::mem::take(&mut self.keepers)
+    }
+
+    /// Returns the open connection with the least requests awaiting responses.\
+    /// If no connection is open, waits for connections which are being initialized
+    /// and returns the first working one, or the last error.
+    pub async fn get_connection(&self) -> Result<Arc<Connection>, QueryError> {
+        let least_loaded = self
+            .keepers
+            .iter()
+            .filter_map(|keeper| match keeper.connection_state() {
+                ConnectionState::Connected(conn) => Some(conn),
+                _ => None,
+            })
+            .min_by_key(|conn| conn.get_in_flight_requests());
+
+        if let Some(conn) = least_loaded {
+            return Ok(conn);
+        }
+
+        let mut last_error: Option<QueryError> = None;
+        for keeper in &self.keepers {
+            match keeper.get_connection().await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap()) // By invariant keepers is nonempty
+    }
+
+    /// Waits for all connections to initialize and returns the working ones,
+    /// or the last error if none of them works
+    pub async fn get_working_connections(&self) -> Result<Vec<Arc<Connection>>, QueryError> {
+        let mut result: Vec<Arc<Connection>> = Vec::with_capacity(self.keepers.len());
+        let mut last_error: Option<QueryError> = None;
+
+        for keeper in &self.keepers {
+            match keeper.get_connection().await {
+                Ok(conn) => result.push(conn),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        if result.is_empty() {
+            return Err(last_error.unwrap()); // By invariant keepers is nonempty
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PoolSize;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn connections_per_shard() {
+        let n = |n: usize| NonZeroUsize::new(n).unwrap();
+
+        assert_eq!(PoolSize::default(), PoolSize::PerShard(n(1)));
+
+        assert_eq!(PoolSize::PerShard(n(2)).connections_per_shard(1), 2);
+        assert_eq!(PoolSize::PerShard(n(2)).connections_per_shard(8), 2);
+
+        assert_eq!(PoolSize::PerHost(n(3)).connections_per_shard(1), 3);
+        assert_eq!(PoolSize::PerHost(n(8)).connections_per_shard(4), 2);
+        assert_eq!(PoolSize::PerHost(n(9)).connections_per_shard(4), 3);
+        assert_eq!(PoolSize::PerHost(n(2)).connections_per_shard(8), 1);
+        assert_eq!(PoolSize::PerHost(n(2)).connections_per_shard(0), 2);
+    }
+}
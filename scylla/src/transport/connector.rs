@@ -0,0 +1,166 @@
+/// Connectors open the byte streams over which connections talk to nodes
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpSocket;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Byte stream over which a connection exchanges frames with a node
+pub trait TransportStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> TransportStream for T {}
+
+/// Stream opened by a [Connector]
+pub struct ConnectedStream {
+    pub stream: Box<dyn TransportStream>,
+    /// Local port of the stream, None if the transport doesn't have ports
+    pub source_port: Option<u16>,
+}
+
+/// Options of a new stream, taken from the session configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectOptions {
+    /// Local port to connect from, only given if the connector
+    /// [supports source ports](Connector::supports_source_port)
+    pub source_port: Option<u16>,
+    /// Local address to bind to, if the transport has addresses
+    pub local_ip_address: Option<IpAddr>,
+    /// Whether to disable Nagle's algorithm on TCP streams
+    pub tcp_nodelay: bool,
+    /// Whether to enable TCP keepalive probes
+    pub tcp_keepalive: bool,
+}
+
+/// Opens streams to nodes, which decides the transport used by all connections of a session.\
+/// Custom connectors can e.g. connect through a SOCKS5 proxy or use an in-memory stream in tests.
+pub trait Connector: Send + Sync {
+    /// Opens a stream to the node at `address`
+    fn connect<'a>(
+        &'a self,
+        address: SocketAddr,
+        options: &'a ConnectOptions,
+    ) -> BoxFuture<'a, Result<ConnectedStream, std::io::Error>>;
+
+    /// Whether streams can be opened from a chosen source port.\
+    /// Scylla's shard-aware port assigns connections to shards based on their source port,
+    /// without it connections reach the right shard through the regular port.
+    fn supports_source_port(&self) -> bool {
+        false
+    }
+}
+
+/// Default [Connector] - connects directly to nodes over TCP
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpConnector;
+
+impl Connector for TcpConnector {
+    fn connect<'a>(
+        &'a self,
+        address: SocketAddr,
+        options: &'a ConnectOptions,
+    ) -> BoxFuture<'a, Result<ConnectedStream, std::io::Error>> {
+        Box::pin(async move {
+            let (socket, unspecified_ip) = match address {
+                SocketAddr::V4(_) => (TcpSocket::new_v4()?, IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                SocketAddr::V6(_) => (TcpSocket::new_v6()?, IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+            };
+            socket.set_keepalive(options.tcp_keepalive)?;
+
+            // The local address can only be used with nodes of the same address family
+            let local_ip_address = options
+                .local_ip_address
+                .filter(|ip| ip.is_ipv4() == address.is_ipv4());
+
+            if options.source_port.is_some() || local_ip_address.is_some() {
+                let source_ip = local_ip_address.unwrap_or(unspecified_ip);
+                // Port 0 lets the system choose the source port
+                socket.bind(SocketAddr::new(source_ip, options.source_port.unwrap_or(0)))?;
+            }
+
+            let stream = socket.connect(address).await?;
+            stream.set_nodelay(options.tcp_nodelay)?;
+            let source_port = stream.local_addr()?.port();
+
+            Ok(ConnectedStream {
+                stream: Box::new(stream),
+                source_port: Some(source_port),
+            })
+        })
+    }
+
+    fn supports_source_port(&self) -> bool {
+        true
+    }
+}
+
+/// Connects through a Unix domain socket, e.g. Scylla's maintenance socket.\
+/// Every connection uses the socket regardless of the node address,
+/// so the session should only know the single node listening on it.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixSocketConnector {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocketConnector {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        UnixSocketConnector { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixSocketConnector {
+    fn connect<'a>(
+        &'a self,
+        _address: SocketAddr,
+        _options: &'a ConnectOptions,
+    ) -> BoxFuture<'a, Result<ConnectedStream, std::io::Error>> {
+        Box::pin(async move {
+            let stream = tokio::net::UnixStream::connect(&self.path).await?;
+            Ok(ConnectedStream {
+                stream: Box::new(stream),
+                source_port: None,
+            })
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{ConnectOptions, Connector, UnixSocketConnector};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn unix_socket_connector() {
+        let path = std::env::temp_dir().join(format!("scylla-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let connector = UnixSocketConnector::new(&path);
+        assert!(!connector.supports_source_port());
+
+        let options = ConnectOptions {
+            source_port: None,
+            local_ip_address: None,
+            tcp_nodelay: true,
+            tcp_keepalive: false,
+        };
+        // The node address is ignored, every stream goes through the socket
+        let connect = connector.connect("127.0.0.1:9042".parse().unwrap(), &options);
+        let (connected, accepted) = tokio::join!(connect, listener.accept());
+        let mut connected = connected.unwrap();
+        let (mut accepted, _) = accepted.unwrap();
+        assert_eq!(connected.source_port, None);
+
+        connected.stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
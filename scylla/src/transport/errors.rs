@@ -1,6 +1,8 @@
 use crate::frame::frame_errors::{FrameError, ParseError};
 use crate::frame::value::SerializeValuesError;
+use bytes::Bytes;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Error that occured during query execution
@@ -21,6 +23,19 @@ pub enum QueryError {
     /// Unexpected or invalid message received
     #[error("Protocol Error: {0}")]
     ProtocolError(&'static str),
+
+    /// Response didn't arrive before the client-side request timeout
+    #[error("Request timed out after {0:?}")]
+    RequestTimeout(Duration),
+
+    /// Too many requests timed out on a connection, while their stream ids are still in use.\
+    /// The connection is closed and opened again.
+    #[error("Too many orphaned stream ids: {0}")]
+    TooManyOrphanedStreamIds(usize),
+
+    /// Limit of requests awaiting responses on a connection was reached
+    #[error("Too many in-flight requests on a connection, limit: {0}")]
+    TooManyInFlightRequests(usize),
 }
 
 /// An error sent from database in response to a query
@@ -32,6 +47,48 @@ pub enum DBError {
     // Make it fit what we encounter.
     #[error("Response is an error message: code: {0} message: {1}")]
     ErrorMsg(i32, String),
+
+    /// Scylla rejected the operation because a per-partition rate limit was exceeded.\
+    /// Sent only if the `SCYLLA_RATE_LIMIT_ERROR` extension was negotiated, the request
+    /// should be retried after backing off
+    #[error("Rate limit reached for {op_type:?} operation, rejected by coordinator: {rejected_by_coordinator}")]
+    RateLimitReached {
+        op_type: OperationType,
+        rejected_by_coordinator: bool,
+    },
+
+    /// The node doesn't know the prepared statement with the given id, e.g. after a restart.\
+    /// The statement has to be prepared again.
+    #[error("Unprepared statement, id: {statement_id:?}")]
+    Unprepared { statement_id: Bytes },
+}
+
+/// Type of operation rejected because of reaching a rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationType {
+    Read,
+    Write,
+    Other(u8),
+}
+
+impl From<OperationType> for u8 {
+    fn from(op_type: OperationType) -> u8 {
+        match op_type {
+            OperationType::Read => 0,
+            OperationType::Write => 1,
+            OperationType::Other(other) => other,
+        }
+    }
+}
+
+impl From<u8> for OperationType {
+    fn from(op_type: u8) -> OperationType {
+        match op_type {
+            0 => OperationType::Read,
+            1 => OperationType::Write,
+            other => OperationType::Other(other),
+        }
+    }
 }
 
 /// Error caused by caller creating an invalid query
@@ -49,6 +106,10 @@ pub enum BadQuery {
     /// Serialized values are too long to compute parition key
     #[error("Serialized values are too long to compute parition key! Length: {0}, Max allowed length: {1}")]
     ValuesTooLongForKey(usize, usize),
+
+    /// Keyspace name passed to use_keyspace is invalid
+    #[error("Invalid keyspace name: {0}, should be 1-48 alphanumeric characters or underscores")]
+    BadKeyspaceName(String),
 }
 
 /// Error that occured during session creation
@@ -78,6 +139,19 @@ pub enum NewSessionError {
     /// Unexpected or invalid message received
     #[error("Protocol Error: {0}")]
     ProtocolError(&'static str),
+
+    /// Response didn't arrive before the client-side request timeout
+    #[error("Request timed out after {0:?}")]
+    RequestTimeout(Duration),
+
+    /// Too many requests timed out on a connection, while their stream ids are still in use.\
+    /// The connection is closed and opened again.
+    #[error("Too many orphaned stream ids: {0}")]
+    TooManyOrphanedStreamIds(usize),
+
+    /// Limit of requests awaiting responses on a connection was reached
+    #[error("Too many in-flight requests on a connection, limit: {0}")]
+    TooManyInFlightRequests(usize),
 }
 
 impl From<std::io::Error> for QueryError {
@@ -117,6 +191,9 @@ impl From<QueryError> for NewSessionError {
             QueryError::BadQuery(e) => NewSessionError::BadQuery(e),
             QueryError::IOError(e) => NewSessionError::IOError(e),
             QueryError::ProtocolError(m) => NewSessionError::ProtocolError(m),
+            QueryError::RequestTimeout(t) => NewSessionError::RequestTimeout(t),
+            QueryError::TooManyOrphanedStreamIds(n) => NewSessionError::TooManyOrphanedStreamIds(n),
+            QueryError::TooManyInFlightRequests(n) => NewSessionError::TooManyInFlightRequests(n),
         }
     }
 }
@@ -10,6 +10,7 @@ use futures::Stream;
 use std::result::Result as StdResult;
 use thiserror::Error;
 use tokio::sync::mpsc;
+use uuid::Uuid;
 
 use super::errors::QueryError;
 use crate::cql_to_rust::{FromRow, FromRowError};
@@ -22,13 +23,19 @@ use crate::frame::{
     value::SerializedValues,
 };
 use crate::statement::{prepared_statement::PreparedStatement, query::Query};
-use crate::transport::connection::Connection;
+use crate::transport::connection::{Connection, QueryResponse};
 use crate::transport::metrics::Metrics;
 
 pub struct RowIterator {
     current_row_idx: usize,
     current_page: Rows,
-    page_receiver: mpsc::Receiver<StdResult<Rows, QueryError>>,
+    page_receiver: mpsc::Receiver<StdResult<ReceivedPage, QueryError>>,
+    tracing_ids: Vec<Uuid>,
+}
+
+struct ReceivedPage {
+    rows: Rows,
+    tracing_id: Option<Uuid>,
 }
 
 impl Stream for RowIterator {
@@ -39,9 +46,10 @@ impl Stream for RowIterator {
 
         if s.is_current_page_exhausted() {
             match Pin::new(&mut s.page_receiver).poll_recv(cx) {
-                Poll::Ready(Some(Ok(rows))) => {
-                    s.current_page = rows;
+                Poll::Ready(Some(Ok(received_page))) => {
+                    s.current_page = received_page.rows;
                     s.current_row_idx = 0;
+                    s.tracing_ids.extend(received_page.tracing_id);
                 }
                 Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
                 Poll::Ready(None) => return Poll::Ready(None),
@@ -64,6 +72,11 @@ impl Stream for RowIterator {
 }
 
 impl RowIterator {
+    /// Tracing ids of the pages fetched so far, one per page fetched with tracing enabled
+    pub fn get_tracing_ids(&self) -> &[Uuid] {
+        &self.tracing_ids
+    }
+
     pub fn into_typed<RowT: FromRow>(self) -> TypedRowIterator<RowT> {
         TypedRowIterator {
             row_iterator: self,
@@ -138,6 +151,7 @@ impl RowIterator {
             current_row_idx: 0,
             current_page: Default::default(),
             page_receiver: receiver,
+            tracing_ids: Vec::new(),
         }
     }
 
@@ -146,30 +160,34 @@ impl RowIterator {
     }
 }
 struct WorkerHelper {
-    sender: mpsc::Sender<StdResult<Rows, QueryError>>,
+    sender: mpsc::Sender<StdResult<ReceivedPage, QueryError>>,
     metrics: Arc<Metrics>,
 }
 
 impl WorkerHelper {
-    fn new(sender: mpsc::Sender<StdResult<Rows, QueryError>>, metrics: Arc<Metrics>) -> Self {
+    fn new(
+        sender: mpsc::Sender<StdResult<ReceivedPage, QueryError>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Self { sender, metrics }
     }
 
     async fn handle_response(
         &mut self,
-        response: StdResult<Response, QueryError>,
+        response: StdResult<QueryResponse, QueryError>,
     ) -> Option<Bytes> {
-        match response {
-            Ok(Response::Result(Result::Rows(rows))) => {
+        match response.map(|r| (r.response, r.tracing_id)) {
+            Ok((Response::Result(Result::Rows(rows)), tracing_id)) => {
                 let paging_state = rows.metadata.paging_state.clone();
-                if self.sender.send(Ok(rows)).await.is_err() {
+                let received_page = ReceivedPage { rows, tracing_id };
+                if self.sender.send(Ok(received_page)).await.is_err() {
                     // TODO: Log error
                     None
                 } else {
                     paging_state
                 }
             }
-            Ok(Response::Error(err)) => {
+            Ok((Response::Error(err), _)) => {
                 self.metrics.inc_failed_paged_queries();
                 let _ = self.sender.send(Err(err.into())).await;
                 None
@@ -210,6 +228,13 @@ pub enum NextRowError {
     FromRowError(#[from] FromRowError),
 }
 
+impl<RowT> TypedRowIterator<RowT> {
+    /// Tracing ids of the pages fetched so far, see [RowIterator::get_tracing_ids]
+    pub fn get_tracing_ids(&self) -> &[Uuid] {
+        self.row_iterator.get_tracing_ids()
+    }
+}
+
 impl<RowT: FromRow> Stream for TypedRowIterator<RowT> {
     type Item = StdResult<RowT, NextRowError>;
 
@@ -1,14 +1,20 @@
 mod cluster;
 pub mod connection;
 mod connection_keeper;
+pub mod connection_pool;
+pub mod connector;
 mod node;
 pub mod session;
 pub mod session_builder;
 mod topology;
+pub mod tracing;
 
 pub mod errors;
 pub mod iterator;
 mod metrics;
+pub mod protocol_features;
+pub mod reconnection_policy;
+pub mod timestamp_generator;
 
 #[cfg(test)]
 mod session_test;
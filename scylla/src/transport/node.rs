@@ -1,8 +1,13 @@
 /// Node represents a cluster node along with it's data and connections
 use crate::routing::{Shard, ShardInfo, Token};
 use crate::transport::connection::{Connection, ConnectionConfig};
-use crate::transport::connection_keeper::{ConnectionKeeper, ShardInfoSender};
+use crate::transport::connection_keeper::{
+    ConnectionKeeper, ConnectionState, NodeShardingInfo, ShardInfoSender, ShardTarget,
+    SpareConnections,
+};
+use crate::transport::connection_pool::ConnectionPool;
 use crate::transport::errors::QueryError;
+use crate::transport::protocol_features::ProtocolFeatures;
 
 use futures::{future::RemoteHandle, FutureExt};
 use std::net::SocketAddr;
@@ -21,13 +26,13 @@ pub struct Node {
 }
 
 pub enum NodeConnections {
-    /// Non shard-aware ex. a Cassandra node connection
-    Single(ConnectionKeeper),
+    /// Non shard-aware ex. a Cassandra node connections
+    Single(ConnectionPool),
     /// Shard aware Scylla node connections
     Sharded {
         shard_info: ShardInfo,
-        /// shard_conns always contains shard_info.nr_shards ConnectionKeepers
-        shard_conns: Vec<ConnectionKeeper>,
+        /// shard_conns always contains shard_info.nr_shards ConnectionPools
+        shard_conns: Vec<ConnectionPool>,
     },
 }
 
@@ -38,7 +43,7 @@ struct NodeWorker {
     connection_config: ConnectionConfig,
 
     shard_info_sender: ShardInfoSender,
-    shard_info_receiver: tokio::sync::watch::Receiver<Option<ShardInfo>>,
+    shard_info_receiver: tokio::sync::watch::Receiver<Option<NodeShardingInfo>>,
 }
 
 impl Node {
@@ -60,11 +65,12 @@ impl Node {
         let shard_info_sender = Arc::new(std::sync::Mutex::new(shard_info_sender));
 
         let connections = Arc::new(AsyncRwLock::new(NodeConnections::Single(
-            ConnectionKeeper::new(
+            ConnectionPool::new(
                 address,
                 connection_config.clone(),
                 None,
                 Some(shard_info_sender.clone()),
+                connection_config.pool_size.connections_per_shard(1),
             ),
         )));
 
@@ -93,7 +99,7 @@ impl Node {
         let connections = &*self.connections.read().await;
 
         match connections {
-            NodeConnections::Single(conn_keeper) => conn_keeper.get_connection().await,
+            NodeConnections::Single(pool) => pool.get_connection().await,
             NodeConnections::Sharded {
                 shard_info,
                 shard_conns,
@@ -104,11 +110,26 @@ impl Node {
             }
         }
     }
+
+    /// Features reported by the node in response to OPTIONS.\
+    /// Sharding parameters describe the shard of an arbitrary connection to this node.
+    pub async fn get_protocol_features(&self) -> Result<ProtocolFeatures, QueryError> {
+        let connection: Arc<Connection> = match &*self.connections.read().await {
+            NodeConnections::Single(pool) => pool.get_connection().await?,
+            NodeConnections::Sharded { shard_conns, .. } => {
+                // shard_conns is never empty, it contains nr_shards ConnectionPools
+                shard_conns[0].get_connection().await?
+            }
+        };
+
+        Ok(connection.get_protocol_features().clone())
+    }
 }
 
 impl NodeWorker {
     pub async fn work(mut self) {
-        let mut cur_shard_info: Option<ShardInfo> = self.shard_info_receiver.borrow().clone();
+        let mut cur_shard_info: Option<NodeShardingInfo> =
+            self.shard_info_receiver.borrow().clone();
 
         loop {
             // Wait for current shard_info to change
@@ -117,12 +138,16 @@ impl NodeWorker {
                 .changed()
                 .await
                 .expect("Bug in NodeWorker::work");
-            let new_shard_info: Option<ShardInfo> = self.shard_info_receiver.borrow().clone();
+            let new_shard_info: Option<NodeShardingInfo> =
+                self.shard_info_receiver.borrow().clone();
 
-            // See if the node has resharded
+            // See if the node has resharded or changed its shard-aware port
             match (&cur_shard_info, &new_shard_info) {
                 (Some(cur), Some(new)) => {
-                    if cur.nr_shards == new.nr_shards && cur.msb_ignore == new.msb_ignore {
+                    if cur.shard_info.nr_shards == new.shard_info.nr_shards
+                        && cur.shard_info.msb_ignore == new.shard_info.msb_ignore
+                        && cur.shard_aware_port == new.shard_aware_port
+                    {
                         // Nothing chaged, go back to waiting for a change
                         continue;
                     }
@@ -133,44 +158,375 @@ impl NodeWorker {
 
             cur_shard_info = new_shard_info;
 
-            // We received updated node ShardInfo
-            // Create new node connections. It will happen rarely so we can probably afford it
-            // TODO: Maybe save some connections instead of recreating?
-            let mut new_connections: NodeConnections = match &cur_shard_info {
-                None => NodeConnections::Single(ConnectionKeeper::new(
-                    self.node_addr,
-                    self.connection_config.clone(),
-                    None,
-                    Some(self.shard_info_sender.clone()),
-                )),
-                Some(shard_info) => {
-                    let mut connections: Vec<ConnectionKeeper> =
-                        Vec::with_capacity(shard_info.nr_shards as usize);
-
-                    for shard in 0..shard_info.nr_shards {
-                        let mut cur_conn_shard_info = shard_info.clone();
-                        cur_conn_shard_info.shard = shard;
-                        let cur_conn = ConnectionKeeper::new(
-                            self.node_addr,
-                            self.connection_config.clone(),
-                            Some(cur_conn_shard_info),
-                            Some(self.shard_info_sender.clone()),
-                        );
-                        connections.push(cur_conn);
-                    }
+            // We received updated node ShardInfo, rebuild node connections.
+            // Connections which still lead to a valid shard are moved to the new pools,
+            // only missing connections are opened.
+            let mut node_conns_lock = self.node_conns.write().await;
+            let old_keepers: Vec<ConnectionKeeper> = node_conns_lock.take_keepers();
+            let mut new_connections: NodeConnections =
+                self.new_connections(&cur_shard_info, old_keepers);
+            std::mem::swap(&mut *node_conns_lock, &mut new_connections);
+            drop(node_conns_lock);
+        }
+    }
 
-                    NodeConnections::Sharded {
-                        shard_info: shard_info.clone(),
-                        shard_conns: connections,
-                    }
+    /// Creates connections for the given sharding, reusing working connections from `old_keepers`.\
+    /// Unused keepers are dropped, their connections close once requests in progress
+    /// on them finish, because each request holds an `Arc<Connection>`.
+    fn new_connections(
+        &self,
+        sharding: &Option<NodeShardingInfo>,
+        old_keepers: Vec<ConnectionKeeper>,
+    ) -> NodeConnections {
+        let NodeShardingInfo {
+            shard_info,
+            shard_aware_port,
+        } = match sharding {
+            Some(sharding) => sharding,
+            None => {
+                let pool_size = self.connection_config.pool_size.connections_per_shard(1);
+                let mut keepers: Vec<ConnectionKeeper> = old_keepers
+                    .into_iter()
+                    .filter(|keeper| {
+                        matches!(keeper.connection_state(), ConnectionState::Connected(_))
+                    })
+                    .take(pool_size)
+                    .collect();
+
+                for keeper in &keepers {
+                    keeper.set_shard_target(None);
+                }
+
+                while keepers.len() < pool_size {
+                    keepers.push(ConnectionKeeper::new(
+                        self.node_addr,
+                        self.connection_config.clone(),
+                        None,
+                        Some(self.shard_info_sender.clone()),
+                    ));
                 }
+
+                return NodeConnections::Single(ConnectionPool::from_keepers(keepers));
+            }
+        };
+
+        let pool_size = self
+            .connection_config
+            .pool_size
+            .connections_per_shard(shard_info.nr_shards as usize);
+        // Used to fill all shards when the shard-aware port can't be used
+        let spare_connections = Arc::new(SpareConnections::default());
+        // The shard-aware port picks the shard based on the source port,
+        // which can't be chosen with some connectors, e.g. Unix domain sockets
+        let shard_aware_port: Option<u16> =
+            shard_aware_port.filter(|_| self.connection_config.connector.supports_source_port());
+
+        let shard_target = |shard: u16| -> ShardTarget {
+            let mut target_shard_info = shard_info.clone();
+            target_shard_info.shard = shard;
+            ShardTarget {
+                shard_info: target_shard_info,
+                shard_aware_port,
+                spare_connections: spare_connections.clone(),
+            }
+        };
+
+        let mut shard_keepers: Vec<Vec<ConnectionKeeper>> =
+            (0..shard_info.nr_shards).map(|_| Vec::new()).collect();
+
+        for keeper in old_keepers {
+            let conn_shard: Option<u16> = match keeper.connection_state() {
+                ConnectionState::Connected(conn) => conn
+                    .get_shard_info()
+                    .as_ref()
+                    .filter(|info| {
+                        info.nr_shards == shard_info.nr_shards
+                            && info.msb_ignore == shard_info.msb_ignore
+                    })
+                    .map(|info| info.shard),
+                _ => None,
             };
 
-            // Update node.connections
-            // Use std::mem::swap to minimalize time spent holding write lock
-            let mut node_conns_lock = self.node_conns.write().await;
-            std::mem::swap(&mut *node_conns_lock, &mut new_connections);
-            drop(node_conns_lock);
+            if let Some(shard) = conn_shard {
+                let keepers = &mut shard_keepers[shard as usize];
+                if keepers.len() < pool_size {
+                    keeper.set_shard_target(Some(shard_target(shard)));
+                    keepers.push(keeper);
+                }
+            }
+        }
+
+        let shard_conns: Vec<ConnectionPool> = shard_keepers
+            .into_iter()
+            .zip(0..shard_info.nr_shards)
+            .map(|(mut keepers, shard)| {
+                while keepers.len() < pool_size {
+                    keepers.push(ConnectionKeeper::new(
+                        self.node_addr,
+                        self.connection_config.clone(),
+                        Some(shard_target(shard)),
+                        Some(self.shard_info_sender.clone()),
+                    ));
+                }
+                ConnectionPool::from_keepers(keepers)
+            })
+            .collect();
+
+        NodeConnections::Sharded {
+            shard_info: shard_info.clone(),
+            shard_conns,
         }
     }
 }
+
+impl NodeConnections {
+    /// Takes keepers out of all pools, leaving them empty
+    fn take_keepers(&mut self) -> Vec<ConnectionKeeper> {
+        match self {
+            NodeConnections::Single(pool) => pool.take_keepers(),
+            NodeConnections::Sharded { shard_conns, .. } => shard_conns
+                .iter_mut()
+                .flat_map(|pool| pool.take_keepers())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Node, NodeConnections, NodeWorker};
+    use crate::frame::ProtocolVersion;
+    use crate::query::Query;
+    use crate::routing::{ShardInfo, Token};
+    use crate::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+    use crate::transport::connection::Connection;
+    use crate::transport::connection_keeper::{
+        ConnectionKeeper, ConnectionState, NodeShardingInfo, ShardTarget,
+    };
+    use crate::transport::connection_pool::ConnectionPool;
+    use crate::transport::reconnection_policy::ConstantReconnectionPolicy;
+    use crate::transport::session::SessionConfig;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::RwLock as AsyncRwLock;
+
+    // Waits until the node learns its sharding parameters and returns connections to each shard
+    async fn connections_to_shards(node: &Node) -> Vec<Vec<Arc<Connection>>> {
+        let wait_for_connections = async {
+            loop {
+                if let NodeConnections::Sharded { shard_conns, .. } =
+                    &*node.connections.read().await
+                {
+                    let mut result = Vec::with_capacity(shard_conns.len());
+                    for pool in shard_conns {
+                        result.push(pool.get_working_connections().await.unwrap());
+                    }
+                    return result;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), wait_for_connections)
+            .await
+            .expect("Node didn't connect to its shards")
+    }
+
+    // Requires binding to the source ports used with the shard-aware port
+    #[tokio::test]
+    async fn shard_aware_port() {
+        let server = FakeServer::start_sharded(4).await.unwrap();
+        let config = SessionConfig::new().get_connection_config();
+        let node = Node::new(server.address(), config, None, None);
+
+        let shards = connections_to_shards(&node).await;
+        assert_eq!(shards.len(), 4);
+        for (shard, connections) in shards.iter().enumerate() {
+            for connection in connections {
+                let shard_info = connection.get_shard_info().as_ref().unwrap();
+                assert_eq!(shard_info.shard as usize, shard);
+            }
+        }
+
+        // The first connection, opened to the regular port, is kept on its shard
+        let through_regular_port = shards
+            .iter()
+            .flatten()
+            .filter(|connection| !connection.get_is_shard_aware())
+            .count();
+        assert!(through_regular_port <= 1);
+    }
+
+    #[tokio::test]
+    async fn blocked_shard_aware_port() {
+        let server = FakeServer::start_sharded(4).await.unwrap();
+        server.block_shard_aware_port(true);
+        let mut config = SessionConfig::new().get_connection_config();
+        config.shard_aware_port_connect_timeout = Duration::from_millis(100);
+        let node = Node::new(server.address(), config, None, None);
+
+        // Connections are opened to the regular port instead, until all shards are filled
+        let shards = connections_to_shards(&node).await;
+        assert_eq!(shards.len(), 4);
+        for (shard, connections) in shards.iter().enumerate() {
+            for connection in connections {
+                assert!(!connection.get_is_shard_aware());
+                assert_eq!(connection.get_connect_address(), server.address());
+                let shard_info = connection.get_shard_info().as_ref().unwrap();
+                assert_eq!(shard_info.shard as usize, shard);
+            }
+        }
+        node.connection_for_token(Token { value: 0 }).await.unwrap();
+
+        // Connections which landed on already filled shards are closed
+        let surplus_closed = async {
+            while server.connections_per_shard() != vec![1, 1, 1, 1] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), surplus_closed)
+            .await
+            .expect("Surplus connections weren't closed");
+    }
+
+    #[tokio::test]
+    async fn reshard_keeps_valid_connections() {
+        let server = FakeServer::start_sharded(2).await.unwrap();
+        server.block_shard_aware_port(true);
+        server.add_rule(
+            Rule::new(
+                StatementPattern::Exact("SELECT slow".to_string()),
+                FakeResponse::Void,
+            )
+            .with_delay(Duration::from_millis(300)),
+        );
+        let mut config = SessionConfig::new().get_connection_config();
+        config.shard_aware_port_connect_timeout = Duration::from_millis(100);
+
+        // The regular port assigns connections to shards in turns
+        let old_pool = ConnectionPool::new(server.address(), config.clone(), None, None, 4);
+        let mut old_connections = old_pool.get_working_connections().await.unwrap();
+        assert_eq!(server.connections_per_shard(), vec![2, 2]);
+
+        // The first connection to each shard is kept, the last one is the second on its shard
+        let kept: Vec<Arc<Connection>> = (0..2)
+            .map(|shard| {
+                old_connections
+                    .iter()
+                    .find(|conn| conn.get_shard_info().as_ref().unwrap().shard == shard)
+                    .unwrap()
+                    .clone()
+            })
+            .collect();
+        let slow_conn = old_connections.pop().unwrap();
+        drop(old_connections);
+        let slow_query = tokio::spawn(async move {
+            slow_conn
+                .query(&Query::new("SELECT slow".to_string()), (), None)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let (shard_info_sender, shard_info_receiver) = tokio::sync::watch::channel(None);
+        let worker = NodeWorker {
+            node_conns: Arc::new(AsyncRwLock::new(NodeConnections::Single(old_pool))),
+            node_addr: server.address(),
+            connection_config: config,
+            shard_info_sender: Arc::new(Mutex::new(shard_info_sender)),
+            shard_info_receiver,
+        };
+        let sharding = Some(NodeShardingInfo {
+            shard_info: ShardInfo::new(0, 2, 12),
+            shard_aware_port: server.shard_aware_address().map(|addr| addr.port()),
+        });
+        let old_keepers = worker.node_conns.write().await.take_keepers();
+        let new_connections = worker.new_connections(&sharding, old_keepers);
+
+        match &new_connections {
+            NodeConnections::Sharded { shard_conns, .. } => {
+                assert_eq!(shard_conns.len(), 2);
+                for (pool, kept_conn) in shard_conns.iter().zip(&kept) {
+                    let connections = pool.get_working_connections().await.unwrap();
+                    assert_eq!(connections.len(), 1);
+                    assert!(Arc::ptr_eq(&connections[0], kept_conn));
+                }
+            }
+            NodeConnections::Single(_) => panic!("Expected sharded connections"),
+        }
+        drop(kept);
+
+        // The request in progress on a dropped connection finishes
+        slow_query.await.unwrap().unwrap();
+
+        let surplus_closed = async {
+            while server.connections_per_shard() != vec![1, 1] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), surplus_closed)
+            .await
+            .expect("Surplus connections weren't closed");
+        drop(worker);
+    }
+
+    #[tokio::test]
+    async fn shard_connection_attempts_limit() {
+        let server = FakeServer::start_sharded(2).await.unwrap();
+        let mut config = SessionConfig::new().get_connection_config();
+        config.shard_connection_attempts = 0;
+        let target = ShardTarget {
+            shard_info: ShardInfo::new(1, 2, 12),
+            shard_aware_port: None,
+            spare_connections: Default::default(),
+        };
+
+        // Without a connection kept from the attempts, connecting fails instead of opening more
+        let keeper = ConnectionKeeper::new(server.address(), config, Some(target), None);
+        assert!(keeper.get_connection().await.is_err());
+        assert_eq!(server.connections_per_shard(), vec![0, 0]);
+    }
+
+    #[tokio::test]
+    async fn shard_covered_after_failed_attempts() {
+        let server = FakeServer::start_sharded(2).await.unwrap();
+        let mut config = SessionConfig::new().get_connection_config();
+        config.shard_connection_attempts = 1;
+        // No connections are spent on negotiating the protocol version
+        config.protocol_version = ProtocolVersion::V4;
+        config.reconnection_policy =
+            Arc::new(ConstantReconnectionPolicy::new(Duration::from_millis(10)));
+        let target = ShardTarget {
+            shard_info: ShardInfo::new(1, 2, 12),
+            shard_aware_port: None,
+            spare_connections: Default::default(),
+        };
+
+        // The regular port assigns the first connection to shard 0, it's closed
+        // instead of being used for shard 1
+        let keeper = ConnectionKeeper::new(server.address(), config, Some(target), None);
+        assert!(keeper.get_connection().await.is_err());
+
+        // The next attempt lands on shard 1
+        let connected = async {
+            loop {
+                if let ConnectionState::Connected(connection) = keeper.connection_state() {
+                    return connection;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let connection = tokio::time::timeout(Duration::from_secs(5), connected)
+            .await
+            .expect("Shard wasn't connected to");
+        assert_eq!(connection.get_shard_info().as_ref().unwrap().shard, 1);
+
+        let surplus_closed = async {
+            while server.connections_per_shard() != vec![0, 1] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), surplus_closed)
+            .await
+            .expect("Connection to the wrong shard wasn't closed");
+    }
+}
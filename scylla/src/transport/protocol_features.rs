@@ -0,0 +1,281 @@
+//! Features and extensions reported by a node in response to OPTIONS
+use crate::frame::ProtocolVersion;
+use crate::routing::ShardInfo;
+use crate::transport::Compression;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+const CQL_VERSION_KEY: &str = "CQL_VERSION";
+const PROTOCOL_VERSIONS_KEY: &str = "PROTOCOL_VERSIONS";
+const COMPRESSION_KEY: &str = "COMPRESSION";
+const SHARD_AWARE_PORT_KEY: &str = "SCYLLA_SHARD_AWARE_PORT";
+const SHARD_AWARE_PORT_SSL_KEY: &str = "SCYLLA_SHARD_AWARE_PORT_SSL";
+const PARTITIONER_KEY: &str = "SCYLLA_PARTITIONER";
+const SHARDING_ALGORITHM_KEY: &str = "SCYLLA_SHARDING_ALGORITHM";
+
+const DEFAULT_CQL_VERSION: &str = "4.0.0";
+
+// Scylla extension marking prepared LWT statements, e.g. `LWT_OPTIMIZATION_META_BIT_MASK=2147483648`
+const LWT_ADD_METADATA_MARK_KEY: &str = "SCYLLA_LWT_ADD_METADATA_MARK";
+const LWT_OPTIMIZATION_META_BIT_MASK: &str = "LWT_OPTIMIZATION_META_BIT_MASK";
+
+// Scylla extension sending a dedicated error when a per-partition rate limit is exceeded,
+// e.g. `ERROR_CODE=61440`
+const RATE_LIMIT_ERROR_KEY: &str = "SCYLLA_RATE_LIMIT_ERROR";
+const RATE_LIMIT_ERROR_CODE: &str = "ERROR_CODE";
+
+// Scylla extension adding result metadata ids (normally introduced in v5) to protocol v4
+const USE_METADATA_ID_KEY: &str = "SCYLLA_USE_METADATA_ID";
+
+/// Features supported by a node, parsed from its SUPPORTED response.\
+/// They decide which protocol extensions the driver requests in STARTUP.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtocolFeatures {
+    /// Versions of CQL supported by the node
+    pub cql_versions: Vec<String>,
+    /// Versions of the native protocol supported by the node (reported only by Cassandra)
+    pub protocol_versions: Vec<String>,
+    /// Compression algorithms supported by the node and known by the driver
+    pub compression: Vec<Compression>,
+
+    /// Sharding parameters of the node, including the shard which handles this connection
+    pub shard_info: Option<ShardInfo>,
+    pub partitioner: Option<String>,
+    pub sharding_algorithm: Option<String>,
+    /// Port on which the shard handling a connection is chosen based on its source port
+    pub shard_aware_port: Option<u16>,
+    pub shard_aware_port_ssl: Option<u16>,
+
+    /// Flag marking LWT statements in prepared metadata
+    pub lwt_optimization_mask: Option<i32>,
+    /// Code of the error sent when a per-partition rate limit is exceeded
+    pub rate_limit_error_code: Option<i32>,
+    /// Whether result metadata ids (normally introduced in v5) can be used in protocol v4.\
+    /// The extension is requested in STARTUP whenever the node supports it.
+    pub use_metadata_id: bool,
+}
+
+impl ProtocolFeatures {
+    /// Parses features from options sent in a SUPPORTED response
+    pub fn parse(options: &HashMap<String, Vec<String>>) -> Self {
+        let first = |key: &str| options.get(key).and_then(|values| values.first());
+        let all = |key: &str| options.get(key).cloned().unwrap_or_default();
+
+        ProtocolFeatures {
+            cql_versions: all(CQL_VERSION_KEY),
+            protocol_versions: all(PROTOCOL_VERSIONS_KEY),
+            compression: all(COMPRESSION_KEY)
+                .iter()
+                .filter_map(|name| parse_compression(name))
+                .collect(),
+
+            shard_info: ShardInfo::try_from(options).ok(),
+            partitioner: first(PARTITIONER_KEY).cloned(),
+            sharding_algorithm: first(SHARDING_ALGORITHM_KEY).cloned(),
+            shard_aware_port: first(SHARD_AWARE_PORT_KEY).and_then(|p| p.parse().ok()),
+            shard_aware_port_ssl: first(SHARD_AWARE_PORT_SSL_KEY).and_then(|p| p.parse().ok()),
+
+            lwt_optimization_mask: first(LWT_ADD_METADATA_MARK_KEY)
+                .and_then(|v| parse_key_value_u32(v, LWT_OPTIMIZATION_META_BIT_MASK))
+                .map(|mask| mask as i32),
+            rate_limit_error_code: first(RATE_LIMIT_ERROR_KEY)
+                .and_then(|v| parse_key_value_u32(v, RATE_LIMIT_ERROR_CODE))
+                .map(|code| code as i32),
+            use_metadata_id: options.contains_key(USE_METADATA_ID_KEY),
+        }
+    }
+
+    /// Adds options enabling the extensions which are supported both by the node and the driver
+    pub fn add_startup_options(&self, options: &mut HashMap<String, String>) {
+        // Use the CQL version reported by the server, older servers don't report any
+        let cql_version = self
+            .cql_versions
+            .first()
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_CQL_VERSION);
+        options.insert(CQL_VERSION_KEY.to_string(), cql_version.to_string());
+
+        if let Some(mask) = self.lwt_optimization_mask {
+            // Ask Scylla to mark LWT statements in prepared metadata
+            options.insert(
+                LWT_ADD_METADATA_MARK_KEY.to_string(),
+                format!("{}={}", LWT_OPTIMIZATION_META_BIT_MASK, mask as u32),
+            );
+        }
+        if self.rate_limit_error_code.is_some() {
+            // Ask Scylla to send a dedicated error when a rate limit is reached
+            options.insert(RATE_LIMIT_ERROR_KEY.to_string(), String::new());
+        }
+        if self.use_metadata_id {
+            // Ask Scylla to send result metadata ids and report metadata changes in protocol v4
+            options.insert(USE_METADATA_ID_KEY.to_string(), String::new());
+        }
+    }
+
+    /// Whether result metadata ids are exchanged on a connection using the given protocol version.\
+    /// They are part of protocol v5, older versions use them through Scylla's extension.
+    /// Only then the server reports changes of result metadata, e.g. after `ALTER TABLE`.
+    pub fn uses_metadata_ids(&self, version: ProtocolVersion) -> bool {
+        version >= ProtocolVersion::V5 || self.use_metadata_id
+    }
+
+    /// Whether the node supports the given compression algorithm
+    pub fn supports_compression(&self, compression: Compression) -> bool {
+        self.compression.contains(&compression)
+    }
+}
+
+fn parse_compression(name: &str) -> Option<Compression> {
+    match name {
+        "lz4" => Some(Compression::LZ4),
+        "snappy" => Some(Compression::Snappy),
+        _ => None,
+    }
+}
+
+// Parses values like `LWT_OPTIMIZATION_META_BIT_MASK=2147483648`
+fn parse_key_value_u32(value: &str, expected_key: &str) -> Option<u32> {
+    let mut parts = value.splitn(2, '=');
+    if parts.next()? != expected_key {
+        return None;
+    }
+    parts.next()?.parse::<u32>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProtocolFeatures;
+    use crate::frame::ProtocolVersion;
+    use crate::routing::ShardInfo;
+    use crate::transport::Compression;
+    use std::collections::HashMap;
+
+    fn options(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(key, values)| {
+                let values = values.iter().map(|v| v.to_string()).collect();
+                (key.to_string(), values)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scylla_features() {
+        let features = ProtocolFeatures::parse(&options(&[
+            ("CQL_VERSION", &["3.3.1"]),
+            ("COMPRESSION", &["lz4", "snappy", "unknown"]),
+            ("SCYLLA_SHARD", &["3"]),
+            ("SCYLLA_NR_SHARDS", &["8"]),
+            ("SCYLLA_SHARDING_IGNORE_MSB", &["12"]),
+            (
+                "SCYLLA_PARTITIONER",
+                &["org.apache.cassandra.dht.Murmur3Partitioner"],
+            ),
+            ("SCYLLA_SHARDING_ALGORITHM", &["biased-token-round-robin"]),
+            ("SCYLLA_SHARD_AWARE_PORT", &["19042"]),
+            (
+                "SCYLLA_LWT_ADD_METADATA_MARK",
+                &["LWT_OPTIMIZATION_META_BIT_MASK=2147483648"],
+            ),
+            ("SCYLLA_RATE_LIMIT_ERROR", &["ERROR_CODE=61440"]),
+            ("SCYLLA_USE_METADATA_ID", &[""]),
+        ]));
+
+        assert_eq!(features.cql_versions, vec!["3.3.1".to_string()]);
+        assert_eq!(
+            features.compression,
+            vec![Compression::LZ4, Compression::Snappy]
+        );
+        assert_eq!(features.shard_info, Some(ShardInfo::new(3, 8, 12)));
+        assert_eq!(
+            features.sharding_algorithm.as_deref(),
+            Some("biased-token-round-robin")
+        );
+        assert_eq!(features.shard_aware_port, Some(19042));
+        assert_eq!(features.shard_aware_port_ssl, None);
+        assert_eq!(features.lwt_optimization_mask, Some(i32::MIN));
+        assert_eq!(features.rate_limit_error_code, Some(61440));
+        assert!(features.use_metadata_id);
+
+        let mut startup_options = HashMap::new();
+        features.add_startup_options(&mut startup_options);
+        assert_eq!(
+            startup_options.get("CQL_VERSION").map(String::as_str),
+            Some("3.3.1")
+        );
+        assert_eq!(
+            startup_options
+                .get("SCYLLA_LWT_ADD_METADATA_MARK")
+                .map(String::as_str),
+            Some("LWT_OPTIMIZATION_META_BIT_MASK=2147483648")
+        );
+        assert_eq!(
+            startup_options
+                .get("SCYLLA_RATE_LIMIT_ERROR")
+                .map(String::as_str),
+            Some("")
+        );
+        assert_eq!(
+            startup_options
+                .get("SCYLLA_USE_METADATA_ID")
+                .map(String::as_str),
+            Some("")
+        );
+        assert!(features.uses_metadata_ids(ProtocolVersion::V4));
+    }
+
+    #[test]
+    fn cassandra_features() {
+        let features = ProtocolFeatures::parse(&options(&[
+            ("CQL_VERSION", &["3.4.5"]),
+            ("PROTOCOL_VERSIONS", &["3/v3", "4/v4", "5/v5"]),
+            ("COMPRESSION", &["lz4"]),
+        ]));
+
+        assert_eq!(features.protocol_versions.len(), 3);
+        assert!(features.supports_compression(Compression::LZ4));
+        assert!(!features.supports_compression(Compression::Snappy));
+        assert_eq!(features.shard_info, None);
+        assert_eq!(features.lwt_optimization_mask, None);
+        assert!(!features.uses_metadata_ids(ProtocolVersion::V4));
+        assert!(features.uses_metadata_ids(ProtocolVersion::V5));
+
+        let mut startup_options = HashMap::new();
+        features.add_startup_options(&mut startup_options);
+        assert_eq!(startup_options.len(), 1);
+        assert_eq!(
+            startup_options.get("CQL_VERSION").map(String::as_str),
+            Some("3.4.5")
+        );
+    }
+
+    #[test]
+    fn lwt_optimization_mask() {
+        let mask = |value: &str| {
+            ProtocolFeatures::parse(&options(&[("SCYLLA_LWT_ADD_METADATA_MARK", &[value])]))
+                .lwt_optimization_mask
+        };
+
+        assert_eq!(
+            mask("LWT_OPTIMIZATION_META_BIT_MASK=2147483648"),
+            Some(i32::MIN)
+        );
+        assert_eq!(mask("LWT_OPTIMIZATION_META_BIT_MASK=4"), Some(4));
+        assert_eq!(mask("OTHER_MASK=4"), None);
+        assert_eq!(mask("LWT_OPTIMIZATION_META_BIT_MASK"), None);
+    }
+
+    #[test]
+    fn malformed_extension_values() {
+        let features = ProtocolFeatures::parse(&options(&[
+            ("SCYLLA_LWT_ADD_METADATA_MARK", &["OTHER_MASK=4"]),
+            ("SCYLLA_RATE_LIMIT_ERROR", &["ERROR_CODE"]),
+            ("SCYLLA_SHARD_AWARE_PORT", &["not a port"]),
+        ]));
+
+        assert_eq!(features.lwt_optimization_mask, None);
+        assert_eq!(features.rate_limit_error_code, None);
+        assert_eq!(features.shard_aware_port, None);
+    }
+}
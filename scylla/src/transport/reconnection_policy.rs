@@ -0,0 +1,107 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Decides how long to wait before trying to reopen a broken connection.\
+/// Connections are reopened until they succeed, with a delay before each attempt.
+pub trait ReconnectionPolicy: Send + Sync {
+    /// Returns the delay before the given attempt.\
+    /// Attempts are counted from 0 since the connection last broke.
+    fn next_delay(&self, attempt: u32) -> Duration;
+}
+
+/// Waits the same amount of time before each attempt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstantReconnectionPolicy {
+    delay: Duration,
+}
+
+impl ConstantReconnectionPolicy {
+    pub fn new(delay: Duration) -> Self {
+        ConstantReconnectionPolicy { delay }
+    }
+}
+
+impl ReconnectionPolicy for ConstantReconnectionPolicy {
+    fn next_delay(&self, _attempt: u32) -> Duration {
+        self.delay
+    }
+}
+
+/// Default [ReconnectionPolicy] - doubles the delay after each failed attempt, up to `max_delay`.\
+/// A random jitter of up to half of the delay is subtracted, so that connections broken
+/// at the same time (e.g. by a node restart) don't all reconnect at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExponentialReconnectionPolicy {
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl ExponentialReconnectionPolicy {
+    /// Creates a policy waiting `base_delay` before the first attempt
+    /// and at most `max_delay` before any attempt
+    pub fn new(base_delay: Duration, max_delay: Duration) -> Self {
+        ExponentialReconnectionPolicy {
+            base_delay,
+            max_delay: std::cmp::max(base_delay, max_delay),
+        }
+    }
+
+    // Delay before the given attempt, without jitter
+    fn max_delay_for_attempt(&self, attempt: u32) -> Duration {
+        // 2^31 times any base delay is already more than any reasonable max_delay
+        let multiplier = 1u32 << std::cmp::min(attempt, 31);
+        self.base_delay
+            .checked_mul(multiplier)
+            .map_or(self.max_delay, |delay| std::cmp::min(delay, self.max_delay))
+    }
+}
+
+impl Default for ExponentialReconnectionPolicy {
+    /// Starts with 1 second and grows up to 1 minute
+    fn default() -> Self {
+        ExponentialReconnectionPolicy::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+impl ReconnectionPolicy for ExponentialReconnectionPolicy {
+    fn next_delay(&self, attempt: u32) -> Duration {
+        let delay = self.max_delay_for_attempt(attempt);
+        let jitter = rand::thread_rng().gen_range(0.0..=0.5);
+        delay.mul_f64(1.0 - jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConstantReconnectionPolicy, ExponentialReconnectionPolicy, ReconnectionPolicy};
+    use std::time::Duration;
+
+    #[test]
+    fn constant_delays() {
+        let policy = ConstantReconnectionPolicy::new(Duration::from_millis(100));
+        for attempt in 0..10 {
+            assert_eq!(policy.next_delay(attempt), Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn exponential_delays() {
+        let policy =
+            ExponentialReconnectionPolicy::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        let expected_max = [100, 200, 400, 800, 1000, 1000];
+        for (attempt, max) in expected_max.iter().enumerate() {
+            let max = Duration::from_millis(*max);
+            assert_eq!(policy.max_delay_for_attempt(attempt as u32), max);
+
+            let delay = policy.next_delay(attempt as u32);
+            assert!(delay <= max && delay >= max / 2);
+        }
+
+        // Large attempt numbers don't overflow
+        assert_eq!(
+            policy.max_delay_for_attempt(u32::MAX),
+            Duration::from_secs(1)
+        );
+    }
+}
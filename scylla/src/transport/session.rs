@@ -1,30 +1,43 @@
 use core::ops::Bound::{Included, Unbounded};
 use futures::future::join_all;
 use rand::Rng;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Instant;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::net::lookup_host;
+use uuid::Uuid;
 
 use super::errors::{BadQuery, NewSessionError, QueryError};
-use crate::batch::Batch;
+use crate::batch::{Batch, BatchStatement};
 use crate::cql_to_rust::FromRow;
 use crate::frame::response::cql_to_rust::FromRowError;
 use crate::frame::response::result;
 use crate::frame::response::Response;
 use crate::frame::value::{BatchValues, SerializedValues, ValueList};
+use crate::frame::ProtocolVersion;
 use crate::prepared_statement::{PartitionKeyError, PreparedStatement};
 use crate::query::Query;
 use crate::routing::{murmur3_token, Token};
+use crate::statement::Consistency;
 use crate::transport::cluster::{Cluster, ClusterData};
-use crate::transport::connection::{Connection, ConnectionConfig};
+use crate::transport::connection::{
+    BatchResult, Connection, ConnectionConfig, QueryResult, WarningHandler, MAX_STREAM_IDS,
+};
+use crate::transport::connection_pool::PoolSize;
+use crate::transport::connector::{Connector, TcpConnector};
 use crate::transport::iterator::RowIterator;
 use crate::transport::metrics::{Metrics, MetricsView};
 use crate::transport::node::Node;
+use crate::transport::protocol_features::ProtocolFeatures;
+use crate::transport::reconnection_policy::{ExponentialReconnectionPolicy, ReconnectionPolicy};
+use crate::transport::timestamp_generator::{MonotonicTimestampGenerator, TimestampGenerator};
+use crate::transport::tracing::{self, GetTracingConfig, TracingEvent, TracingInfo};
 use crate::transport::Compression;
 
 pub struct Session {
     cluster: Cluster,
+    used_keyspace: Arc<RwLock<Option<String>>>,
 
     metrics: Arc<Metrics>,
 }
@@ -42,6 +55,75 @@ pub struct SessionConfig {
     /// If it's not supported by database server Session will fall back to no compression.  
     pub compression: Option<Compression>,
     pub tcp_nodelay: bool,
+    /// Enables TCP keepalive probes, with timings configured in the operating system.
+    pub tcp_keepalive: bool,
+
+    /// Handler called with warnings attached by the database to responses.\
+    /// Warnings are always returned in [QueryResult] and [BatchResult] as well.
+    pub warning_handler: Option<Arc<dyn WarningHandler>>,
+
+    /// Highest CQL protocol version to use.\
+    /// If a node doesn't support it, lower versions are tried until one is accepted.
+    pub protocol_version: ProtocolVersion,
+
+    /// Generates client-side default timestamps for requests.\
+    /// If None, timestamps are assigned by the coordinator nodes.
+    pub timestamp_generator: Option<Arc<dyn TimestampGenerator>>,
+
+    /// Keyspace to be used on all connections, see [Session::use_keyspace].\
+    /// Each connection sends `USE` on startup.
+    pub used_keyspace: Option<String>,
+    pub keyspace_case_sensitive: bool,
+
+    /// Decides how long to wait before reopening broken connections
+    pub reconnection_policy: Arc<dyn ReconnectionPolicy>,
+
+    /// Client-side timeout of requests, can be overridden on each statement.\
+    /// If None, requests wait for their responses indefinitely.
+    pub request_timeout: Option<Duration>,
+
+    /// Number of requests waiting to be sent on each connection.\
+    /// When the queue is full, new requests wait until there is place in it.
+    pub submit_queue_size: usize,
+
+    /// Number of requests awaiting responses on each connection, at most 32768.\
+    /// More requests fail with [TooManyInFlightRequests](QueryError::TooManyInFlightRequests).
+    pub max_in_flight_requests: usize,
+
+    /// Idle time after which a heartbeat is sent on a connection, None disables heartbeats.\
+    /// Heartbeats detect connections silently dropped e.g. by a NAT or a load balancer.
+    pub heartbeat_interval: Option<Duration>,
+    /// A connection is reopened if the response to a heartbeat doesn't arrive within this time.
+    pub heartbeat_timeout: Duration,
+
+    /// Number of connections opened to each node, either in total or to each of its shards.\
+    /// Requests are sent on the connection with the least requests awaiting responses.
+    pub pool_size: PoolSize,
+
+    /// Number of connections opened to a shard of a node while looking for one
+    /// handled by the right shard, used when the shard-aware port isn't available.\
+    /// Connections landing on other shards are passed on to the connection pools of these shards.
+    /// If all attempts land on other shards, one of these connections is used instead,
+    /// unless they were all passed on. Then connecting is retried according to the reconnection policy.
+    pub shard_connection_attempts: usize,
+
+    /// Connecting to the shard-aware port is abandoned after this time, in case it's silently
+    /// blocked by a firewall. The regular port is used instead, the shard-aware one is tried
+    /// again when the connection is reopened.
+    pub shard_aware_port_connect_timeout: Duration,
+
+    /// Local address to which connections are bound, e.g. on hosts with multiple network interfaces.\
+    /// Used only for connections to nodes with addresses of the same family (IPv4 or IPv6).
+    /// If None, the system chooses the local address.
+    pub local_ip_address: Option<IpAddr>,
+
+    /// Address family preferred when a hostname of a known node resolves to both IPv4 and IPv6 addresses
+    pub address_family_preference: AddressFamilyPreference,
+
+    /// Opens the streams over which connections talk to nodes, e.g. through a proxy
+    /// or a Unix domain socket. Connections to Scylla's shard-aware port are only opened
+    /// if the connector supports choosing the source port.
+    pub connector: Arc<dyn Connector>,
     /*
     These configuration options will be added in the future:
 
@@ -51,8 +133,6 @@ pub struct SessionConfig {
     pub use_tls: bool,
     pub tls_certificate_path: Option<String>,
 
-    pub tcp_keepalive: bool,
-
     pub load_balancing: Option<String>,
     pub retry_policy: Option<String>,
 
@@ -67,10 +147,32 @@ pub enum KnownNode {
     Address(SocketAddr),
 }
 
+/// Address family chosen when a hostname resolves to both IPv4 and IPv6 addresses.\
+/// If there are no addresses of the preferred family, an address of the other one is used.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AddressFamilyPreference {
+    #[default]
+    PreferIpv4,
+    PreferIpv6,
+}
+
 impl SessionConfig {
     /// Creates a [`SessionConfig`] with default configuration
     /// # Default configuration
     /// * Compression: None
+    /// * Protocol version: 5, lower versions are negotiated if a node doesn't support it
+    /// * Timestamp generator: [MonotonicTimestampGenerator]
+    /// * Reconnection policy: [ExponentialReconnectionPolicy] from 1 second up to 1 minute
+    /// * Request timeout: 30 seconds
+    /// * Submit queue size: 128
+    /// * Max in-flight requests: 32768 (all stream ids)
+    /// * Heartbeats: sent after 30 seconds of idleness, with a 30 second timeout
+    /// * Pool size: 1 connection per shard
+    /// * Shard connection attempts: 10
+    /// * Shard-aware port connect timeout: 5 seconds
+    /// * Local IP address: None, chosen by the system
+    /// * Address family preference: IPv4
+    /// * Connector: [TcpConnector], connecting directly over TCP
     ///
     /// # Example
     /// ```
@@ -82,6 +184,24 @@ impl SessionConfig {
             known_nodes: Vec::new(),
             compression: None,
             tcp_nodelay: false,
+            tcp_keepalive: false,
+            warning_handler: None,
+            protocol_version: Default::default(),
+            timestamp_generator: Some(Arc::new(MonotonicTimestampGenerator::new())),
+            used_keyspace: None,
+            keyspace_case_sensitive: false,
+            reconnection_policy: Arc::new(ExponentialReconnectionPolicy::default()),
+            request_timeout: Some(Duration::from_secs(30)),
+            submit_queue_size: 128,
+            max_in_flight_requests: MAX_STREAM_IDS,
+            heartbeat_interval: Some(Duration::from_secs(30)),
+            heartbeat_timeout: Duration::from_secs(30),
+            pool_size: Default::default(),
+            shard_connection_attempts: 10,
+            shard_aware_port_connect_timeout: Duration::from_secs(5),
+            local_ip_address: None,
+            address_family_preference: Default::default(),
+            connector: Arc::new(TcpConnector),
         }
     }
 
@@ -142,10 +262,28 @@ impl SessionConfig {
     }
 
     /// Makes a config that should be used in Connection
-    fn get_connection_config(&self) -> ConnectionConfig {
+    pub(crate) fn get_connection_config(&self) -> ConnectionConfig {
+        // The keyspace is set by Session::connect() after it verifies it's correct
         ConnectionConfig {
             compression: self.compression,
             tcp_nodelay: self.tcp_nodelay,
+            tcp_keepalive: self.tcp_keepalive,
+            warning_handler: self.warning_handler.clone(),
+            protocol_version: self.protocol_version,
+            negotiated_protocol_version: Default::default(),
+            timestamp_generator: self.timestamp_generator.clone(),
+            used_keyspace: Default::default(),
+            reconnection_policy: self.reconnection_policy.clone(),
+            request_timeout: self.request_timeout,
+            submit_queue_size: self.submit_queue_size,
+            max_in_flight_requests: self.max_in_flight_requests,
+            heartbeat_interval: self.heartbeat_interval,
+            heartbeat_timeout: self.heartbeat_timeout,
+            pool_size: self.pool_size,
+            shard_connection_attempts: self.shard_connection_attempts,
+            shard_aware_port_connect_timeout: self.shard_aware_port_connect_timeout,
+            local_ip_address: self.local_ip_address,
+            connector: self.connector.clone(),
         }
     }
 }
@@ -215,16 +353,32 @@ impl Session {
             };
         }
 
-        let resolve_futures = to_resolve.into_iter().map(resolve_hostname);
+        let resolve_futures = to_resolve
+            .into_iter()
+            .map(|hostname| resolve_hostname(hostname, config.address_family_preference));
         let resolved: Vec<SocketAddr> = futures::future::try_join_all(resolve_futures).await?;
 
         node_addresses.extend(resolved);
 
         // Start the session
-        let cluster = Cluster::new(&node_addresses, config.get_connection_config()).await?;
+        let connection_config = config.get_connection_config();
+        let used_keyspace = connection_config.used_keyspace.clone();
+        let cluster = Cluster::new(&node_addresses, connection_config).await?;
         let metrics = Arc::new(Metrics::new());
 
-        Ok(Session { cluster, metrics })
+        let session = Session {
+            cluster,
+            used_keyspace,
+            metrics,
+        };
+
+        if let Some(keyspace_name) = config.used_keyspace {
+            session
+                .use_keyspace(keyspace_name, config.keyspace_case_sensitive)
+                .await?;
+        }
+
+        Ok(session)
     }
 
     // TODO: Should return an iterator over results
@@ -241,7 +395,7 @@ impl Session {
         &self,
         query: impl Into<Query>,
         values: impl ValueList,
-    ) -> Result<Option<Vec<result::Row>>, QueryError> {
+    ) -> Result<QueryResult, QueryError> {
         let now = Instant::now();
         self.metrics.inc_total_nonpaged_queries();
         let result = self.query_no_metrics(query, values).await;
@@ -256,11 +410,87 @@ impl Session {
         &self,
         query: impl Into<Query>,
         values: impl ValueList,
-    ) -> Result<Option<Vec<result::Row>>, QueryError> {
-        self.any_connection()
+    ) -> Result<QueryResult, QueryError> {
+        let result = self
+            .any_connection()
             .await?
-            .query_single_page(query, values)
-            .await
+            .query(&query.into(), values, None)
+            .await?
+            .into_query_result()?;
+
+        self.propagate_set_keyspace(&result).await?;
+        Ok(result)
+    }
+
+    // A USE statement changed the keyspace on one connection, set it on all of them
+    async fn propagate_set_keyspace(&self, result: &QueryResult) -> Result<(), QueryError> {
+        match &result.set_keyspace {
+            Some(set_keyspace) => {
+                self.use_keyspace(set_keyspace.keyspace_name.clone(), true)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the keyspace used by all connections of the session, the same as sending `USE <keyspace_name>`.\
+    /// Connections opened later (e.g. after a reconnect) will use the keyspace as well,
+    /// unless the database rejects it - then they keep using the previous keyspace.
+    ///
+    /// `USE` statements sent with [Session::query] or [Session::execute] have the same effect.
+    /// Sent with [Session::query_iter] or [Session::execute_iter] they change the keyspace
+    /// of a single connection only. Batches can't contain `USE` statements.
+    /// # Arguments
+    ///
+    /// * `keyspace_name` - keyspace to use, 1-48 alphanumeric characters or underscores
+    /// * `case_sensitive` - if false, the name is converted to lowercase like an unquoted CQL identifier
+    ///
+    /// # Example
+    /// ```rust
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let session: Session = SessionBuilder::new().known_node("127.0.0.1:9042").build().await?;
+    /// session.use_keyspace("my_keyspace", false).await?;
+    ///
+    /// // Tables in my_keyspace can be queried without the keyspace prefix
+    /// session.query("SELECT a, b FROM my_table", &[]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn use_keyspace(
+        &self,
+        keyspace_name: impl Into<String>,
+        case_sensitive: bool,
+    ) -> Result<(), QueryError> {
+        let mut keyspace_name: String = keyspace_name.into();
+        verify_keyspace_name(&keyspace_name)?;
+        if !case_sensitive {
+            keyspace_name = keyspace_name.to_lowercase();
+        }
+
+        // Set the keyspace for new connections first, so that none of them is missed
+        let previous_keyspace = self
+            .used_keyspace
+            .write()
+            .unwrap()
+            .replace(keyspace_name.clone());
+
+        let connections = self.cluster.get_working_connections().await?;
+        let results = join_all(connections.iter().map(|c| c.use_keyspace(&keyspace_name))).await;
+        let result = results.into_iter().collect::<Result<(), QueryError>>();
+
+        // The database rejected the keyspace (e.g. it doesn't exist), new connections
+        // would fail to open with it. Errors of broken connections don't matter,
+        // they are replaced by new connections using the keyspace.
+        if let Err(QueryError::DBError(_)) = &result {
+            let mut used_keyspace = self.used_keyspace.write().unwrap();
+            // Unless it was changed by a concurrent call in the meantime
+            if used_keyspace.as_ref() == Some(&keyspace_name) {
+                *used_keyspace = previous_keyspace;
+            }
+        }
+
+        result
     }
 
     pub async fn query_iter(
@@ -329,7 +559,7 @@ impl Session {
         &self,
         prepared: &PreparedStatement,
         values: impl ValueList,
-    ) -> Result<Option<Vec<result::Row>>, QueryError> {
+    ) -> Result<QueryResult, QueryError> {
         let now = Instant::now();
         self.metrics.inc_total_nonpaged_queries();
         let result = self.execute_no_metrics(prepared, values).await;
@@ -344,51 +574,43 @@ impl Session {
         &self,
         prepared: &PreparedStatement,
         values: impl ValueList,
-    ) -> Result<Option<Vec<result::Row>>, QueryError> {
+    ) -> Result<QueryResult, QueryError> {
         // FIXME: Prepared statement ids are local to a node, so we must make sure
         // that prepare() sends to all nodes and keeps all ids.
         let serialized_values = values.serialized()?;
 
         let token = calculate_token(prepared, &serialized_values)?;
+        // pick_connection() always chooses the primary replica of the token,
+        // which is required for LWT statements, see lwt_aware_connection()
         let connection = self.pick_connection(token).await?;
-        let result = connection
+        let response = connection
             .execute(prepared, &serialized_values, None)
             .await?;
-        match result {
-            Response::Error(err) => {
-                match err.code {
-                    9472 => {
-                        // Repreparation of a statement is needed
-                        let reprepared = connection.prepare(prepared.get_statement()).await?;
-                        // Reprepared statement should keep its id - it's the md5 sum
-                        // of statement contents
-                        if reprepared.get_id() != prepared.get_id() {
-                            return Err(QueryError::ProtocolError(
-                                "Prepared statement Id changed, md5 sum should stay the same",
-                            ));
-                        }
-
-                        let result = connection
-                            .execute(prepared, &serialized_values, None)
-                            .await?;
-                        match result {
-                            Response::Error(err) => Err(err.into()),
-                            Response::Result(result::Result::Rows(rs)) => Ok(Some(rs.rows)),
-                            Response::Result(_) => Ok(None),
-                            _ => Err(QueryError::ProtocolError(
-                                "EXECUTE: Unexpected server response",
-                            )),
-                        }
-                    }
-                    _ => Err(err.into()),
+
+        let result = match response.response {
+            Response::Error(err) if err.code == 9472 => {
+                // Repreparation of a statement is needed
+                let reprepared = connection.prepare(prepared.get_statement()).await?;
+                // Reprepared statement should keep its id - it's the md5 sum
+                // of statement contents
+                if reprepared.get_id() != prepared.get_id() {
+                    return Err(QueryError::ProtocolError(
+                        "Prepared statement Id changed, md5 sum should stay the same",
+                    ));
                 }
+                // Result metadata might have changed along with the schema
+                prepared.update_result_metadata_from(&reprepared);
+
+                connection
+                    .execute(prepared, &serialized_values, None)
+                    .await?
+                    .into_query_result()
             }
-            Response::Result(result::Result::Rows(rs)) => Ok(Some(rs.rows)),
-            Response::Result(_) => Ok(None),
-            _ => Err(QueryError::ProtocolError(
-                "EXECUTE: Unexpected server response",
-            )),
-        }
+            _ => response.into_query_result(),
+        }?;
+
+        self.propagate_set_keyspace(&result).await?;
+        Ok(result)
     }
 
     pub async fn execute_iter(
@@ -396,11 +618,15 @@ impl Session {
         prepared: impl Into<PreparedStatement>,
         values: impl ValueList,
     ) -> Result<RowIterator, QueryError> {
+        let prepared: PreparedStatement = prepared.into();
         let serialized_values = values.serialized()?;
+        let connection = self
+            .lwt_aware_connection(&prepared, &serialized_values)
+            .await?;
 
         Ok(RowIterator::new_for_prepared_statement(
-            self.any_connection().await?,
-            prepared.into(),
+            connection,
+            prepared,
             serialized_values.into_owned(),
             self.metrics.clone(),
         ))
@@ -411,23 +637,146 @@ impl Session {
     ///
     /// * `batch` - batch to be performed
     /// * `values` - values bound to the query
-    pub async fn batch(&self, batch: &Batch, values: impl BatchValues) -> Result<(), QueryError> {
+    pub async fn batch(
+        &self,
+        batch: &Batch,
+        values: impl BatchValues,
+    ) -> Result<BatchResult, QueryError> {
         // FIXME: Prepared statement ids are local to a node
         // this method does not handle this
-        let response = self.any_connection().await?.batch(&batch, values).await?;
-        match response {
+        let connection = match lwt_batch_token(batch, &values)? {
+            Some(token) => self.pick_connection(token).await?,
+            None => self.any_connection().await?,
+        };
+        let response = connection.batch(&batch, values).await?;
+        match response.response {
             Response::Error(err) => Err(err.into()),
-            Response::Result(_) => Ok(()),
+            // Conditional batches return rows with the `[applied]` column
+            Response::Result(result::Result::Rows(rs)) => Ok(BatchResult {
+                rows: Some(rs.rows),
+                col_specs: rs.metadata.col_specs,
+                warnings: response.warnings,
+                tracing_id: response.tracing_id,
+                custom_payload: response.custom_payload,
+            }),
+            Response::Result(_) => Ok(BatchResult {
+                warnings: response.warnings,
+                tracing_id: response.tracing_id,
+                custom_payload: response.custom_payload,
+                ..Default::default()
+            }),
             _ => Err(QueryError::ProtocolError(
                 "BATCH: Unexpected server response",
             )),
         }
     }
 
+    /// Queries tracing info with default retry settings.\
+    /// Tracing info might not be available immediately on queried node -
+    /// that's why the driver performs a few attempts with sleeps in between.
+    ///
+    /// See [GetTracingConfig] for the default retry settings
+    /// # Arguments
+    ///
+    /// * `tracing_id` - tracing id returned in [QueryResult] of a query with tracing enabled
+    pub async fn get_tracing_info(&self, tracing_id: &Uuid) -> Result<TracingInfo, QueryError> {
+        self.get_tracing_info_custom(tracing_id, &GetTracingConfig::default())
+            .await
+    }
+
+    /// Queries tracing info with custom retry settings.\
+    /// Polls `system_traces.sessions` and `system_traces.events` until the tracing
+    /// session is complete or all attempts are exhausted
+    /// # Arguments
+    ///
+    /// * `tracing_id` - tracing id returned in [QueryResult] of a query with tracing enabled
+    /// * `config` - number of attempts, interval between them and consistency of the queries
+    pub async fn get_tracing_info_custom(
+        &self,
+        tracing_id: &Uuid,
+        config: &GetTracingConfig,
+    ) -> Result<TracingInfo, QueryError> {
+        for _ in 0..config.attempts.get() {
+            if let Some(tracing_info) = self
+                .try_getting_tracing_info(tracing_id, config.consistency)
+                .await?
+            {
+                if tracing_info.is_complete() {
+                    return Ok(tracing_info);
+                }
+            }
+
+            tokio::time::sleep(config.interval).await;
+        }
+
+        Err(QueryError::ProtocolError(
+            "Tracing info is not complete after all attempts, maybe it didn't reach this node yet",
+        ))
+    }
+
+    // Tries getting the tracing info
+    // If the queries return 0 rows then returns None - the information didn't reach this node yet
+    // If there is some other error returns this error
+    async fn try_getting_tracing_info(
+        &self,
+        tracing_id: &Uuid,
+        consistency: Consistency,
+    ) -> Result<Option<TracingInfo>, QueryError> {
+        let mut traces_session_query = Query::new(tracing::TRACES_SESSION_QUERY_STR.to_string());
+        traces_session_query.set_consistency(consistency);
+        traces_session_query.disable_paging();
+
+        let mut traces_events_query = Query::new(tracing::TRACES_EVENTS_QUERY_STR.to_string());
+        traces_events_query.set_consistency(consistency);
+        traces_events_query.disable_paging();
+
+        let (traces_session_res, traces_events_res) = tokio::try_join!(
+            self.query(traces_session_query, (tracing_id,)),
+            self.query(traces_events_query, (tracing_id,))
+        )?;
+
+        let session_rows = traces_session_res.rows.ok_or(QueryError::ProtocolError(
+            "system_traces.sessions query response was not Rows",
+        ))?;
+
+        let mut tracing_info: TracingInfo = match session_rows.into_iter().next() {
+            Some(row) => tracing::tracing_info_from_row(row)?,
+            None => return Ok(None),
+        };
+
+        let event_rows = traces_events_res.rows.ok_or(QueryError::ProtocolError(
+            "system_traces.events query response was not Rows",
+        ))?;
+
+        tracing_info.events = event_rows
+            .into_iter()
+            .map(tracing::tracing_event_from_row)
+            .collect::<Result<Vec<TracingEvent>, QueryError>>()?;
+
+        Ok(Some(tracing_info))
+    }
+
     pub async fn refresh_topology(&self) -> Result<(), QueryError> {
         self.cluster.refresh_topology().await
     }
 
+    /// Returns features reported in SUPPORTED by each known node.\
+    /// Nodes to which the driver currently can't connect are omitted.
+    pub async fn get_protocol_features(&self) -> HashMap<SocketAddr, ProtocolFeatures> {
+        let cluster_data: Arc<ClusterData> = self.cluster.get_data();
+        let mut result = HashMap::with_capacity(cluster_data.known_peers.len());
+
+        for (address, node) in &cluster_data.known_peers {
+            if let Ok(features) = node.get_protocol_features().await {
+                result.insert(*address, features);
+            }
+        }
+
+        result
+    }
+
+    // Picks a connection to the primary replica (owner of the token),
+    // the choice must stay deterministic for prepared LWT statements
     async fn pick_connection(&self, t: Token) -> Result<Arc<Connection>, QueryError> {
         // TODO: we try only the owner of the range (vnode) that the token lies in
         // we should calculate the *set* of replicas for this token, using the replication strategy
@@ -446,6 +795,22 @@ impl Session {
         owner.connection_for_token(t).await
     }
 
+    // LWT statements always go to the primary replica of their partition, while other statements
+    // can go to any node. Paxos rounds of a partition coordinated by different nodes contend
+    // with each other, so the replica must stay the same for all executions.
+    async fn lwt_aware_connection(
+        &self,
+        prepared: &PreparedStatement,
+        values: &SerializedValues,
+    ) -> Result<Arc<Connection>, QueryError> {
+        if prepared.is_lwt() {
+            self.pick_connection(calculate_token(prepared, values)?)
+                .await
+        } else {
+            self.any_connection().await
+        }
+    }
+
     async fn any_connection(&self) -> Result<Arc<Connection>, QueryError> {
         let random_token: Token = Token {
             value: rand::thread_rng().gen(),
@@ -465,6 +830,31 @@ impl Session {
     }
 }
 
+// Token of a batch containing a prepared LWT statement, None if it contains none.
+// Conditional batches can modify only a single partition,
+// so the token of the LWT statement is the token of the whole batch.
+fn lwt_batch_token(batch: &Batch, values: &impl BatchValues) -> Result<Option<Token>, QueryError> {
+    let lwt_statement = batch
+        .get_statements()
+        .iter()
+        .enumerate()
+        .find_map(|(n, statement)| match statement {
+            BatchStatement::PreparedStatement(prepared) if prepared.is_lwt() => Some((n, prepared)),
+            _ => None,
+        });
+
+    let (n, prepared) = match lwt_statement {
+        Some(lwt_statement) if lwt_statement.0 < values.len() => lwt_statement,
+        _ => return Ok(None),
+    };
+
+    let mut buf: Vec<u8> = Vec::new();
+    values.write_nth_to_request(n, &mut buf)?;
+    let statement_values = SerializedValues::new_from_frame(&mut &buf[..])?;
+
+    Ok(Some(calculate_token(prepared, &statement_values)?))
+}
+
 fn calculate_token(
     stmt: &PreparedStatement,
     values: &SerializedValues,
@@ -489,20 +879,123 @@ fn calculate_token(
     Ok(murmur3_token(partition_key))
 }
 
+// Keyspace names can contain only alphanumeric characters and underscores, up to 48 of them
+fn verify_keyspace_name(keyspace_name: &str) -> Result<(), BadQuery> {
+    let is_valid = !keyspace_name.is_empty()
+        && keyspace_name.len() <= 48
+        && keyspace_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(BadQuery::BadKeyspaceName(keyspace_name.to_string()))
+    }
+}
+
 // Resolve the given hostname using a DNS lookup if necessary.
 // The resolution may return multiple IPs and the function returns one of them.
-// It prefers to return IPv4s first, and only if there are none, IPv6s.
-async fn resolve_hostname(hostname: &str) -> Result<SocketAddr, NewSessionError> {
+// It prefers to return addresses of the preferred family, and only if there are none, the other ones.
+async fn resolve_hostname(
+    hostname: &str,
+    preference: AddressFamilyPreference,
+) -> Result<SocketAddr, NewSessionError> {
     let failed_err = NewSessionError::FailedToResolveAddress(hostname.to_string());
+    choose_address(lookup_host(hostname).await?, preference).ok_or(failed_err)
+}
+
+fn choose_address(
+    addresses: impl IntoIterator<Item = SocketAddr>,
+    preference: AddressFamilyPreference,
+) -> Option<SocketAddr> {
+    let prefer_ipv4 = preference == AddressFamilyPreference::PreferIpv4;
     let mut ret = None;
-    for a in lookup_host(hostname).await? {
-        match a {
-            SocketAddr::V4(_) => return Ok(a),
-            _ => {
-                ret = Some(a);
-            }
+    for a in addresses {
+        if a.is_ipv4() == prefer_ipv4 {
+            return Some(a);
         }
+        ret = ret.or(Some(a));
     }
 
-    ret.ok_or(failed_err)
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        calculate_token, choose_address, lwt_batch_token, verify_keyspace_name,
+        AddressFamilyPreference,
+    };
+    use crate::batch::Batch;
+    use crate::frame::response::result::PreparedMetadata;
+    use crate::frame::value::ValueList;
+    use crate::prepared_statement::PreparedStatement;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn keyspace_name_verification() {
+        assert!(verify_keyspace_name("ks").is_ok());
+        assert!(verify_keyspace_name("My_Keyspace_1").is_ok());
+        assert!(verify_keyspace_name(&"a".repeat(48)).is_ok());
+
+        assert!(verify_keyspace_name("").is_err());
+        assert!(verify_keyspace_name(&"a".repeat(49)).is_err());
+        assert!(verify_keyspace_name("ks; DROP TABLE t").is_err());
+        assert!(verify_keyspace_name("\"ks\"").is_err());
+    }
+
+    #[test]
+    fn address_family_preference() {
+        let ipv4: SocketAddr = "127.0.0.1:9042".parse().unwrap();
+        let ipv6: SocketAddr = "[::1]:9042".parse().unwrap();
+        let ipv4_2: SocketAddr = "127.0.0.2:9042".parse().unwrap();
+
+        let prefer_ipv4 = AddressFamilyPreference::PreferIpv4;
+        let prefer_ipv6 = AddressFamilyPreference::PreferIpv6;
+
+        assert_eq!(
+            choose_address(vec![ipv6, ipv4, ipv4_2], prefer_ipv4),
+            Some(ipv4)
+        );
+        assert_eq!(choose_address(vec![ipv4, ipv6], prefer_ipv6), Some(ipv6));
+
+        // Addresses of the other family are used if there are no preferred ones
+        assert_eq!(choose_address(vec![ipv6], prefer_ipv4), Some(ipv6));
+        assert_eq!(choose_address(vec![ipv4, ipv4_2], prefer_ipv6), Some(ipv4));
+        assert_eq!(choose_address(vec![], prefer_ipv4), None);
+    }
+
+    #[test]
+    fn lwt_batch_routing() {
+        let metadata = PreparedMetadata {
+            flags: 0,
+            col_count: 2,
+            pk_indexes: vec![1],
+            col_specs: Vec::new(),
+        };
+        let mut lwt = PreparedStatement::new(
+            "lwt-id".into(),
+            metadata.clone(),
+            "UPDATE ks.t SET a = ? WHERE b = ? IF a = 0".to_string(),
+        );
+        lwt.set_is_lwt(true);
+        let not_lwt = PreparedStatement::new(
+            "id".into(),
+            metadata,
+            "UPDATE ks.t SET a = ? WHERE b = ?".to_string(),
+        );
+
+        let mut batch = Batch::default();
+        batch.append_statement(not_lwt.clone());
+        assert_eq!(lwt_batch_token(&batch, &((1, 2),)).unwrap(), None);
+
+        // The batch goes to the owner of the LWT statement's partition
+        batch.append_statement(lwt.clone());
+        let lwt_token = calculate_token(&lwt, &(3, 4).serialized().unwrap()).unwrap();
+        assert_eq!(
+            lwt_batch_token(&batch, &((1, 2), (3, 4))).unwrap(),
+            Some(lwt_token)
+        );
+    }
 }
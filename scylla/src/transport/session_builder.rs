@@ -1,7 +1,15 @@
+use super::connection::{WarningHandler, MAX_STREAM_IDS};
+use super::connection_pool::PoolSize;
+use super::connector::Connector;
 use super::errors::NewSessionError;
-use super::session::{Session, SessionConfig};
+use super::reconnection_policy::ReconnectionPolicy;
+use super::session::{AddressFamilyPreference, Session, SessionConfig};
+use super::timestamp_generator::TimestampGenerator;
 use super::Compression;
-use std::net::SocketAddr;
+use crate::frame::ProtocolVersion;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// SessionBuilder is used to create new Session instances
 /// # Example
@@ -154,6 +162,385 @@ impl SessionBuilder {
         self
     }
 
+    /// Enable TCP keepalive probes, their timings are configured in the operating system.\
+    /// The default is false.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .tcp_keepalive(true)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tcp_keepalive(mut self, keepalive: bool) -> Self {
+        self.config.tcp_keepalive = keepalive;
+        self
+    }
+
+    /// Set a handler called with warnings sent by the database.  
+    /// By default warnings are only returned in `QueryResult` and `BatchResult`.  
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::net::SocketAddr;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .warning_handler(|addr: SocketAddr, warnings: &[String]| {
+    ///         for warning in warnings {
+    ///             println!("Warning from {}: {}", addr, warning);
+    ///         }
+    ///     })
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn warning_handler(mut self, handler: impl WarningHandler + 'static) -> Self {
+        self.config.warning_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Set the highest CQL protocol version the driver will try to use.\
+    /// Connections negotiate down to a lower version if the server doesn't support it.\
+    /// Default is version 5.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::frame::ProtocolVersion;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .protocol_version(ProtocolVersion::V3)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.config.protocol_version = version;
+        self
+    }
+
+    /// Set the generator of client-side default timestamps attached to requests.\
+    /// By default [MonotonicTimestampGenerator](super::timestamp_generator::MonotonicTimestampGenerator) is used.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::transport::timestamp_generator::MonotonicTimestampGenerator;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .timestamp_generator(MonotonicTimestampGenerator::new())
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn timestamp_generator(mut self, generator: impl TimestampGenerator + 'static) -> Self {
+        self.config.timestamp_generator = Some(Arc::new(generator));
+        self
+    }
+
+    /// Set the keyspace to be used on all connections.\
+    /// Each connection will send `USE keyspace_name` on startup, see [Session::use_keyspace].
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .use_keyspace("my_keyspace_name", false)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn use_keyspace(mut self, keyspace_name: impl Into<String>, case_sensitive: bool) -> Self {
+        self.config.used_keyspace = Some(keyspace_name.into());
+        self.config.keyspace_case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Set the policy deciding how long to wait before reopening broken connections.\
+    /// By default [ExponentialReconnectionPolicy](super::reconnection_policy::ExponentialReconnectionPolicy)
+    /// waits from 1 second up to 1 minute.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::transport::reconnection_policy::ConstantReconnectionPolicy;
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .reconnection_policy(ConstantReconnectionPolicy::new(Duration::from_millis(500)))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reconnection_policy(mut self, policy: impl ReconnectionPolicy + 'static) -> Self {
+        self.config.reconnection_policy = Arc::new(policy);
+        self
+    }
+
+    /// Set the default client-side timeout of requests, None disables it.\
+    /// Requests which don't receive a response in time fail with
+    /// [RequestTimeout](crate::transport::errors::QueryError::RequestTimeout).
+    /// Statements can override it with `set_request_timeout`.\
+    /// The default timeout is 30 seconds.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .request_timeout(Some(Duration::from_secs(5)))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
+
+    /// Set the number of requests waiting to be sent on each connection.\
+    /// When the queue is full, new requests wait (within their timeout) until there is place in it.
+    /// The default size is 128.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .submit_queue_size(1024)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn submit_queue_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "submit queue size must be larger than 0");
+        self.config.submit_queue_size = size;
+        self
+    }
+
+    /// Set the limit of requests awaiting responses on each connection.\
+    /// Requests above the limit fail immediately with
+    /// [TooManyInFlightRequests](crate::transport::errors::QueryError::TooManyInFlightRequests).
+    /// The limit can't be larger than the number of stream ids, 32768, which is the default.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .max_in_flight_requests(2048)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_in_flight_requests(mut self, max: usize) -> Self {
+        self.config.max_in_flight_requests = std::cmp::min(max, MAX_STREAM_IDS);
+        self
+    }
+
+    /// Set the idle time after which a heartbeat (OPTIONS request) is sent on a connection,
+    /// None disables heartbeats.\
+    /// Heartbeats detect connections silently dropped e.g. by a NAT or a load balancer.
+    /// The default interval is 30 seconds.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .heartbeat_interval(Some(Duration::from_secs(10)))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.heartbeat_interval = interval;
+        self
+    }
+
+    /// Set the time to wait for a response to a heartbeat, after which the connection is reopened.\
+    /// The default timeout is 30 seconds.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .heartbeat_timeout(Duration::from_secs(5))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.config.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Set the number of connections opened to each node.\
+    /// Requests are sent on the connection with the least requests awaiting responses,
+    /// broken connections are reopened in the background.
+    /// The default is one connection per shard.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::transport::connection_pool::PoolSize;
+    /// # use std::num::NonZeroUsize;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .pool_size(PoolSize::PerShard(NonZeroUsize::new(2).unwrap()))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pool_size(mut self, size: PoolSize) -> Self {
+        self.config.pool_size = size;
+        self
+    }
+
+    /// Set the number of connections opened to the regular port of a node while looking for one
+    /// handled by a given shard. Used when Scylla's shard-aware port isn't available,
+    /// e.g. on older Scylla versions or when it's blocked by a firewall.
+    /// If none of them lands on the shard, the reconnection policy decides when to try again.
+    /// The default is 10 attempts.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .shard_connection_attempts(32)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shard_connection_attempts(mut self, attempts: usize) -> Self {
+        self.config.shard_connection_attempts = attempts;
+        self
+    }
+
+    /// Set the time after which connecting to Scylla's shard-aware port is abandoned,
+    /// in case it's silently blocked by a firewall. The regular port is used instead,
+    /// the shard-aware one is tried again when the connection is reopened.
+    /// The default is 5 seconds.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .shard_aware_port_connect_timeout(Duration::from_secs(1))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shard_aware_port_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.shard_aware_port_connect_timeout = timeout;
+        self
+    }
+
+    /// Set the local address to which connections are bound, e.g. to choose the network interface
+    /// on hosts with multiple ones. It's used only for connections to nodes with addresses
+    /// of the same family (IPv4 or IPv6). By default the system chooses the local address.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use std::net::{IpAddr, Ipv6Addr};
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("[::1]:9042")
+    ///     .local_ip_address(Some(IpAddr::V6(Ipv6Addr::LOCALHOST)))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn local_ip_address(mut self, address: Option<IpAddr>) -> Self {
+        self.config.local_ip_address = address;
+        self
+    }
+
+    /// Set the address family chosen when a hostname of a known node resolves
+    /// to both IPv4 and IPv6 addresses. IPv4 is preferred by default.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::transport::session::AddressFamilyPreference;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("db1.example.com:9042")
+    ///     .address_family_preference(AddressFamilyPreference::PreferIpv6)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn address_family_preference(mut self, preference: AddressFamilyPreference) -> Self {
+        self.config.address_family_preference = preference;
+        self
+    }
+
+    /// Set the connector opening the streams over which connections talk to nodes.\
+    /// By default [TcpConnector](super::connector::TcpConnector) connects directly over TCP,
+    /// a custom connector can e.g. go through a SOCKS5 proxy.
+    ///
+    /// # Example
+    /// ```
+    /// # use scylla::{Session, SessionBuilder};
+    /// # use scylla::transport::connector::UnixSocketConnector;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session: Session = SessionBuilder::new()
+    ///     .known_node("127.0.0.1:9042")
+    ///     .connector(UnixSocketConnector::new("/var/lib/scylla/cql.m"))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connector(mut self, connector: impl Connector + 'static) -> Self {
+        self.config.connector = Arc::new(connector);
+        self
+    }
+
     /// Builds the Session after setting all the options
     ///
     /// # Example
@@ -184,9 +571,16 @@ impl Default for SessionBuilder {
 #[cfg(test)]
 mod tests {
     use super::SessionBuilder;
-    use crate::transport::session::KnownNode;
+    use crate::frame::ProtocolVersion;
+    use crate::transport::connection_pool::PoolSize;
+    #[cfg(unix)]
+    use crate::transport::connector::UnixSocketConnector;
+    use crate::transport::reconnection_policy::ConstantReconnectionPolicy;
+    use crate::transport::session::{AddressFamilyPreference, KnownNode};
     use crate::transport::Compression;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::num::NonZeroUsize;
+    use std::time::Duration;
 
     #[test]
     fn default_session_builder() {
@@ -279,6 +673,185 @@ mod tests {
         assert_eq!(builder.config.tcp_nodelay, false);
     }
 
+    #[test]
+    fn tcp_keepalive() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.tcp_keepalive, false);
+
+        builder = builder.tcp_keepalive(true);
+        assert_eq!(builder.config.tcp_keepalive, true);
+    }
+
+    #[test]
+    fn heartbeats() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(
+            builder.config.heartbeat_interval,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(builder.config.heartbeat_timeout, Duration::from_secs(30));
+
+        builder = builder
+            .heartbeat_interval(None)
+            .heartbeat_timeout(Duration::from_secs(1));
+        assert_eq!(builder.config.heartbeat_interval, None);
+        assert_eq!(builder.config.heartbeat_timeout, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn pool_size() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.pool_size, PoolSize::default());
+
+        let size = PoolSize::PerHost(NonZeroUsize::new(4).unwrap());
+        builder = builder.pool_size(size);
+        assert_eq!(builder.config.pool_size, size);
+    }
+
+    #[test]
+    fn shard_connection_attempts() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.shard_connection_attempts, 10);
+
+        builder = builder.shard_connection_attempts(32);
+        assert_eq!(builder.config.shard_connection_attempts, 32);
+    }
+
+    #[test]
+    fn shard_aware_port_connect_timeout() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(
+            builder.config.shard_aware_port_connect_timeout,
+            Duration::from_secs(5)
+        );
+
+        builder = builder.shard_aware_port_connect_timeout(Duration::from_millis(500));
+        assert_eq!(
+            builder.config.shard_aware_port_connect_timeout,
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn local_ip_address() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.local_ip_address, None);
+
+        let address = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        builder = builder.local_ip_address(Some(address));
+        assert_eq!(builder.config.local_ip_address, Some(address));
+    }
+
+    #[test]
+    fn address_family_preference() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(
+            builder.config.address_family_preference,
+            AddressFamilyPreference::PreferIpv4
+        );
+
+        builder = builder.address_family_preference(AddressFamilyPreference::PreferIpv6);
+        assert_eq!(
+            builder.config.address_family_preference,
+            AddressFamilyPreference::PreferIpv6
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connector() {
+        let mut builder = SessionBuilder::new();
+        assert!(builder.config.connector.supports_source_port());
+
+        builder = builder.connector(UnixSocketConnector::new("/tmp/scylla.sock"));
+        assert!(!builder.config.connector.supports_source_port());
+    }
+
+    #[test]
+    fn warning_handler() {
+        let mut builder = SessionBuilder::new();
+        assert!(builder.config.warning_handler.is_none());
+
+        builder = builder.warning_handler(|_addr: SocketAddr, _warnings: &[String]| {});
+        assert!(builder.config.warning_handler.is_some());
+    }
+
+    #[test]
+    fn protocol_version() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.protocol_version, ProtocolVersion::V5);
+
+        builder = builder.protocol_version(ProtocolVersion::V3);
+        assert_eq!(builder.config.protocol_version, ProtocolVersion::V3);
+    }
+
+    #[test]
+    fn timestamp_generator() {
+        let mut builder = SessionBuilder::new();
+        assert!(builder.config.timestamp_generator.is_some());
+
+        builder = builder.timestamp_generator(|| 42);
+        let generator = builder.config.timestamp_generator.as_ref().unwrap();
+        assert_eq!(generator.next_timestamp(), 42);
+    }
+
+    #[test]
+    fn use_keyspace() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.used_keyspace, None);
+        assert_eq!(builder.config.keyspace_case_sensitive, false);
+
+        builder = builder.use_keyspace("ks_name_1", true);
+        assert_eq!(builder.config.used_keyspace, Some("ks_name_1".to_string()));
+        assert_eq!(builder.config.keyspace_case_sensitive, true);
+    }
+
+    #[test]
+    fn reconnection_policy() {
+        let mut builder = SessionBuilder::new();
+        let default_delay = builder.config.reconnection_policy.next_delay(0);
+        assert!(default_delay <= Duration::from_secs(1));
+
+        builder =
+            builder.reconnection_policy(ConstantReconnectionPolicy::new(Duration::from_millis(10)));
+        assert_eq!(
+            builder.config.reconnection_policy.next_delay(5),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn request_timeout() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(
+            builder.config.request_timeout,
+            Some(Duration::from_secs(30))
+        );
+
+        builder = builder.request_timeout(Some(Duration::from_millis(500)));
+        assert_eq!(
+            builder.config.request_timeout,
+            Some(Duration::from_millis(500))
+        );
+
+        builder = builder.request_timeout(None);
+        assert_eq!(builder.config.request_timeout, None);
+    }
+
+    #[test]
+    fn in_flight_requests() {
+        let mut builder = SessionBuilder::new();
+        assert_eq!(builder.config.submit_queue_size, 128);
+        assert_eq!(builder.config.max_in_flight_requests, 32768);
+
+        builder = builder.submit_queue_size(16).max_in_flight_requests(100);
+        assert_eq!(builder.config.submit_queue_size, 16);
+        assert_eq!(builder.config.max_in_flight_requests, 100);
+
+        builder = builder.max_in_flight_requests(100_000);
+        assert_eq!(builder.config.max_in_flight_requests, 32768);
+    }
+
     #[test]
     fn all_features() {
         let mut builder = SessionBuilder::new();
@@ -1,6 +1,9 @@
 use crate::frame::value::ValueList;
+use crate::query::Query;
 use crate::routing::hash3_x64_128;
+use crate::IntoTypedRows;
 use crate::SessionBuilder;
+use futures::StreamExt;
 
 // TODO: Requires a running local Scylla instance
 #[tokio::test]
@@ -40,6 +43,7 @@ async fn test_unprepared_statement() {
         .query("SELECT a, b, c FROM ks.t", &[])
         .await
         .unwrap()
+        .rows
         .unwrap();
 
     let mut results: Vec<(i32, i32, &String)> = rs
@@ -114,6 +118,7 @@ async fn test_prepared_statement() {
             .query("SELECT token(a) FROM ks.t2", &[])
             .await
             .unwrap()
+            .rows
             .unwrap();
         let token: i64 = rs.first().unwrap().columns[0]
             .as_ref()
@@ -133,6 +138,7 @@ async fn test_prepared_statement() {
             .query("SELECT token(a,b,c) FROM ks.complex_pk", &[])
             .await
             .unwrap()
+            .rows
             .unwrap();
         let token: i64 = rs.first().unwrap().columns[0]
             .as_ref()
@@ -154,6 +160,7 @@ async fn test_prepared_statement() {
             .query("SELECT a,b,c FROM ks.t2", &[])
             .await
             .unwrap()
+            .rows
             .unwrap();
         let r = rs.first().unwrap();
         let a = r.columns[0].as_ref().unwrap().as_int().unwrap();
@@ -166,6 +173,7 @@ async fn test_prepared_statement() {
             .query("SELECT a,b,c,d,e FROM ks.complex_pk", &[])
             .await
             .unwrap()
+            .rows
             .unwrap();
         let r = rs.first().unwrap();
         let a = r.columns[0].as_ref().unwrap().as_int().unwrap();
@@ -221,6 +229,7 @@ async fn test_batch() {
         .query("SELECT a, b, c FROM ks.t_batch", &[])
         .await
         .unwrap()
+        .rows
         .unwrap();
 
     let mut results: Vec<(i32, i32, &String)> = rs
@@ -280,6 +289,7 @@ async fn test_token_calculation() {
             .query("SELECT token(a) FROM ks.t3 WHERE a = ?", &values)
             .await
             .unwrap()
+            .rows
             .unwrap();
         let token: i64 = rs.first().unwrap().columns[0]
             .as_ref()
@@ -294,3 +304,981 @@ async fn test_token_calculation() {
         assert_eq!(token, expected_token)
     }
 }
+
+#[tokio::test]
+#[ignore]
+async fn test_tracing() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+
+    session.query("CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await.unwrap();
+    session
+        .query(
+            "CREATE TABLE IF NOT EXISTS ks.tab (a text primary key)",
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // A query without tracing enabled has no tracing id
+    let untraced = session.query("SELECT * FROM ks.tab", &[]).await.unwrap();
+    assert!(untraced.tracing_id.is_none());
+
+    let mut traced_query = Query::new("SELECT * FROM ks.tab".to_string());
+    traced_query.set_tracing(true);
+
+    let traced_result = session.query(traced_query, &[]).await.unwrap();
+    let tracing_id = traced_result.tracing_id.unwrap();
+
+    let tracing_info = session.get_tracing_info(&tracing_id).await.unwrap();
+    assert!(tracing_info.is_complete());
+    assert!(!tracing_info.events.is_empty());
+    assert!(!tracing_info.nodes().is_empty());
+
+    let mut prepared = session.prepare("SELECT * FROM ks.tab").await.unwrap();
+    prepared.set_tracing(true);
+    let traced_execute = session.execute(&prepared, &[]).await.unwrap();
+    assert!(traced_execute.tracing_id.is_some());
+
+    // Iterators collect a tracing id for every fetched page
+    for a in &["a", "b", "c"] {
+        session
+            .query("INSERT INTO ks.tab (a) VALUES (?)", (a,))
+            .await
+            .unwrap();
+    }
+    let mut traced_iter_query = Query::new("SELECT * FROM ks.tab".to_string());
+    traced_iter_query.set_tracing(true);
+    traced_iter_query.set_page_size(1);
+    let mut row_iterator = session.query_iter(traced_iter_query, &[]).await.unwrap();
+    while let Some(row) = row_iterator.next().await {
+        row.unwrap();
+    }
+    // There are 3 pages with a row each and the last, empty one
+    assert!(row_iterator.get_tracing_ids().len() >= 3);
+    for tracing_id in row_iterator.get_tracing_ids() {
+        assert!(session.get_tracing_info(tracing_id).await.is_ok());
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_warnings() {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let handled_warnings = Arc::new(AtomicUsize::new(0));
+    let handled_warnings_copy = handled_warnings.clone();
+    let session = SessionBuilder::new()
+        .known_node(uri)
+        .warning_handler(move |_addr: SocketAddr, warnings: &[String]| {
+            handled_warnings_copy.fetch_add(warnings.len(), Ordering::SeqCst);
+        })
+        .build()
+        .await
+        .unwrap();
+
+    session.query("CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await.unwrap();
+    session
+        .query(
+            "CREATE TABLE IF NOT EXISTS ks.t_warnings (a int primary key, b text)",
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // A batch bigger than batch_size_warn_threshold_in_kb makes the server attach a warning
+    use crate::batch::Batch;
+    let mut batch: Batch = Default::default();
+    batch.append_statement("INSERT INTO ks.t_warnings (a, b) VALUES (?, ?)");
+    let big_text: String = "a".repeat(128 * 1024);
+
+    let batch_result = session.batch(&batch, ((1_i32, &big_text),)).await.unwrap();
+
+    assert!(!batch_result.warnings.is_empty());
+    assert_eq!(
+        handled_warnings.load(Ordering::SeqCst),
+        batch_result.warnings.len()
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_timestamp() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new()
+        .known_node(uri)
+        .timestamp_generator(|| 1000)
+        .build()
+        .await
+        .unwrap();
+
+    session.query("CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await.unwrap();
+    session
+        .query(
+            "CREATE TABLE IF NOT EXISTS ks.t_timestamp (a int primary key, b int)",
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // Generated timestamp
+    session
+        .query("INSERT INTO ks.t_timestamp (a, b) VALUES (1, 1)", &[])
+        .await
+        .unwrap();
+
+    // Timestamp set on the query takes precedence over the generated one
+    let mut query = Query::new("INSERT INTO ks.t_timestamp (a, b) VALUES (2, 2)".to_string());
+    query.set_timestamp(Some(2000));
+    session.query(query, &[]).await.unwrap();
+
+    let mut writetimes: Vec<(i32, i64)> = session
+        .query("SELECT a, writetime(b) FROM ks.t_timestamp", &[])
+        .await
+        .unwrap()
+        .rows
+        .unwrap()
+        .into_typed::<(i32, i64)>()
+        .map(|r| r.unwrap())
+        .collect();
+    writetimes.sort_unstable();
+
+    assert_eq!(writetimes, vec![(1, 1000), (2, 2000)]);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_lwt() {
+    use crate::statement::Consistency;
+
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new().known_node(uri).build().await.unwrap();
+
+    session.query("CREATE KEYSPACE IF NOT EXISTS ks WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await.unwrap();
+    session
+        .query("DROP TABLE IF EXISTS ks.t_lwt", &[])
+        .await
+        .unwrap();
+    session
+        .query(
+            "CREATE TABLE IF NOT EXISTS ks.t_lwt (a int primary key, b int)",
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let mut prepared = session
+        .prepare("INSERT INTO ks.t_lwt (a, b) VALUES (?, ?) IF NOT EXISTS")
+        .await
+        .unwrap();
+    prepared.set_serial_consistency(Some(Consistency::LocalSerial));
+
+    let first = session
+        .execute(&prepared, (1_i32, 1_i32))
+        .await
+        .unwrap()
+        .into_lwt_result()
+        .unwrap();
+    assert!(first.applied);
+    assert!(first.existing_row.is_none());
+
+    let second = session
+        .execute(&prepared, (1_i32, 2_i32))
+        .await
+        .unwrap()
+        .into_lwt_result()
+        .unwrap();
+    assert!(!second.applied);
+    let (a, b) = second
+        .existing_row
+        .unwrap()
+        .into_typed::<(i32, i32)>()
+        .unwrap();
+    assert_eq!((a, b), (1, 1));
+
+    // Results of regular statements don't have the [applied] column
+    let select = session
+        .query("SELECT a, b FROM ks.t_lwt", &[])
+        .await
+        .unwrap();
+    assert!(select.into_lwt_result().is_none());
+
+    let mut batch = crate::batch::Batch::default();
+    batch.append_statement("INSERT INTO ks.t_lwt (a, b) VALUES (?, ?) IF NOT EXISTS");
+    batch.set_serial_consistency(Some(Consistency::LocalSerial));
+    let batch_result = session
+        .batch(&batch, ((1_i32, 3_i32),))
+        .await
+        .unwrap()
+        .into_lwt_result()
+        .unwrap();
+    assert!(!batch_result.applied);
+    let (a, b) = batch_result
+        .existing_row
+        .unwrap()
+        .into_typed::<(i32, i32)>()
+        .unwrap();
+    assert_eq!((a, b), (1, 1));
+}
+
+#[tokio::test]
+async fn test_lwt_batch() {
+    use crate::batch::Batch;
+    use crate::frame::response::result::{CQLValue, ColumnType};
+    use crate::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+
+    let server = FakeServer::start().await.unwrap();
+    server.add_rule(Rule::new(
+        StatementPattern::Contains("IF b = 5".to_string()),
+        FakeResponse::rows(
+            &[("[applied]", ColumnType::Boolean)],
+            vec![vec![Some(CQLValue::Boolean(true))]],
+        ),
+    ));
+    server.add_rule(Rule::new(
+        StatementPattern::Contains("IF b = 6".to_string()),
+        FakeResponse::rows(
+            &[
+                ("[applied]", ColumnType::Boolean),
+                ("a", ColumnType::Int),
+                ("b", ColumnType::Int),
+            ],
+            vec![vec![
+                Some(CQLValue::Boolean(false)),
+                Some(CQLValue::Int(1)),
+                Some(CQLValue::Int(5)),
+            ]],
+        ),
+    ));
+
+    let session = SessionBuilder::new()
+        .known_node_addr(server.address())
+        .build()
+        .await
+        .unwrap();
+
+    let mut batch = Batch::default();
+    batch.append_statement("INSERT INTO ks.t (a, b) VALUES (2, 2)");
+    let result = session.batch(&batch, ((),)).await.unwrap();
+    assert!(result.rows.is_none());
+    assert!(result.into_lwt_result().is_none());
+
+    let mut batch = Batch::default();
+    batch.append_statement("UPDATE ks.t SET b = 7 WHERE a = 1 IF b = 5");
+    let applied = session
+        .batch(&batch, ((),))
+        .await
+        .unwrap()
+        .into_lwt_result()
+        .unwrap();
+    assert!(applied.applied);
+    assert!(applied.existing_row.is_none());
+
+    let mut batch = Batch::default();
+    batch.append_statement("UPDATE ks.t SET b = 7 WHERE a = 1 IF b = 6");
+    let result = session.batch(&batch, ((),)).await.unwrap();
+    assert_eq!(result.rows.as_ref().unwrap().len(), 1);
+    let not_applied = result.into_lwt_result().unwrap();
+    assert!(!not_applied.applied);
+    let names: Vec<&str> = not_applied
+        .col_specs
+        .iter()
+        .map(|spec| spec.name())
+        .collect();
+    assert_eq!(names, vec!["a", "b"]);
+    let (a, b) = not_applied
+        .existing_row
+        .unwrap()
+        .into_typed::<(i32, i32)>()
+        .unwrap();
+    assert_eq!((a, b), (1, 5));
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_use_keyspace() {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let session = SessionBuilder::new()
+        .known_node(&uri)
+        .build()
+        .await
+        .unwrap();
+
+    session.query("CREATE KEYSPACE IF NOT EXISTS use_ks_test WITH REPLICATION = {'class' : 'SimpleStrategy', 'replication_factor' : 1}", &[]).await.unwrap();
+    session
+        .query(
+            "CREATE TABLE IF NOT EXISTS use_ks_test.tab (a text primary key)",
+            &[],
+        )
+        .await
+        .unwrap();
+    session
+        .query("INSERT INTO use_ks_test.tab (a) VALUES ('test1')", &[])
+        .await
+        .unwrap();
+
+    session.use_keyspace("use_ks_test", false).await.unwrap();
+
+    // Every query should work, no matter which connection it's sent to
+    for _ in 0..16 {
+        let rows = session
+            .query("SELECT * FROM tab", &[])
+            .await
+            .unwrap()
+            .rows
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    assert!(matches!(
+        session.use_keyspace("invalid; name", false).await,
+        Err(crate::transport::errors::QueryError::BadQuery(_))
+    ));
+
+    // Keyspace set in the builder
+    let session2 = SessionBuilder::new()
+        .known_node(uri)
+        .use_keyspace("use_ks_test", false)
+        .build()
+        .await
+        .unwrap();
+    let rows = session2
+        .query("SELECT * FROM tab", &[])
+        .await
+        .unwrap()
+        .rows
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+}
+
+#[tokio::test]
+async fn test_fake_server() {
+    use crate::frame::response::result::{CQLValue, ColumnType};
+    use crate::frame::response::{Error, Response};
+    use crate::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+    use crate::transport::connection::open_connection;
+    use crate::transport::errors::{DBError, QueryError};
+    use crate::transport::session::SessionConfig;
+    use std::time::{Duration, Instant};
+
+    let server = FakeServer::start().await.unwrap();
+    server.add_rule(Rule::new(
+        StatementPattern::Prefix("SELECT a, b FROM ks.t".to_string()),
+        FakeResponse::rows(
+            &[("a", ColumnType::Int), ("b", ColumnType::Text)],
+            vec![
+                vec![
+                    Some(CQLValue::Int(1)),
+                    Some(CQLValue::Text("x".to_string())),
+                ],
+                vec![Some(CQLValue::Int(2)), None],
+            ],
+        ),
+    ));
+    server.add_rule(Rule::new(
+        StatementPattern::Contains("ks.overloaded".to_string()),
+        FakeResponse::error(0x1001, "Overloaded"),
+    ));
+    server.add_rule(
+        Rule::new(
+            StatementPattern::Exact("INSERT INTO ks.slow (a) VALUES (?)".to_string()),
+            FakeResponse::Void,
+        )
+        .with_delay(Duration::from_millis(100)),
+    );
+
+    let session = SessionBuilder::new()
+        .known_node_addr(server.address())
+        .build()
+        .await
+        .unwrap();
+
+    let rows = session
+        .query("SELECT a, b FROM ks.t", &[])
+        .await
+        .unwrap()
+        .rows
+        .unwrap()
+        .into_typed::<(i32, Option<String>)>()
+        .map(|row| row.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(rows, vec![(1, Some("x".to_string())), (2, None)]);
+
+    // Prepared statements are resolved to their text and matched against the same rules
+    let prepared = session
+        .prepare("SELECT a, b FROM ks.t WHERE a = ?")
+        .await
+        .unwrap();
+    let rows = session
+        .execute(&prepared, (1,))
+        .await
+        .unwrap()
+        .rows
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+
+    match session.query("SELECT * FROM ks.overloaded", &[]).await {
+        Err(QueryError::DBError(DBError::ErrorMsg(code, message))) => {
+            assert_eq!(code, 0x1001);
+            assert_eq!(message, "Overloaded");
+        }
+        other => panic!("Expected an Overloaded error, got {:?}", other),
+    }
+
+    let start = Instant::now();
+    session
+        .query("INSERT INTO ks.slow (a) VALUES (?)", (1,))
+        .await
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    assert!(server
+        .received_statements()
+        .contains(&"SELECT * FROM ks.overloaded".to_string()));
+
+    // A statement prepared on another server is unknown, its id is sent back
+    let other_server = FakeServer::start().await.unwrap();
+    let config = SessionConfig::new().get_connection_config();
+    let (connection, _) = open_connection(other_server.address(), None, config)
+        .await
+        .unwrap();
+    match connection
+        .execute(&prepared, (1,), None)
+        .await
+        .unwrap()
+        .response
+    {
+        Response::Error(Error {
+            error: DBError::Unprepared { statement_id },
+            ..
+        }) => assert_eq!(&statement_id, prepared.get_id()),
+        other => panic!("Expected an Unprepared error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_tracing_ids_of_pages() {
+    use crate::frame::response::result::{CQLValue, ColumnType};
+    use crate::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+
+    let server = FakeServer::start().await.unwrap();
+    server.add_rule(Rule::new(
+        StatementPattern::Exact("SELECT a FROM ks.t".to_string()),
+        FakeResponse::rows(
+            &[("a", ColumnType::Int)],
+            vec![vec![Some(CQLValue::Int(1))]],
+        ),
+    ));
+
+    let session = SessionBuilder::new()
+        .known_node_addr(server.address())
+        .build()
+        .await
+        .unwrap();
+
+    let mut row_iterator = session.query_iter("SELECT a FROM ks.t", &[]).await.unwrap();
+    while let Some(row) = row_iterator.next().await {
+        row.unwrap();
+    }
+    assert!(row_iterator.get_tracing_ids().is_empty());
+
+    let mut query = Query::new("SELECT a FROM ks.t".to_string());
+    query.set_tracing(true);
+    let mut row_iterator = session.query_iter(query, &[]).await.unwrap();
+    assert!(row_iterator.get_tracing_ids().is_empty());
+    while let Some(row) = row_iterator.next().await {
+        row.unwrap();
+    }
+    // The server sends all rows in one page
+    assert_eq!(row_iterator.get_tracing_ids().len(), 1);
+
+    let mut prepared = session.prepare("SELECT a FROM ks.t").await.unwrap();
+    prepared.set_tracing(true);
+    let mut typed_iterator = session
+        .execute_iter(prepared, &[])
+        .await
+        .unwrap()
+        .into_typed::<(i32,)>();
+    while let Some(row) = typed_iterator.next().await {
+        row.unwrap();
+    }
+    assert_eq!(typed_iterator.get_tracing_ids().len(), 1);
+}
+
+#[tokio::test]
+async fn test_protocol_v5() {
+    use crate::batch::Batch;
+    use crate::frame::response::result::{CQLValue, ColumnType};
+    use crate::frame::ProtocolVersion;
+    use crate::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+    use crate::transport::Compression;
+
+    let server = FakeServer::start().await.unwrap();
+    server.set_max_protocol_version(ProtocolVersion::V5);
+
+    // The response doesn't fit in a single segment
+    let text = "abcdefghij".repeat(10);
+    let big_rows = (0..3000)
+        .map(|i| vec![Some(CQLValue::Int(i)), Some(CQLValue::Text(text.clone()))])
+        .collect();
+    server.add_rule(Rule::new(
+        StatementPattern::Exact("SELECT a, b FROM ks.big".to_string()),
+        FakeResponse::rows(&[("a", ColumnType::Int), ("b", ColumnType::Text)], big_rows),
+    ));
+    server.add_rule(Rule::new(
+        StatementPattern::Prefix("SELECT a FROM ks.t".to_string()),
+        FakeResponse::rows(
+            &[("a", ColumnType::Int)],
+            vec![vec![Some(CQLValue::Int(7))]],
+        ),
+    ));
+
+    for compression in [None, Some(Compression::LZ4)] {
+        let session = SessionBuilder::new()
+            .known_node_addr(server.address())
+            .compression(compression)
+            .build()
+            .await
+            .unwrap();
+
+        let rows = session
+            .query("SELECT a FROM ks.t", &[])
+            .await
+            .unwrap()
+            .rows
+            .unwrap()
+            .into_typed::<(i32,)>()
+            .map(|row| row.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![(7,)]);
+
+        let prepared = session
+            .prepare("SELECT a FROM ks.t WHERE a = ?")
+            .await
+            .unwrap();
+        let rows = session
+            .execute(&prepared, (7,))
+            .await
+            .unwrap()
+            .rows
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let mut batch = Batch::default();
+        batch.append_statement("INSERT INTO ks.t (a) VALUES (?)");
+        batch.append_statement(prepared);
+        session.batch(&batch, ((1,), (2,))).await.unwrap();
+
+        let mut query = Query::new("SELECT a, b FROM ks.big".to_string());
+        query.set_tracing(true);
+        let result = session.query(query, &[]).await.unwrap();
+        assert!(result.tracing_id.is_some());
+        let rows = result
+            .rows
+            .unwrap()
+            .into_typed::<(i32, String)>()
+            .map(|row| row.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3000);
+        assert!(rows
+            .iter()
+            .enumerate()
+            .all(|(i, row)| row.0 == i as i32 && row.1 == text));
+    }
+
+    let versions = server.startup_protocol_versions();
+    assert!(!versions.is_empty());
+    assert!(versions
+        .iter()
+        .all(|version| *version == ProtocolVersion::V5));
+}
+
+#[tokio::test]
+async fn test_fault_injection_proxy() {
+    use crate::test_utils::{
+        FakeServer, FaultAction, InjectedError, Proxy, ProxyRule, RequestCondition,
+        StatementPattern,
+    };
+    use crate::transport::errors::{DBError, QueryError};
+    use std::time::{Duration, Instant};
+
+    let server = FakeServer::start().await.unwrap();
+    let proxy = Proxy::start(server.address()).await.unwrap();
+
+    let session = SessionBuilder::new()
+        .known_node_addr(proxy.address())
+        .build()
+        .await
+        .unwrap();
+    let prepared = session.prepare("SELECT * FROM ks.t").await.unwrap();
+
+    // Executions of prepared statements are matched by their text
+    proxy.add_rule(
+        ProxyRule::new(
+            RequestCondition::Statement(StatementPattern::Exact("SELECT * FROM ks.t".to_string())),
+            FaultAction::RespondWithError(InjectedError::Overloaded),
+        )
+        .times(1),
+    );
+    match session.execute(&prepared, &[]).await {
+        Err(QueryError::DBError(DBError::ErrorMsg(code, _))) => assert_eq!(code, 0x1001),
+        other => panic!("Expected an Overloaded error, got {:?}", other),
+    }
+    // The rule was triggered once, the statement reaches the server now
+    session.execute(&prepared, &[]).await.unwrap();
+
+    proxy.add_rule(
+        ProxyRule::new(
+            RequestCondition::Statement(StatementPattern::Prefix("SELECT".to_string())),
+            FaultAction::DelayResponse(Duration::from_millis(100)),
+        )
+        .times(1),
+    );
+    let start = Instant::now();
+    session.query("SELECT a FROM ks.t", &[]).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    proxy.add_rule(ProxyRule::new(
+        RequestCondition::Statement(StatementPattern::Contains("ks.dropped".to_string())),
+        FaultAction::DropResponse,
+    ));
+    let dropped = session.query("SELECT a FROM ks.dropped", &[]);
+    assert!(tokio::time::timeout(Duration::from_millis(100), dropped)
+        .await
+        .is_err());
+    proxy.clear_rules();
+    session
+        .query("SELECT a FROM ks.dropped", &[])
+        .await
+        .unwrap();
+
+    proxy.add_rule(ProxyRule::new(
+        RequestCondition::Opcode(crate::frame::request::RequestOpcode::Query),
+        FaultAction::DropConnection,
+    ));
+    assert!(session.query("SELECT a FROM ks.t", &[]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_reconnect_after_connection_broke() {
+    use crate::test_utils::{
+        FakeServer, FaultAction, Proxy, ProxyRule, RequestCondition, StatementPattern,
+    };
+    use crate::transport::reconnection_policy::ConstantReconnectionPolicy;
+    use std::time::Duration;
+
+    let server = FakeServer::start().await.unwrap();
+    let proxy = Proxy::start(server.address()).await.unwrap();
+
+    let session = SessionBuilder::new()
+        .known_node_addr(proxy.address())
+        .reconnection_policy(ConstantReconnectionPolicy::new(Duration::from_millis(10)))
+        .build()
+        .await
+        .unwrap();
+    session.query("SELECT a FROM ks.t", &[]).await.unwrap();
+
+    proxy.add_rule(
+        ProxyRule::new(
+            RequestCondition::Statement(StatementPattern::Contains("ks.t".to_string())),
+            FaultAction::DropConnection,
+        )
+        .times(1),
+    );
+    assert!(session.query("SELECT a FROM ks.t", &[]).await.is_err());
+
+    // The broken connection is noticed and reopened in the background
+    let reconnected = async {
+        while session.query("SELECT a FROM ks.t", &[]).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), reconnected)
+        .await
+        .expect("Session didn't reconnect");
+}
+
+#[tokio::test]
+async fn test_request_timeout() {
+    use crate::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+    use crate::transport::errors::QueryError;
+    use std::time::Duration;
+
+    let server = FakeServer::start().await.unwrap();
+    server.add_rule(
+        Rule::new(
+            StatementPattern::Contains("ks.slow".to_string()),
+            FakeResponse::Void,
+        )
+        .with_delay(Duration::from_millis(300)),
+    );
+
+    let session = SessionBuilder::new()
+        .known_node_addr(server.address())
+        .request_timeout(Some(Duration::from_millis(100)))
+        .build()
+        .await
+        .unwrap();
+
+    match session.query("SELECT * FROM ks.slow", &[]).await {
+        Err(QueryError::RequestTimeout(timeout)) => {
+            assert_eq!(timeout, Duration::from_millis(100))
+        }
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+
+    // The connection works after a timeout, and the late response is handled
+    session.query("SELECT * FROM ks.fast", &[]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    session.query("SELECT * FROM ks.fast", &[]).await.unwrap();
+
+    // Timeout set on a statement overrides the session's one
+    let mut query = Query::new("SELECT * FROM ks.slow".to_string());
+    query.set_request_timeout(Some(Duration::from_secs(5)));
+    session.query(query.clone(), &[]).await.unwrap();
+
+    query.set_request_timeout(Some(Duration::from_millis(50)));
+    assert!(matches!(
+        session.query(query, &[]).await,
+        Err(QueryError::RequestTimeout(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_too_many_in_flight_requests() {
+    use crate::test_utils::{FakeResponse, FakeServer, Rule, StatementPattern};
+    use crate::transport::errors::QueryError;
+    use std::time::Duration;
+
+    let server = FakeServer::start().await.unwrap();
+    server.add_rule(
+        Rule::new(
+            StatementPattern::Contains("ks.slow".to_string()),
+            FakeResponse::Void,
+        )
+        .with_delay(Duration::from_millis(200)),
+    );
+
+    // Topology discovery sends 3 requests at once, so this is the lowest usable limit
+    let session = SessionBuilder::new()
+        .known_node_addr(server.address())
+        .max_in_flight_requests(3)
+        .build()
+        .await
+        .unwrap();
+
+    let queries = (0..4).map(|_| session.query("SELECT * FROM ks.slow", &[]));
+    let results = futures::future::join_all(queries).await;
+
+    let failed: Vec<_> = results.iter().filter(|result| result.is_err()).collect();
+    assert_eq!(failed.len(), 1);
+    assert!(matches!(
+        failed[0],
+        Err(QueryError::TooManyInFlightRequests(3))
+    ));
+
+    // Stream ids were released along with the responses
+    session.query("SELECT * FROM ks.slow", &[]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_heartbeat_timeout() {
+    use crate::frame::request::RequestOpcode;
+    use crate::test_utils::{FakeServer, FaultAction, Proxy, ProxyRule, RequestCondition};
+    use crate::transport::reconnection_policy::ConstantReconnectionPolicy;
+    use std::time::Duration;
+
+    let server = FakeServer::start().await.unwrap();
+    let proxy = Proxy::start(server.address()).await.unwrap();
+
+    let session = SessionBuilder::new()
+        .known_node_addr(proxy.address())
+        .heartbeat_interval(Some(Duration::from_millis(50)))
+        .heartbeat_timeout(Duration::from_millis(50))
+        .request_timeout(Some(Duration::from_millis(200)))
+        .reconnection_policy(ConstantReconnectionPolicy::new(Duration::from_millis(10)))
+        .build()
+        .await
+        .unwrap();
+    session.query("SELECT a FROM ks.t", &[]).await.unwrap();
+
+    // Heartbeats are sent on idle connections, the connection is broken
+    // when their responses don't arrive. Other requests still work.
+    proxy.add_rule(ProxyRule::new(
+        RequestCondition::Opcode(RequestOpcode::Options),
+        FaultAction::DropResponse,
+    ));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(session.query("SELECT a FROM ks.t", &[]).await.is_err());
+
+    proxy.clear_rules();
+    let reconnected = async {
+        while session.query("SELECT a FROM ks.t", &[]).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), reconnected)
+        .await
+        .expect("Session didn't reconnect");
+}
+
+#[tokio::test]
+async fn test_connection_pool() {
+    use crate::test_utils::{
+        FakeServer, FaultAction, Proxy, ProxyRule, RequestCondition, StatementPattern,
+    };
+    use crate::transport::connection_pool::PoolSize;
+    use crate::transport::reconnection_policy::ConstantReconnectionPolicy;
+    use std::num::NonZeroUsize;
+    use std::time::Duration;
+
+    let server = FakeServer::start().await.unwrap();
+    let proxy = Proxy::start(server.address()).await.unwrap();
+
+    let session = SessionBuilder::new()
+        .known_node_addr(proxy.address())
+        .pool_size(PoolSize::PerHost(NonZeroUsize::new(3).unwrap()))
+        .reconnection_policy(ConstantReconnectionPolicy::new(Duration::from_millis(10)))
+        .build()
+        .await
+        .unwrap();
+
+    // Prepare waits for all connections to open, so that they don't
+    // send USE on startup. Then use_keyspace sends it on every connection of the pool.
+    session.prepare("SELECT a FROM ks.t").await.unwrap();
+    let use_count = || {
+        server
+            .received_statements()
+            .iter()
+            .filter(|statement| statement.starts_with("USE"))
+            .count()
+    };
+    session.use_keyspace("ks", false).await.unwrap();
+    assert_eq!(use_count(), 3);
+
+    let queries = (0..10).map(|_| session.query("SELECT a FROM ks.t", &[]));
+    for result in futures::future::join_all(queries).await {
+        result.unwrap();
+    }
+
+    // A broken connection is replaced with a new one, which sends USE on startup
+    proxy.add_rule(
+        ProxyRule::new(
+            RequestCondition::Statement(StatementPattern::Exact("SELECT a FROM ks.broken".into())),
+            FaultAction::DropConnection,
+        )
+        .times(1),
+    );
+    assert!(session.query("SELECT a FROM ks.broken", &[]).await.is_err());
+
+    let refilled = async {
+        while use_count() < 4 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), refilled)
+        .await
+        .expect("Pool wasn't refilled");
+    session.query("SELECT a FROM ks.t", &[]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_use_unknown_keyspace() {
+    use crate::test_utils::{
+        FakeResponse, FakeServer, FaultAction, Proxy, ProxyRule, RequestCondition, Rule,
+        StatementPattern,
+    };
+    use crate::transport::errors::QueryError;
+    use crate::transport::reconnection_policy::ConstantReconnectionPolicy;
+    use std::time::Duration;
+
+    let server = FakeServer::start().await.unwrap();
+    server.add_rule(Rule::new(
+        StatementPattern::Exact("USE \"unknown\"".to_string()),
+        FakeResponse::error(0x2200, "Keyspace 'unknown' does not exist"),
+    ));
+    let proxy = Proxy::start(server.address()).await.unwrap();
+
+    let session = SessionBuilder::new()
+        .known_node_addr(proxy.address())
+        .reconnection_policy(ConstantReconnectionPolicy::new(Duration::from_millis(10)))
+        .build()
+        .await
+        .unwrap();
+
+    session.use_keyspace("ks", false).await.unwrap();
+    assert!(matches!(
+        session.use_keyspace("unknown", false).await,
+        Err(QueryError::DBError(_))
+    ));
+
+    // The connection is reopened with the previous keyspace
+    proxy.add_rule(
+        ProxyRule::new(
+            RequestCondition::Statement(StatementPattern::Exact("SELECT a FROM ks.broken".into())),
+            FaultAction::DropConnection,
+        )
+        .times(1),
+    );
+    assert!(session.query("SELECT a FROM ks.broken", &[]).await.is_err());
+
+    let use_count = |keyspace: &str| {
+        let statement = format!("USE \"{}\"", keyspace);
+        server
+            .received_statements()
+            .iter()
+            .filter(|received| **received == statement)
+            .count()
+    };
+    let reconnected = async {
+        while session.query("SELECT a FROM t", &[]).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), reconnected)
+        .await
+        .expect("Session didn't reconnect");
+    assert!(use_count("ks") >= 2);
+    assert_eq!(use_count("unknown"), 1);
+
+    // USE sent with execute() changes the keyspace of all connections too
+    let prepared = session.prepare("USE other_ks").await.unwrap();
+    session.execute(&prepared, &[]).await.unwrap();
+    assert_eq!(use_count("other_ks"), 1);
+}
+
+#[tokio::test]
+async fn test_custom_connector() {
+    use crate::test_utils::FakeServer;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    // Nothing listens on this address, connections go through in-memory streams
+    let server = FakeServer::start_sharded(2).await.unwrap();
+    let node_address: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+    let session = SessionBuilder::new()
+        .known_node_addr(node_address)
+        .connector(server.in_memory_connector())
+        .build()
+        .await
+        .unwrap();
+
+    session.query("SELECT a FROM ks.t", &[]).await.unwrap();
+    assert!(server
+        .received_statements()
+        .contains(&"SELECT a FROM ks.t".to_string()));
+
+    // Source ports can't be chosen, so shards are filled through the regular port
+    let shards_filled = async {
+        while server.connections_per_shard().contains(&0) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), shards_filled)
+        .await
+        .expect("Not all shards were connected to");
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Generates client-side timestamps attached to requests as their default timestamp.\
+/// Timestamps are microseconds since unix epoch.\
+/// With client-side timestamps the order of writes doesn't depend on clocks of coordinator nodes.
+pub trait TimestampGenerator: Send + Sync {
+    /// Returns the timestamp for the next request
+    fn next_timestamp(&self) -> i64;
+}
+
+/// Default [TimestampGenerator] - uses the system clock, but never returns the same
+/// or a smaller timestamp twice, even if the clock goes backwards
+/// or many timestamps are generated in the same microsecond.
+#[derive(Debug, Default)]
+pub struct MonotonicTimestampGenerator {
+    last: AtomicI64,
+}
+
+impl MonotonicTimestampGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TimestampGenerator for MonotonicTimestampGenerator {
+    fn next_timestamp(&self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_micros() as i64)
+            .unwrap_or(0);
+
+        let previous = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(std::cmp::max(now, last + 1))
+            })
+            .unwrap(); // The closure always returns Some
+
+        std::cmp::max(now, previous + 1)
+    }
+}
+
+impl<F: Fn() -> i64 + Send + Sync> TimestampGenerator for F {
+    fn next_timestamp(&self) -> i64 {
+        self()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MonotonicTimestampGenerator, TimestampGenerator};
+
+    #[test]
+    fn monotonic_timestamps() {
+        let generator = MonotonicTimestampGenerator::new();
+
+        let mut previous = generator.next_timestamp();
+        for _ in 0..1000 {
+            let next = generator.next_timestamp();
+            assert!(next > previous);
+            previous = next;
+        }
+    }
+
+    #[test]
+    fn monotonic_when_clock_is_behind() {
+        let generator = MonotonicTimestampGenerator::new();
+        let future = generator.next_timestamp() + 1_000_000_000;
+        generator
+            .last
+            .store(future, std::sync::atomic::Ordering::SeqCst);
+
+        assert_eq!(generator.next_timestamp(), future + 1);
+        assert_eq!(generator.next_timestamp(), future + 2);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::frame::response::result::Row;
+use crate::statement::Consistency;
+use crate::transport::errors::QueryError;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Tracing info retrieved from `system_traces.sessions`
+/// with all events from `system_traces.events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracingInfo {
+    pub client: Option<IpAddr>,
+    pub command: Option<String>,
+    pub coordinator: Option<IpAddr>,
+    /// Duration of the traced request in microseconds
+    pub duration: Option<i32>,
+    pub parameters: Option<HashMap<String, String>>,
+    pub request: Option<String>,
+    /// Milliseconds since unix epoch
+    pub started_at: Option<i64>,
+
+    pub events: Vec<TracingEvent>,
+}
+
+/// A single event happening during a traced query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracingEvent {
+    pub event_id: Uuid,
+    pub activity: Option<String>,
+    pub source: Option<IpAddr>,
+    /// Microseconds elapsed on the source node since the request started
+    pub source_elapsed: Option<i32>,
+    pub thread: Option<String>,
+}
+
+impl TracingInfo {
+    /// Returns true if the tracing session is complete - the coordinator has recorded
+    /// its duration. Events might still be arriving from replicas after that
+    pub fn is_complete(&self) -> bool {
+        self.duration.is_some()
+    }
+
+    /// Returns the list of nodes which recorded at least one event
+    pub fn nodes(&self) -> Vec<IpAddr> {
+        let mut nodes: Vec<IpAddr> = self.events.iter().filter_map(|e| e.source).collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes
+    }
+
+    /// Returns events recorded on the given node, in the order they happened
+    pub fn events_on_node(&self, node: IpAddr) -> impl Iterator<Item = &TracingEvent> {
+        self.events
+            .iter()
+            .filter(move |event| event.source == Some(node))
+    }
+}
+
+/// Used to configure a custom retry strategy when querying tracing info
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetTracingConfig {
+    /// Number of attempts to be made before giving up.
+    /// Default value: 5
+    pub attempts: NonZeroU32,
+    /// Interval to wait between each attempt.
+    /// Default value: 3 milliseconds
+    pub interval: Duration,
+    /// Consistency to use in queries that read TracingInfo.
+    /// Default value: One
+    pub consistency: Consistency,
+}
+
+impl Default for GetTracingConfig {
+    fn default() -> Self {
+        GetTracingConfig {
+            attempts: NonZeroU32::new(5).unwrap(),
+            interval: Duration::from_millis(3),
+            consistency: Consistency::One,
+        }
+    }
+}
+
+// A query used to query TracingInfo from system_traces.sessions
+pub(crate) const TRACES_SESSION_QUERY_STR: &str =
+    "SELECT client, command, coordinator, duration, parameters, request, started_at \
+    FROM system_traces.sessions WHERE session_id = ?";
+
+// A query used to query TracingEvent from system_traces.events
+pub(crate) const TRACES_EVENTS_QUERY_STR: &str =
+    "SELECT event_id, activity, source, source_elapsed, thread \
+    FROM system_traces.events WHERE session_id = ?";
+
+pub(crate) fn tracing_info_from_row(row: Row) -> Result<TracingInfo, QueryError> {
+    let bad_type = || QueryError::ProtocolError("system_traces.sessions has invalid column type");
+
+    let mut columns = row.columns;
+    if columns.len() != 7 {
+        return Err(bad_type());
+    }
+
+    // started_at is a timestamp, which doesn't have a FromCQLVal implementation
+    let started_at = match columns.pop().flatten() {
+        Some(value) => Some(value.as_timestamp().ok_or_else(bad_type)?),
+        None => None,
+    };
+
+    let (client, command, coordinator, duration, parameters, request) = Row { columns }
+        .into_typed::<(
+            Option<IpAddr>,
+            Option<String>,
+            Option<IpAddr>,
+            Option<i32>,
+            Option<HashMap<String, String>>,
+            Option<String>,
+        )>()
+        .map_err(|_| bad_type())?;
+
+    Ok(TracingInfo {
+        client,
+        command,
+        coordinator,
+        duration,
+        parameters,
+        request,
+        started_at,
+        events: Vec::new(),
+    })
+}
+
+pub(crate) fn tracing_event_from_row(row: Row) -> Result<TracingEvent, QueryError> {
+    let (event_id, activity, source, source_elapsed, thread) = row
+        .into_typed::<(
+            Uuid,
+            Option<String>,
+            Option<IpAddr>,
+            Option<i32>,
+            Option<String>,
+        )>()
+        .map_err(|_| QueryError::ProtocolError("system_traces.events has invalid column type"))?;
+
+    Ok(TracingEvent {
+        event_id,
+        activity,
+        source,
+        source_elapsed,
+        thread,
+    })
+}